@@ -0,0 +1,114 @@
+//! # Redraw Benchmark
+//!
+//! Replays a redraw recording (see [`Recording`]) through a fresh [`Editor`] and
+//! reports how long each frame took, from its first event to the end of its flush.
+//!
+//! Run with `cargo bench`. The recordings in `benches/recordings` are replayed by
+//! default, other ones can be given with `cargo bench -- <recording> [iterations]`.
+//! Each frame is read back like the UI would do, so the time spent publishing it is
+//! also measured.
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use weovim::editor::{Editor, UiStateFromEditor};
+use weovim::neovim::{EventListener, Recording, RedrawEvent};
+
+/// Number of times the recording is replayed if not specified.
+const DEFAULT_ITERATIONS: usize = 10;
+
+/// Recordings replayed when none is given.
+const DEFAULT_RECORDINGS: &[&str] = &["recordings/paste.redraw"];
+
+fn main() -> std::io::Result<()> {
+    // Cargo passes `--bench`, and the filter of `cargo bench <filter>`.
+    let mut args = std::env::args().skip(1).filter(|arg| !arg.starts_with("--"));
+
+    let recordings = match args.next() {
+        Some(path) => vec![PathBuf::from(path)],
+        None => DEFAULT_RECORDINGS
+            .iter()
+            .map(|path| Path::new(env!("CARGO_MANIFEST_DIR")).join("benches").join(path))
+            .collect(),
+    };
+    let iterations = args
+        .next()
+        .and_then(|iterations| iterations.parse().ok())
+        .unwrap_or(DEFAULT_ITERATIONS);
+
+    for path in recordings {
+        println!("{}", path.display());
+        run(&path, iterations)?;
+    }
+
+    Ok(())
+}
+
+/// Replay the recording at `path` `iterations` times and print the frame timings.
+fn run(path: &Path, iterations: usize) -> std::io::Result<()> {
+    let recording = Recording::load(path)?;
+    println!("Loaded {} redraw notifications", recording.len());
+
+    let mut frames = Vec::new();
+    let total = Instant::now();
+
+    for _ in 0..iterations {
+        let (editor, ui_state) = Editor::new();
+        let mut listener = TimedEditor {
+            editor,
+            ui_state,
+            frame_start: None,
+            frames: Vec::new(),
+        };
+
+        recording.replay(&mut listener)?;
+        frames.append(&mut listener.frames);
+    }
+
+    let total = total.elapsed();
+    report(&mut frames, total);
+
+    Ok(())
+}
+
+fn report(frames: &mut [Duration], total: Duration) {
+    if frames.is_empty() {
+        println!("No frames were flushed");
+        return;
+    }
+
+    frames.sort_unstable();
+    let percentile = |p: usize| frames[(frames.len() - 1) * p / 100];
+    let sum: Duration = frames.iter().sum();
+
+    println!("frames: {}", frames.len());
+    println!("total:  {:?}", total);
+    println!("mean:   {:?}", sum / frames.len() as u32);
+    println!("p50:    {:?}", percentile(50));
+    println!("p90:    {:?}", percentile(90));
+    println!("p99:    {:?}", percentile(99));
+    println!("max:    {:?}", frames[frames.len() - 1]);
+}
+
+/// Forwards events to the editor, measuring the time taken by each frame.
+struct TimedEditor {
+    editor: Editor,
+    ui_state: UiStateFromEditor,
+    frame_start: Option<Instant>,
+    frames: Vec<Duration>,
+}
+
+impl EventListener for TimedEditor {
+    fn on_redraw_event(&mut self, event: RedrawEvent<'_>) {
+        let start = *self.frame_start.get_or_insert_with(Instant::now);
+        let is_flush = matches!(event, RedrawEvent::Flush);
+
+        self.editor.on_redraw_event(event);
+
+        if is_flush {
+            let _ = self.ui_state.reader.buffer();
+            while self.ui_state.recv.try_recv().is_ok() {}
+
+            self.frames.push(start.elapsed());
+            self.frame_start = None;
+        }
+    }
+}
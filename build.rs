@@ -4,7 +4,10 @@ use std::convert::TryFrom;
 
 const SHADERS: &[&str] = &[
     "./shaders/quad.vert",
-    "./shaders/quad.frag"
+    "./shaders/quad.frag",
+    "./shaders/glyph.vert",
+    "./shaders/glyph.frag",
+    "./shaders/color_glyph.frag"
 ];
 
 struct ShaderData {
@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in vec3 v_Uv;
+layout(location = 1) in vec4 v_Color;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2DArray t_Atlas;
+layout(set = 0, binding = 1) uniform sampler s_Atlas;
+
+void main() {
+    float coverage = texture(sampler2DArray(t_Atlas, s_Atlas), v_Uv).r;
+
+    f_color = vec4(v_Color.rgb, v_Color.a * coverage);
+}
@@ -0,0 +1,26 @@
+#version 450
+
+layout(location = 0) in vec2 i_Pos;
+layout(location = 1) in vec2 i_Size;
+layout(location = 2) in vec2 i_UvPos;
+layout(location = 3) in vec2 i_UvSize;
+layout(location = 4) in float i_Layer;
+layout(location = 5) in vec4 i_Color;
+
+layout(location = 0) out vec3 v_Uv;
+layout(location = 1) out vec4 v_Color;
+
+const vec2 corners[4] = vec2[4](
+    vec2(0.0, 0.0),
+    vec2(0.0, 1.0),
+    vec2(1.0, 1.0),
+    vec2(1.0, 0.0)
+);
+
+void main() {
+    vec2 corner = corners[gl_VertexIndex];
+
+    gl_Position = vec4(i_Pos + i_Size * corner, 0.0, 1.0);
+    v_Uv = vec3(i_UvPos + i_UvSize * corner, i_Layer);
+    v_Color = i_Color;
+}
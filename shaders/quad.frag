@@ -1,7 +1,9 @@
 #version 450
 
+layout(location = 0) in vec4 v_Color;
+
 layout(location = 0) out vec4 f_color;
 
 void main() {
-    f_color = vec4(0.3, 0.2, 0.1, 1.0);
+    f_color = v_Color;
 }
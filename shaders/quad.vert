@@ -5,6 +5,8 @@ layout(location = 1) in float i_Width;
 layout(location = 2) in float i_Height;
 layout(location = 3) in vec4 i_Color;
 
+layout(location = 0) out vec4 v_Color;
+
 const vec2 vertices[4] = vec2[4](
     vec2(-0.5, -0.5),
     vec2(-0.5,  0.5),
@@ -21,4 +23,5 @@ void main() {
     );
 
     gl_Position = p_Transform * vec4(vertices[gl_VertexIndex], 0.0, 1.0);
+    v_Color = i_Color;
 }
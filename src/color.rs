@@ -119,6 +119,27 @@ impl Color {
     }
 }
 
+impl Color {
+    /// Converts the color to linear RGB, the color space that shaders write to sRGB
+    /// surfaces in.
+    pub fn to_linear(self) -> Self {
+        let linear = |c: f32| {
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+
+        Self {
+            r: linear(self.r),
+            g: linear(self.g),
+            b: linear(self.b),
+            a: self.a,
+        }
+    }
+}
+
 impl Color {
     pub const fn vertex_attribute_descriptor(
         loc: wgpu::ShaderLocation,
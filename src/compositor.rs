@@ -0,0 +1,480 @@
+//! # Window Compositor
+//!
+//! With `ext_multigrid`, every Neovim window is drawn in its own grid and the UI is
+//! responsible for placing the grids on the screen. This module keeps track of the
+//! placement events (`win_pos`, `win_float_pos`, `msg_set_pos`, ...) and resolves
+//! them into absolute cell positions of the default grid.
+//!
+//! The compositor is pure CPU state: it only needs the sequence of redraw events to
+//! produce the list of [`Layer`]s to draw.
+use crate::grid::Grids;
+use crate::neovim::{MsgSetPos, WinFloatAnchor, WinFloatPos, WinPos};
+use fnv::FnvHashMap;
+
+/// Maximum depth of floating windows anchored to other floating windows.
+///
+/// Protects against anchor cycles, that would otherwise make us loop forever.
+const MAX_ANCHOR_DEPTH: usize = 16;
+
+/// The `zindex` Neovim gives to the message grid.
+///
+/// Floating windows with a higher `zindex` are drawn on top of the messages.
+const MESSAGE_ZINDEX: u64 = 200;
+
+/// Stacking order of the different kinds of layers.
+///
+/// Windows are drawn on top of the default grid, then floats and messages are
+/// stacked by `zindex` on top of the windows.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+pub enum LayerKind {
+    /// The default grid, covering the entire screen.
+    Default,
+    /// A normal window, positioned by `win_pos`.
+    Window,
+    /// A floating window, positioned by `win_float_pos`.
+    Float,
+    /// The message grid, positioned by `msg_set_pos`.
+    Message,
+}
+
+/// A grid placed on the screen.
+#[derive(Debug, Copy, Clone)]
+pub struct Layer {
+    /// The grid to be drawn.
+    pub grid: u64,
+    /// What kind of window the grid is shown in.
+    pub kind: LayerKind,
+    /// The absolute row, in the default grid, of the top of the layer.
+    pub row: usize,
+    /// The absolute column, in the default grid, of the left of the layer.
+    pub col: usize,
+    /// Number of rows of the grid that are visible.
+    pub height: usize,
+    /// Number of columns of the grid that are visible.
+    pub width: usize,
+    /// Can the window be focused by mouse clicks?
+    pub focusable: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Placement {
+    Window(WinPos),
+    Float(WinFloatPos),
+    External,
+    Message { row: u64 },
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Window {
+    placement: Placement,
+    hidden: bool,
+    /// When the window was last placed, used to order layers of the same kind.
+    order: u64,
+}
+
+/// Placement state of all the grids of the editor.
+#[derive(Debug, Default)]
+pub struct Compositor {
+    /// Size of each grid as `(rows, columns)`.
+    sizes: FnvHashMap<u64, (usize, usize)>,
+    windows: FnvHashMap<u64, Window>,
+    order_counter: u64,
+}
+
+impl Compositor {
+    /// A grid was resized, or created.
+    pub fn resize_grid(&mut self, grid: u64, width: u64, height: u64) {
+        self.sizes.insert(grid, (height as usize, width as usize));
+    }
+
+    /// A grid was destroyed, together with its window.
+    pub fn destroy_grid(&mut self, grid: u64) {
+        self.sizes.remove(&grid);
+        self.windows.remove(&grid);
+    }
+
+    /// Place a normal window in the default grid.
+    pub fn win_pos(&mut self, win_pos: WinPos) {
+        self.place(win_pos.grid, Placement::Window(win_pos));
+    }
+
+    /// Place a floating window relative to its anchor grid.
+    pub fn win_float_pos(&mut self, win_float_pos: WinFloatPos) {
+        self.place(win_float_pos.grid, Placement::Float(win_float_pos));
+    }
+
+    /// Mark a grid as displayed in an external window.
+    ///
+    /// External windows are not part of the composited screen.
+    pub fn win_external_pos(&mut self, grid: u64) {
+        self.place(grid, Placement::External);
+    }
+
+    /// Place the message grid at `row` of the default grid.
+    pub fn msg_set_pos(&mut self, msg_set_pos: MsgSetPos<'_>) {
+        self.place(
+            msg_set_pos.grid,
+            Placement::Message {
+                row: msg_set_pos.row,
+            },
+        );
+    }
+
+    /// Stop displaying the window of a grid, until it is placed again.
+    pub fn win_hide(&mut self, grid: u64) {
+        if let Some(window) = self.windows.get_mut(&grid) {
+            window.hidden = true;
+        }
+    }
+
+    /// Close the window of a grid.
+    pub fn win_close(&mut self, grid: u64) {
+        self.windows.remove(&grid);
+    }
+
+    /// Compute the layers to be drawn, ordered from bottom to top.
+    ///
+    /// Only grids that exist in `grids` are considered, as a window may be placed
+    /// before its grid is created.
+    pub fn compose(&self, grids: &Grids, layers: &mut Vec<Layer>) {
+        layers.clear();
+
+        if let Some((rows, cols)) = self.size(Grids::DEFAULT_GRID) {
+            layers.push(Layer {
+                grid: Grids::DEFAULT_GRID,
+                kind: LayerKind::Default,
+                row: 0,
+                col: 0,
+                height: rows,
+                width: cols,
+                focusable: true,
+            });
+        }
+
+        let mut placed: Vec<_> = self
+            .windows
+            .iter()
+            .filter(|(grid, window)| !window.hidden && grids.get(**grid).is_some())
+            .filter_map(|(grid, window)| Some((self.layer(*grid, window, 0)?, window)))
+            .collect();
+
+        placed.sort_by_key(|(_, window)| {
+            let zindex = match window.placement {
+                Placement::Float(float) => Some(float.zindex.unwrap_or(0)),
+                Placement::Message { .. } => Some(MESSAGE_ZINDEX),
+                _ => None,
+            };
+
+            // Windows have no `zindex` and stay below every float.
+            (zindex, window.order)
+        });
+
+        layers.extend(placed.into_iter().map(|(layer, _)| layer));
+    }
+
+    fn place(&mut self, grid: u64, placement: Placement) {
+        self.order_counter += 1;
+
+        self.windows.insert(
+            grid,
+            Window {
+                placement,
+                hidden: false,
+                order: self.order_counter,
+            },
+        );
+    }
+
+    fn size(&self, grid: u64) -> Option<(usize, usize)> {
+        self.sizes.get(&grid).copied()
+    }
+
+    /// Absolute position of the top-left corner of a grid.
+    fn position(&self, grid: u64, depth: usize) -> Option<(i64, i64)> {
+        if grid == Grids::DEFAULT_GRID {
+            return Some((0, 0));
+        }
+
+        let window = self.windows.get(&grid)?;
+        let layer = self.layer(grid, window, depth)?;
+
+        Some((layer.row as i64, layer.col as i64))
+    }
+
+    fn layer(&self, grid: u64, window: &Window, depth: usize) -> Option<Layer> {
+        let (screen_rows, screen_cols) = self.size(Grids::DEFAULT_GRID).unwrap_or_default();
+        let (grid_rows, grid_cols) = self.size(grid)?;
+
+        let (kind, row, col, height, width, focusable) = match window.placement {
+            Placement::Window(win_pos) => (
+                LayerKind::Window,
+                win_pos.start_row as i64,
+                win_pos.start_col as i64,
+                win_pos.height as usize,
+                win_pos.width as usize,
+                true,
+            ),
+            Placement::Float(float) => {
+                if depth >= MAX_ANCHOR_DEPTH {
+                    log::warn!("floating window anchors of grid {} are too deep", grid);
+                    return None;
+                }
+
+                let (anchor_row, anchor_col) = self.position(float.anchor_grid, depth + 1)?;
+                let row = anchor_row + float.anchor_row as i64;
+                let col = anchor_col + float.anchor_col as i64;
+
+                let (row, col) = match float.anchor {
+                    WinFloatAnchor::Northwest => (row, col),
+                    WinFloatAnchor::Northeast => (row, col - grid_cols as i64),
+                    WinFloatAnchor::Southwest => (row - grid_rows as i64, col),
+                    WinFloatAnchor::Southeast => (row - grid_rows as i64, col - grid_cols as i64),
+                };
+
+                (
+                    LayerKind::Float,
+                    row,
+                    col,
+                    grid_rows,
+                    grid_cols,
+                    float.focusable,
+                )
+            }
+            Placement::Message { row } => (
+                LayerKind::Message,
+                row as i64,
+                0,
+                screen_rows.saturating_sub(row as usize),
+                screen_cols,
+                false,
+            ),
+            Placement::External => return None,
+        };
+
+        let row = row.max(0) as usize;
+        let col = col.max(0) as usize;
+
+        Some(Layer {
+            grid,
+            kind,
+            row,
+            col,
+            height: height.min(grid_rows),
+            width: width.min(grid_cols),
+            focusable,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A compositor and its grids, created with the given `(grid, rows, cols)`.
+    fn setup(sizes: &[(u64, usize, usize)]) -> (Compositor, Grids) {
+        let mut compositor = Compositor::default();
+        let mut grids = Grids::default();
+
+        for &(grid, rows, cols) in sizes {
+            compositor.resize_grid(grid, cols as u64, rows as u64);
+            grids.resize(grid, rows, cols);
+        }
+
+        (compositor, grids)
+    }
+
+    fn win_pos(grid: u64, start_row: u64, start_col: u64, height: u64, width: u64) -> WinPos {
+        WinPos {
+            grid,
+            start_row,
+            start_col,
+            width,
+            height,
+            ..WinPos::default()
+        }
+    }
+
+    fn float(
+        grid: u64,
+        anchor_grid: u64,
+        anchor: WinFloatAnchor,
+        row: f64,
+        col: f64,
+    ) -> WinFloatPos {
+        WinFloatPos {
+            grid,
+            anchor,
+            anchor_grid,
+            anchor_row: row,
+            anchor_col: col,
+            focusable: true,
+            ..WinFloatPos::default()
+        }
+    }
+
+    fn compose(
+        compositor: &Compositor,
+        grids: &Grids,
+    ) -> Vec<(u64, LayerKind, usize, usize, usize, usize)> {
+        let mut layers = Vec::new();
+        compositor.compose(grids, &mut layers);
+
+        layers
+            .iter()
+            .map(|l| (l.grid, l.kind, l.row, l.col, l.height, l.width))
+            .collect()
+    }
+
+    #[test]
+    fn windows_are_placed_over_the_default_grid() {
+        let (mut compositor, grids) = setup(&[(1, 30, 80), (2, 28, 40), (3, 28, 39)]);
+        compositor.win_pos(win_pos(2, 0, 0, 28, 40));
+        compositor.win_pos(win_pos(3, 0, 41, 28, 39));
+
+        assert_eq!(
+            compose(&compositor, &grids),
+            vec![
+                (1, LayerKind::Default, 0, 0, 30, 80),
+                (2, LayerKind::Window, 0, 0, 28, 40),
+                (3, LayerKind::Window, 0, 41, 28, 39),
+            ]
+        );
+    }
+
+    #[test]
+    fn window_layers_are_clipped_to_their_grid() {
+        let (mut compositor, grids) = setup(&[(1, 30, 80), (2, 10, 20)]);
+        compositor.win_pos(win_pos(2, 5, 5, 28, 40));
+
+        assert_eq!(
+            compose(&compositor, &grids)[1],
+            (2, LayerKind::Window, 5, 5, 10, 20)
+        );
+    }
+
+    #[test]
+    fn floats_are_anchored_to_their_grid() {
+        let (mut compositor, grids) = setup(&[(1, 30, 80), (2, 28, 40), (4, 5, 10), (5, 3, 6)]);
+        compositor.win_pos(win_pos(2, 1, 30, 28, 40));
+        compositor.win_float_pos(float(4, 2, WinFloatAnchor::Northwest, 2.0, 3.0));
+        // Anchored to the bottom right corner of the other float.
+        compositor.win_float_pos(float(5, 4, WinFloatAnchor::Southeast, 5.0, 10.0));
+
+        let layers = compose(&compositor, &grids);
+        assert_eq!(layers[2], (4, LayerKind::Float, 3, 33, 5, 10));
+        assert_eq!(layers[3], (5, LayerKind::Float, 5, 37, 3, 6));
+    }
+
+    #[test]
+    fn floats_out_of_the_screen_are_moved_in() {
+        let (mut compositor, grids) = setup(&[(1, 30, 80), (4, 5, 10)]);
+        compositor.win_float_pos(float(4, 1, WinFloatAnchor::Southeast, 2.0, 3.0));
+
+        assert_eq!(
+            compose(&compositor, &grids)[1],
+            (4, LayerKind::Float, 0, 0, 5, 10)
+        );
+    }
+
+    #[test]
+    fn floats_are_stacked_by_zindex_then_placement() {
+        let (mut compositor, grids) = setup(&[(1, 30, 80), (4, 1, 1), (5, 1, 1), (6, 1, 1)]);
+        compositor.win_float_pos(WinFloatPos {
+            zindex: Some(100),
+            ..float(4, 1, WinFloatAnchor::Northwest, 0.0, 0.0)
+        });
+        compositor.win_float_pos(WinFloatPos {
+            zindex: Some(50),
+            ..float(5, 1, WinFloatAnchor::Northwest, 0.0, 0.0)
+        });
+        compositor.win_float_pos(WinFloatPos {
+            zindex: Some(50),
+            ..float(6, 1, WinFloatAnchor::Northwest, 0.0, 0.0)
+        });
+
+        let order: Vec<_> = compose(&compositor, &grids).iter().map(|l| l.0).collect();
+        assert_eq!(order, vec![1, 5, 6, 4]);
+
+        // Placing a float again moves it above the others with the same zindex.
+        compositor.win_float_pos(WinFloatPos {
+            zindex: Some(50),
+            ..float(5, 1, WinFloatAnchor::Northwest, 0.0, 0.0)
+        });
+        let order: Vec<_> = compose(&compositor, &grids).iter().map(|l| l.0).collect();
+        assert_eq!(order, vec![1, 6, 5, 4]);
+    }
+
+    #[test]
+    fn messages_are_drawn_until_the_bottom_below_higher_floats() {
+        let (mut compositor, grids) =
+            setup(&[(1, 30, 80), (2, 28, 80), (3, 10, 80), (4, 2, 2), (5, 2, 2)]);
+        compositor.msg_set_pos(MsgSetPos {
+            grid: 3,
+            row: 25,
+            scrolled: false,
+            sep_char: "",
+        });
+        compositor.win_float_pos(WinFloatPos {
+            zindex: Some(1000),
+            ..float(4, 1, WinFloatAnchor::Northwest, 0.0, 0.0)
+        });
+        compositor.win_float_pos(WinFloatPos {
+            zindex: Some(50),
+            ..float(5, 1, WinFloatAnchor::Northwest, 0.0, 0.0)
+        });
+        compositor.win_pos(win_pos(2, 0, 0, 28, 80));
+
+        assert_eq!(
+            compose(&compositor, &grids),
+            vec![
+                (1, LayerKind::Default, 0, 0, 30, 80),
+                (2, LayerKind::Window, 0, 0, 28, 80),
+                (5, LayerKind::Float, 0, 0, 2, 2),
+                (3, LayerKind::Message, 25, 0, 5, 80),
+                (4, LayerKind::Float, 0, 0, 2, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn hidden_closed_and_external_windows_are_not_drawn() {
+        let (mut compositor, grids) = setup(&[(1, 30, 80), (2, 5, 5), (3, 5, 5), (4, 5, 5)]);
+        compositor.win_pos(win_pos(2, 0, 0, 5, 5));
+        compositor.win_pos(win_pos(3, 0, 0, 5, 5));
+        compositor.win_external_pos(4);
+
+        compositor.win_hide(2);
+        compositor.win_close(3);
+
+        assert_eq!(compose(&compositor, &grids).len(), 1);
+
+        // Hidden windows come back when placed again.
+        compositor.win_pos(win_pos(2, 1, 1, 5, 5));
+        assert_eq!(
+            compose(&compositor, &grids)[1],
+            (2, LayerKind::Window, 1, 1, 5, 5)
+        );
+    }
+
+    #[test]
+    fn windows_without_grid_are_not_drawn() {
+        let (mut compositor, mut grids) = setup(&[(1, 30, 80), (2, 5, 5)]);
+        compositor.win_pos(win_pos(2, 0, 0, 5, 5));
+        grids.destroy(2);
+
+        assert_eq!(compose(&compositor, &grids).len(), 1);
+
+        compositor.destroy_grid(2);
+        grids.resize(2, 5, 5);
+        assert_eq!(compose(&compositor, &grids).len(), 1);
+    }
+
+    #[test]
+    fn anchor_cycles_are_not_drawn() {
+        let (mut compositor, grids) = setup(&[(1, 30, 80), (4, 1, 1), (5, 1, 1)]);
+        compositor.win_float_pos(float(4, 5, WinFloatAnchor::Northwest, 0.0, 0.0));
+        compositor.win_float_pos(float(5, 4, WinFloatAnchor::Northwest, 0.0, 0.0));
+
+        assert_eq!(compose(&compositor, &grids).len(), 1);
+    }
+}
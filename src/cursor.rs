@@ -1,14 +1,23 @@
-use crate::neovim::CursorShape;
+use crate::neovim::{CursorShape, ModeInfo};
 
 #[derive(Debug, Default, Copy, Clone)]
 pub struct Cursor {
+    /// The grid where the cursor is.
+    pub grid: u64,
     pub row: usize,
     pub col: usize,
     pub shape: CursorShape,
+    /// The percentage of the cell covered by the cursor, between `0.0` and `1.0`.
+    pub cell_percentage: f64,
+    /// The highlight group of the cursor.
+    ///
+    /// When this is 0, the colors of the cell under the cursor should be swapped.
+    pub hl_id: u64,
 }
 
 impl Cursor {
-    pub fn move_to(&mut self, row: usize, col: usize) {
+    pub fn move_to(&mut self, grid: u64, row: usize, col: usize) {
+        self.grid = grid;
         self.row = row;
         self.col = col;
     }
@@ -16,4 +25,11 @@ impl Cursor {
     pub fn change_shape(&mut self, shape: CursorShape) {
         self.shape = shape;
     }
+
+    /// Change the style of the cursor to the one of `mode`.
+    pub fn change_mode(&mut self, mode: &ModeInfo) {
+        self.change_shape(mode.cursor_shape);
+        self.cell_percentage = mode.cell_percentage;
+        self.hl_id = mode.attr_id;
+    }
 }
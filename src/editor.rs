@@ -1,7 +1,14 @@
+use crate::compositor::{Compositor, Layer};
+use crate::cursor::Cursor;
+use crate::grid::links::Link;
 use crate::grid::*;
+use crate::guifont::{FontSpec, GuiFont};
+use crate::ligatures::LigatureConfig;
 use crate::neovim::*;
+use crate::width::{AmbiWidth, WidthOptions};
 use cache_padded::CachePadded;
 use font_kit::family_handle::FamilyHandle;
+use font_kit::family_name::FamilyName;
 use font_kit::source::*;
 use std::sync::{
     atomic::{AtomicBool, AtomicI64, AtomicU16, Ordering},
@@ -14,11 +21,13 @@ pub use self::buffering::*;
 const MPSC_CHANNEL_BUFFER_SIZE: usize = 128;
 
 pub struct Editor {
-    lines: TripleBufferWriter,
+    frame: TripleBufferWriter,
+    compositor: Compositor,
     modes: Vec<ModeInfo>,
     curr_mode: usize,
     shared_state: Arc<UiEditorSharedState>,
-    font_source: SystemSource,
+    /// Which highlight groups are drawn with ligatures.
+    ligatures: LigatureConfig,
     tx: Sender<UiEditorEvent>,
 }
 
@@ -35,30 +44,49 @@ impl EventListener for Editor {
             RedrawEvent::Flush => self.flush(),
 
             // Grid events
-            RedrawEvent::GridResize { width, height, .. } => self.resize_grid(width, height),
+            RedrawEvent::GridResize {
+                grid,
+                width,
+                height,
+            } => self.resize_grid(grid, width, height),
             RedrawEvent::DefaultColorsSet(new_default) => self.set_default_color_set(new_default),
             RedrawEvent::HlAttrDefine(hl_attr) => self.define_hl_attr(hl_attr),
             RedrawEvent::GridLine(line) => self.redraw_grid_line(line),
-            RedrawEvent::GridClear(_) => self.clear_grid(),
+            RedrawEvent::GridClear(grid) => self.clear_grid(grid),
+            RedrawEvent::GridDestroy(grid) => self.destroy_grid(grid),
+            RedrawEvent::GridCursorGoto(goto) => self.move_cursor(goto),
             RedrawEvent::GridScroll(scroll) => self.scroll_grid(scroll),
 
+            // Multigrid events
+            RedrawEvent::WinPos(win_pos) => self.compositor.win_pos(win_pos),
+            RedrawEvent::WinFloatPos(win_float_pos) => self.compositor.win_float_pos(win_float_pos),
+            RedrawEvent::WinExternalPos { grid, .. } => self.compositor.win_external_pos(grid),
+            RedrawEvent::WinHide(grid) => self.compositor.win_hide(grid),
+            RedrawEvent::WinClose(grid) => self.compositor.win_close(grid),
+            RedrawEvent::MsgSetPos(msg_set_pos) => self.compositor.msg_set_pos(msg_set_pos),
+
             // Ignore rest of events.
             _ => {}
         }
     }
+
+    fn on_zoom(&mut self, zoom: Zoom) {
+        self.send_event(UiEditorEvent::Zoom(zoom));
+    }
 }
 
 impl Editor {
     pub fn new() -> (Self, UiStateFromEditor) {
-        let (lines, output) = buffering::new_triple_buffer();
+        let (frame, output) = buffering::new_triple_buffer();
         let shared_state = Arc::<UiEditorSharedState>::default();
         let (tx, rx) = tokio::sync::mpsc::channel(MPSC_CHANNEL_BUFFER_SIZE);
 
         let editor = Self {
-            lines,
+            frame,
+            compositor: Compositor::default(),
             modes: Vec::default(),
             curr_mode: 0,
-            font_source: SystemSource::new(),
+            ligatures: LigatureConfig::from_env(),
             shared_state: shared_state.clone(),
             tx,
         };
@@ -79,6 +107,9 @@ impl Editor {
         self.shared_state
             .cursor_style_enabled
             .store(cursor_style_enabled, Ordering::Release);
+
+        // The properties of the current mode may have changed.
+        self.update_cursor_style();
     }
 
     pub fn set_ui_option(&mut self, option: UiOption<'_>) {
@@ -86,16 +117,50 @@ impl Editor {
             UiOption::Int { option, value } => {
                 if option == "linespace" {
                     self.shared_state.linespace.store(value, Ordering::Release);
+                    self.send_event(UiEditorEvent::LinespaceChanged);
                 }
             }
             UiOption::String { option, value } => match option {
-                "guifont" | "guifontset" => {
-                    if let Some((font_family, size)) = self.parse_guifont(value) {
-                        self.send_event(UiEditorEvent::FontChanged(font_family, size));
-                    } else {
-                        log::warn!("No available font in guifont");
+                "guifont" | "guifontset" => match GuiFont::parse(value) {
+                    GuiFont::Picker => {
+                        log::warn!("No font picker available, keeping the current guifont")
                     }
-                }
+                    GuiFont::Default => match self.default_font() {
+                        Some(font) => self.send_event(UiEditorEvent::FontChanged(vec![font])),
+                        None => log::warn!("No monospace font available"),
+                    },
+                    GuiFont::Fonts(specs) => {
+                        let fonts = self.select_fonts(specs);
+
+                        if fonts.is_empty() {
+                            log::warn!("No available font in guifont");
+                        } else {
+                            self.send_event(UiEditorEvent::FontChanged(fonts));
+                        }
+                    }
+                },
+                "guifontwide" => match GuiFont::parse(value) {
+                    GuiFont::Picker => {
+                        log::warn!("No font picker available, keeping the current guifontwide")
+                    }
+                    // Wide characters use the guifont.
+                    GuiFont::Default => self.send_event(UiEditorEvent::WideFontChanged(vec![])),
+                    GuiFont::Fonts(specs) => {
+                        let fonts = self.select_fonts(specs);
+
+                        if fonts.is_empty() {
+                            log::warn!("No available font in guifontwide");
+                        }
+                        self.send_event(UiEditorEvent::WideFontChanged(fonts));
+                    }
+                },
+                "ambiwidth" => match AmbiWidth::from_option(value) {
+                    Some(ambiwidth) => self
+                        .shared_state
+                        .ambiwidth_double
+                        .store(ambiwidth == AmbiWidth::Double, Ordering::Release),
+                    None => log::warn!("Unknown ambiwidth value: {}", value),
+                },
                 _ => {}
             }
             UiOption::Bool { option, value } => match option {
@@ -123,6 +188,7 @@ impl Editor {
                 "ext_messages" => self
                     .shared_state
                     .set_ui_option_if(UiOptions::EXT_MESSAGES, value),
+                "emoji" => self.shared_state.emoji.store(value, Ordering::Release),
                 "mousefocus" => self
                     .shared_state
                     .mouse_focus_enabled
@@ -132,26 +198,57 @@ impl Editor {
         }
     }
 
-    fn parse_guifont(&self, guifont: &str) -> Option<(FamilyHandle, u8)> {
-        dbg!(guifont);
-        // TODO: handle escaped commas
-        for font in guifont.split(',') {
-            dbg!(font);
-            if let Some((name, size)) = font.split_once(":h") {
-                let size = size.parse::<u8>().unwrap_or(12);
-
-                if let Ok(font_family) = self.font_source.select_family_by_name(name) {
-                    return Some((font_family, size));
+    /// Find the families of the fonts in `specs`, skipping the unavailable ones.
+    ///
+    /// The system source isn't kept around, as it can't be sent to other threads.
+    fn select_fonts(&self, specs: Vec<FontSpec>) -> Vec<SelectedFont> {
+        let source = SystemSource::new();
+
+        specs
+            .into_iter()
+            .filter_map(|spec| match source.select_family_by_name(&spec.family) {
+                Ok(family) => Some(SelectedFont { family, spec }),
+                Err(error) => {
+                    log::warn!("Font {:?} not available: {:?}", spec.family, error);
+                    None
                 }
-            }
-        }
+            })
+            .collect()
+    }
 
-        None
+    /// The font used when `guifont` is empty, the system monospace font.
+    fn default_font(&self) -> Option<SelectedFont> {
+        let family = SystemSource::new()
+            .select_family_by_generic_name(&FamilyName::Monospace)
+            .ok()?;
+
+        Some(SelectedFont {
+            family,
+            spec: FontSpec::default(),
+        })
     }
 
     pub fn change_mode(&mut self, mode_idx: u64) {
         if (mode_idx as usize) < self.modes.len() {
             self.curr_mode = mode_idx as usize;
+            self.update_cursor_style();
+        }
+    }
+
+    /// Apply the cursor style of the current mode to the cursor.
+    ///
+    /// When Neovim asks the UI to not set the cursor style, a block cursor with
+    /// swapped colors is used for all modes.
+    fn update_cursor_style(&mut self) {
+        let cursor = &mut self.frame.buffer().cursor;
+
+        match self.modes.get(self.curr_mode) {
+            Some(mode) if self.shared_state.cursor_style_enabled() => cursor.change_mode(mode),
+            _ => cursor.change_mode(&ModeInfo {
+                cursor_shape: CursorShape::Block,
+                cell_percentage: 1.0,
+                attr_id: 0,
+            }),
         }
     }
 
@@ -166,46 +263,81 @@ impl Editor {
     }
 
     pub fn flush(&mut self) {
-        self.lines.buffer().render();
+        let frame = self.frame.buffer();
+        frame.width = self.shared_state.width_options();
+        frame.grids.render();
+        self.compositor.compose(&frame.grids, &mut frame.layers);
 
         // Check if the UI already processed the previous completed buffer.
         //
         // If it haven't, there is a pending redraw event in flight, meaning
         // that we will duplicate the rendering work if we send another one.
-        if !self.lines.publish() {
+        if !self.frame.publish() {
             self.send_event(UiEditorEvent::Redraw);
         }
     }
 
-    pub fn resize_grid(&mut self, width: u64, height: u64) {
-        self.lines.buffer().resize(height as usize, width as usize);
+    pub fn resize_grid(&mut self, grid: u64, width: u64, height: u64) {
+        self.compositor.resize_grid(grid, width, height);
+        self.frame
+            .buffer()
+            .grids
+            .resize(grid, height as usize, width as usize);
     }
 
     pub fn set_default_color_set(&mut self, color_set: DefaultColorSet) {
-        self.send_event(UiEditorEvent::SetDefaultColorsSet(color_set));
+        self.frame.buffer().hl_groups.update_default(color_set);
     }
 
-    pub fn define_hl_attr(&mut self, hl_attr: HighlightAttr) {
-        self.send_event(UiEditorEvent::DefineHlAttr(hl_attr));
+    pub fn define_hl_attr(&mut self, hl_attr: HighlightAttr<'_>) {
+        let ligatures = self.ligatures.allowed_for(hl_attr.names.iter().copied());
+
+        self.frame.buffer().hl_groups.define(hl_attr, ligatures);
     }
 
     pub fn redraw_grid_line(&mut self, grid_line: GridLine<'_>) {
-        self.lines.buffer().update_line(grid_line);
+        if let Some(lines) = self.grid_mut(grid_line.grid) {
+            lines.update_line(grid_line);
+        }
+    }
+
+    pub fn clear_grid(&mut self, grid: u64) {
+        if let Some(lines) = self.grid_mut(grid) {
+            lines.clear();
+        }
     }
 
-    pub fn clear_grid(&mut self) {
-        self.lines.buffer().clear();
+    pub fn destroy_grid(&mut self, grid: u64) {
+        self.compositor.destroy_grid(grid);
+        self.frame.buffer().grids.destroy(grid);
     }
 
     pub fn move_cursor(&mut self, cursor_goto: GridGoto) {
-        self.lines
-            .buffer()
-            .cursor_mut()
-            .move_to(cursor_goto.row as usize, cursor_goto.column as usize);
+        self.frame.buffer().cursor.move_to(
+            cursor_goto.grid,
+            cursor_goto.row as usize,
+            cursor_goto.column as usize,
+        );
     }
 
     pub fn scroll_grid(&mut self, scroll: GridScroll) {
-        self.lines.buffer().scroll(scroll);
+        if let Some(lines) = self.grid_mut(scroll.grid) {
+            lines.scroll(scroll);
+        }
+    }
+
+    /// The grid with the given handle in the writing buffer.
+    ///
+    /// Neovim always sends a `grid_resize` before using a grid, so a missing
+    /// grid means that we're out of sync with it.
+    fn grid_mut(&mut self, grid: u64) -> Option<&mut Lines> {
+        let lines = self.frame.buffer().grids.get_mut(grid);
+
+        if lines.is_none() {
+            log::warn!("received event for unknown grid {}", grid);
+        }
+
+        lines
     }
 
     fn send_event(&mut self, mut event: UiEditorEvent) {
@@ -230,52 +362,113 @@ impl Editor {
     }
 }
 
+/// A font of the `guifont` fallback chain, with its options.
+pub struct SelectedFont {
+    pub family: FamilyHandle,
+    pub spec: FontSpec,
+}
+
+impl std::fmt::Debug for SelectedFont {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SelectedFont")
+            .field("family", &format_args!("[{} fonts]", self.family.fonts().len()))
+            .field("spec", &self.spec)
+            .finish()
+    }
+}
+
 pub enum UiEditorEvent {
-    FontChanged(FamilyHandle, u8),
-    SetDefaultColorsSet(DefaultColorSet),
-    DefineHlAttr(HighlightAttr),
+    /// The `guifont` changed, the fonts are in fallback order and never empty.
+    FontChanged(Vec<SelectedFont>),
+    /// The `guifontwide` changed, an empty chain means that the `guifont` is used.
+    WideFontChanged(Vec<SelectedFont>),
+    /// The `linespace` option changed, see [`UiEditorSharedState::linespace`].
+    LinespaceChanged,
+    /// The user asked to change the font size with `:WeovimZoom`.
+    Zoom(Zoom),
     Redraw,
 }
 
 impl std::fmt::Debug for UiEditorEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::FontChanged(_, size) => f.debug_tuple("UiEditorEvent::FontChanged")
-                .field(&"..")
-                .field(&size)
-                .finish(),
-            Self::SetDefaultColorsSet(set) => f.debug_tuple("UiEditorEvent::SetDefaultColorsSet")
-                .field(&set)
+            Self::FontChanged(fonts) => f.debug_tuple("UiEditorEvent::FontChanged")
+                .field(fonts)
                 .finish(),
-            Self::DefineHlAttr(hl_attr) => f.debug_tuple("UiEditorEvent::DefineHlAttr")
-                .field(&hl_attr)
+            Self::WideFontChanged(fonts) => f.debug_tuple("UiEditorEvent::WideFontChanged")
+                .field(fonts)
                 .finish(),
+            Self::LinespaceChanged => f.debug_tuple("UiEditorEvent::LinespaceChanged").finish(),
+            Self::Zoom(zoom) => f.debug_tuple("UiEditorEvent::Zoom").field(zoom).finish(),
             Self::Redraw => f.debug_tuple("UiEditorEvent::Redraw").finish(),
         }
     }
 }
 
+/// The highlight table, with the default colors.
+///
+/// The table is published together with the grids, so every frame only references
+/// highlight groups that it knows about.
 #[derive(Debug, Default)]
 pub struct HighlightGroups {
     groups: Vec<RgbAttr>,
+    /// Can the text of each group be drawn with ligatures?
+    ligatures: Vec<bool>,
     default: RgbAttr,
+    /// Incremented every time the table or the default colors change.
+    version: u64,
+}
+
+impl Clone for HighlightGroups {
+    fn clone(&self) -> Self {
+        Self {
+            groups: self.groups.clone(),
+            ligatures: self.ligatures.clone(),
+            default: self.default,
+            version: self.version,
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        // The table rarely changes between frames, avoid copying it when possible.
+        if self.version != source.version {
+            self.groups.clone_from(&source.groups);
+            self.ligatures.clone_from(&source.ligatures);
+            self.default = source.default;
+            self.version = source.version;
+        }
+    }
 }
 
 impl HighlightGroups {
-    fn update_default(&mut self, default: RgbAttr) {
-        self.default = default;
+    /// The version of the table.
+    ///
+    /// Can be used to invalidate data derived from the highlight groups.
+    pub fn version(&self) -> u64 {
+        self.version
     }
 
-    fn update(&mut self, hl_attrs: Vec<HighlightAttr>) {
-        self.groups.reserve(hl_attrs.len());
+    fn update_default(&mut self, default: DefaultColorSet) {
+        self.default = RgbAttr::from(default);
+        self.version += 1;
+    }
 
-        for hl in hl_attrs {
-            if self.groups.len() <= hl.id as usize {
-                self.groups.resize_with(hl.id as usize, Default::default);
-            }
+    fn define(&mut self, hl: HighlightAttr<'_>, ligatures: bool) {
+        let idx = hl.id as usize;
 
-            self.groups[hl.id as usize] = hl.rgb_attr;
+        if self.groups.len() <= idx {
+            self.groups.resize_with(idx + 1, Default::default);
+            self.ligatures.resize(idx + 1, true);
         }
+
+        self.groups[idx] = hl.rgb_attr;
+        self.ligatures[idx] = ligatures;
+        self.version += 1;
+    }
+
+    /// Can the text of a highlight group be drawn with ligatures?
+    pub fn ligatures(&self, hl_id: u64) -> bool {
+        self.ligatures.get(hl_id as usize).copied().unwrap_or(true)
     }
 
     /// Returns the final [`RgbAttr`] to be used for a highlight group.
@@ -298,8 +491,116 @@ impl HighlightGroups {
     }
 
     /// Returns the final [`RgbAttr`] to be used when rendering the cursor position.
-    pub fn cursor_color_set(&self, hl_id: u64) -> RgbAttr {
-        self.group_color_set(hl_id).reverse_rgb_attr()
+    ///
+    /// `cell_hl_id` is the highlight group of the cell under the cursor. When the
+    /// cursor has no highlight group (`hl_id == 0`), its colors are swapped.
+    pub fn cursor_color_set(&self, cursor: &Cursor, cell_hl_id: u64) -> RgbAttr {
+        if cursor.hl_id == 0 {
+            self.group_color_set(cell_hl_id).reverse_rgb_attr()
+        } else {
+            self.group_color_set(cursor.hl_id)
+        }
+    }
+}
+
+/// The editor state published to the UI on each flush.
+#[derive(Debug, Default)]
+pub struct Frame {
+    /// All the grids of the editor.
+    pub grids: Grids,
+    /// The grids to be drawn, ordered from bottom to top.
+    pub layers: Vec<Layer>,
+    /// The cursor state, with the style of the current mode.
+    pub cursor: Cursor,
+    /// The highlight groups used by the grids of this frame.
+    pub hl_groups: HighlightGroups,
+    /// How Neovim measured the text of the grids.
+    pub width: WidthOptions,
+}
+
+impl Clone for Frame {
+    fn clone(&self) -> Self {
+        Self {
+            grids: self.grids.clone(),
+            layers: self.layers.clone(),
+            cursor: self.cursor,
+            hl_groups: self.hl_groups.clone(),
+            width: self.width,
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.grids.clone_from(&source.grids);
+        self.layers.clone_from(&source.layers);
+        self.cursor = source.cursor;
+        self.hl_groups.clone_from(&source.hl_groups);
+        self.width = source.width;
+    }
+}
+
+impl Frame {
+    /// The absolute position of the cursor on the screen, as `(row, col)`.
+    ///
+    /// Returns `None` if the grid of the cursor isn't being displayed.
+    pub fn cursor_position(&self) -> Option<(usize, usize)> {
+        let layer = self
+            .layers
+            .iter()
+            .rev()
+            .find(|layer| layer.grid == self.cursor.grid)?;
+
+        Some((layer.row + self.cursor.row, layer.col + self.cursor.col))
+    }
+
+    /// The grid shown at the screen position `(row, col)`, as `(grid, row, col)` with the
+    /// position relative to the grid.
+    pub fn grid_position(&self, row: usize, col: usize) -> Option<(u64, usize, usize)> {
+        let layer = self.layer_at(row, col)?;
+
+        Some((layer.grid, row - layer.row, col - layer.col))
+    }
+
+    /// The link shown at the screen position `(row, col)`.
+    ///
+    /// The position of the returned link is also in screen coordinates, clipped to
+    /// the visible part of its grid.
+    pub fn link_at(&self, row: usize, col: usize) -> Option<Link> {
+        let layer = self.layer_at(row, col)?;
+        let (grid_row, grid_col) = (row - layer.row, col - layer.col);
+
+        let mut link = self
+            .grids
+            .get(layer.grid)?
+            .links(grid_row, &self.width)
+            .into_iter()
+            .find(|link| link.cols.contains(&grid_col))?;
+
+        link.row = row;
+        link.cols = layer.col + link.cols.start..layer.col + link.cols.end.min(layer.width);
+
+        Some(link)
+    }
+
+    /// The top-most layer covering the screen position `(row, col)`.
+    fn layer_at(&self, row: usize, col: usize) -> Option<&Layer> {
+        self.layers.iter().rev().find(|layer| {
+            (layer.row..layer.row + layer.height).contains(&row)
+                && (layer.col..layer.col + layer.width).contains(&col)
+        })
+    }
+
+    /// The highlight group of the cell under the cursor.
+    pub fn cursor_cell_hl_id(&self) -> Option<u64> {
+        self.grids
+            .get(self.cursor.grid)?
+            .hl_id_at(self.cursor.row, self.cursor.col)
+    }
+
+    /// The final [`RgbAttr`] to be used when rendering the cursor.
+    pub fn cursor_color_set(&self) -> RgbAttr {
+        let cell_hl_id = self.cursor_cell_hl_id().unwrap_or(0);
+
+        self.hl_groups.cursor_color_set(&self.cursor, cell_hl_id)
     }
 }
 
@@ -309,16 +610,34 @@ pub struct UiStateFromEditor {
     pub recv: Receiver<UiEditorEvent>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct UiEditorSharedState {
     ui_options: CachePadded<AtomicU16>,
     cursor_style_enabled: CachePadded<AtomicBool>,
     mouse_focus_enabled: CachePadded<AtomicBool>,
     mouse_enabled: CachePadded<AtomicBool>,
     linespace: CachePadded<AtomicI64>,
+    ambiwidth_double: CachePadded<AtomicBool>,
+    emoji: CachePadded<AtomicBool>,
     buzy: CachePadded<AtomicBool>,
 }
 
+impl Default for UiEditorSharedState {
+    fn default() -> Self {
+        Self {
+            ui_options: CachePadded::default(),
+            cursor_style_enabled: CachePadded::default(),
+            mouse_focus_enabled: CachePadded::default(),
+            mouse_enabled: CachePadded::default(),
+            linespace: CachePadded::default(),
+            ambiwidth_double: CachePadded::default(),
+            // `emoji` is on by default in Neovim.
+            emoji: CachePadded::new(AtomicBool::new(true)),
+            buzy: CachePadded::default(),
+        }
+    }
+}
+
 impl UiEditorSharedState {
     pub fn ui_options(&self) -> UiOptions {
         UiOptions::from_bits(self.ui_options.load(Ordering::Relaxed))
@@ -347,6 +666,25 @@ impl UiEditorSharedState {
         self.mouse_enabled.load(Ordering::Relaxed)
     }
 
+    /// Extra pixels between grid rows.
+    pub fn linespace(&self) -> i64 {
+        self.linespace.load(Ordering::Relaxed)
+    }
+
+    /// The options to measure text like Neovim does.
+    pub fn width_options(&self) -> WidthOptions {
+        let ambiwidth = if self.ambiwidth_double.load(Ordering::Relaxed) {
+            AmbiWidth::Double
+        } else {
+            AmbiWidth::Single
+        };
+
+        WidthOptions {
+            ambiwidth,
+            emoji: self.emoji.load(Ordering::Relaxed),
+        }
+    }
+
     pub fn buzy(&self) -> bool {
         self.buzy.load(Ordering::Relaxed)
     }
@@ -360,7 +698,11 @@ mod buffering {
     //!
     //! We can't use the original crate as it doesn't give access to the back-buffer directly,
     //! which we need to ensure that new writing buffer is updated with the latest changes.
-    use super::Lines;
+    //!
+    //! Catching up is incremental: every grid row carries the generation of its last
+    //! rendering, so the new writing buffer only copies the rows that changed since it
+    //! was last current, instead of the entire frame.
+    use super::Frame;
     use cache_padded::CachePadded;
     use std::cell::UnsafeCell;
     use std::sync::{
@@ -372,10 +714,15 @@ mod buffering {
     const BACK_DIRTY_BIT: u8 = 0b100;
 
     struct TripleBufferSharedState {
-        buffers: [CachePadded<UnsafeCell<Lines>>; 3],
+        buffers: [CachePadded<UnsafeCell<Frame>>; 3],
         back_info: CachePadded<AtomicU8>,
     }
 
+    // SAFETY: The buffers are only accessed through the writer and the reader, and the
+    //   synchronization protocol ensures they never access the same buffer.
+    unsafe impl Send for TripleBufferSharedState {}
+    unsafe impl Sync for TripleBufferSharedState {}
+
     impl TripleBufferSharedState {
         fn new() -> Arc<Self> {
             Arc::new(Self {
@@ -411,12 +758,9 @@ mod buffering {
         input_idx: u8,
     }
 
-    unsafe impl Send for TripleBufferWriter {}
-    unsafe impl Sync for TripleBufferWriter {}
-
     impl TripleBufferWriter {
         /// The current writing buffer.
-        pub fn buffer(&mut self) -> &mut Lines {
+        pub fn buffer(&mut self) -> &mut Frame {
             let input_ptr = self.shared.buffers[self.input_idx as usize].get();
 
             // SAFETY: This is safe because the synchronization protocol ensures
@@ -442,6 +786,9 @@ mod buffering {
             let writing = self.buffer();
 
             // Ensure that the writing buffer is up to date with the complete buffer.
+            //
+            // `Frame::clone_from` only copies what changed since the writing buffer
+            // was last published.
             writing.clone_from(complete);
 
             // Tell whether we have overwritten unread data.
@@ -454,11 +801,8 @@ mod buffering {
         output_idx: u8,
     }
 
-    unsafe impl Send for TripleBufferReader {}
-    unsafe impl Sync for TripleBufferReader {}
-
     impl TripleBufferReader {
-        pub fn buffer(&mut self) -> &Lines {
+        pub fn buffer(&mut self) -> &Frame {
             self.update();
             let output_ptr = self.shared.buffers[self.output_idx as usize].get();
 
@@ -483,3 +827,194 @@ mod buffering {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use bumpalo::{collections::Vec as BumpVec, Bump};
+
+    /// Write `text` at the start of `row` of the default grid, a cell per character.
+    fn write(frame: &mut Frame, row: u64, text: &str) {
+        let arena = Bump::new();
+        let mut cells = BumpVec::new_in(&arena);
+        cells.extend(text.char_indices().map(|(idx, chr)| GridCell {
+            text: &text[idx..idx + chr.len_utf8()],
+            hl_id: chr as u64,
+            repeated: 1,
+        }));
+
+        let lines = frame.grids.get_mut(Grids::DEFAULT_GRID).unwrap();
+        lines.update_line(GridLine {
+            grid: Grids::DEFAULT_GRID,
+            row,
+            col_start: 0,
+            cells,
+        });
+    }
+
+    /// The size, and the text and highlight of every cell, of each grid of `frame`.
+    fn contents(frame: &Frame) -> Vec<(u64, usize, usize, Vec<(String, u64)>)> {
+        let mut grids: Vec<_> = frame
+            .grids
+            .iter()
+            .map(|(grid, lines)| {
+                let cells = (0..lines.rows())
+                    .flat_map(|row| (0..lines.cols()).map(move |col| (row, col)))
+                    .map(|(row, col)| {
+                        let text = lines.cell_text(row, col).unwrap_or_default();
+                        (text.to_string(), lines.hl_id_at(row, col).unwrap())
+                    })
+                    .collect();
+                (grid, lines.rows(), lines.cols(), cells)
+            })
+            .collect();
+
+        grids.sort_by_key(|(grid, ..)| *grid);
+        grids
+    }
+
+    /// Render and publish the frame being written, checking that the next frame to
+    /// write, and the frame of the reader if it `reads`, are equal to a full clone.
+    fn publish(writer: &mut TripleBufferWriter, reader: &mut TripleBufferReader, reads: bool) {
+        let frame = writer.buffer();
+        frame.grids.render();
+        let expected = contents(&frame.clone());
+
+        writer.publish();
+
+        assert_eq!(contents(writer.buffer()), expected);
+        if reads {
+            assert_eq!(contents(reader.buffer()), expected);
+        }
+    }
+
+    #[test]
+    fn every_buffer_catches_up_with_the_published_frames() {
+        let (mut writer, mut reader) = new_triple_buffer();
+        let grid = Grids::DEFAULT_GRID;
+
+        writer.buffer().grids.resize(grid, 3, 4);
+        write(writer.buffer(), 0, "abcd");
+        publish(&mut writer, &mut reader, true);
+
+        // The next buffers are one, then two publishes behind.
+        write(writer.buffer(), 1, "efgh");
+        publish(&mut writer, &mut reader, true);
+        write(writer.buffer(), 2, "ijkl");
+        publish(&mut writer, &mut reader, false);
+        write(writer.buffer(), 0, "mnop");
+        publish(&mut writer, &mut reader, true);
+
+        // A resize, followed by a write to the same rows.
+        writer.buffer().grids.resize(grid, 3, 5);
+        write(writer.buffer(), 0, "qrstu");
+        publish(&mut writer, &mut reader, true);
+        writer.buffer().grids.resize(grid, 3, 4);
+        write(writer.buffer(), 1, "vwxy");
+        publish(&mut writer, &mut reader, false);
+
+        // Grids without rows are never rendered.
+        writer.buffer().grids.resize(grid, 0, 4);
+        publish(&mut writer, &mut reader, true);
+        publish(&mut writer, &mut reader, true);
+        writer.buffer().grids.resize(grid, 2, 2);
+        write(writer.buffer(), 1, "z");
+        publish(&mut writer, &mut reader, true);
+        publish(&mut writer, &mut reader, true);
+    }
+
+    /// Define `id` with a foreground color, and if its text can use `ligatures`.
+    fn define(groups: &mut HighlightGroups, id: u64, foreground: Color, ligatures: bool) {
+        let arena = Bump::new();
+        let mut rgb_attr = RgbAttr::default();
+        rgb_attr.foreground = Some(foreground);
+
+        groups.define(
+            HighlightAttr {
+                id,
+                rgb_attr,
+                names: BumpVec::new_in(&arena),
+            },
+            ligatures,
+        );
+    }
+
+    fn foreground(groups: &HighlightGroups, id: u64) -> Option<Color> {
+        groups.group_color_set(id).foreground
+    }
+
+    #[test]
+    fn highlight_groups_can_be_redefined() {
+        let (red, blue) = (Color::from_rgb(255, 0, 0), Color::from_rgb(0, 0, 255));
+        let mut groups = HighlightGroups::default();
+
+        define(&mut groups, 3, red, true);
+        let version = groups.version();
+        define(&mut groups, 3, blue, false);
+
+        assert!(groups.version() > version);
+        assert_eq!(foreground(&groups, 3), Some(blue));
+        assert!(!groups.ligatures(3));
+    }
+
+    #[test]
+    fn sparse_highlight_ids_fill_the_gap_with_defaults() {
+        let red = Color::from_rgb(255, 0, 0);
+        let mut groups = HighlightGroups::default();
+        groups.update_default(DefaultColorSet {
+            foreground: Color::WHITE,
+            background: Color::BLACK,
+            special: red,
+        });
+
+        define(&mut groups, 1000, red, false);
+
+        assert_eq!(groups.groups.len(), 1001);
+        assert_eq!(groups.ligatures.len(), 1001);
+        assert_eq!(foreground(&groups, 1000), Some(red));
+        assert!(!groups.ligatures(1000));
+        // Groups in the gap, and past the table, use the default colors.
+        for id in [0, 500, 999, 1001, 5000] {
+            assert_eq!(foreground(&groups, id), Some(Color::WHITE));
+            assert!(groups.ligatures(id));
+        }
+
+        // Defining a lower id keeps the table.
+        define(&mut groups, 2, red, true);
+        assert_eq!(groups.groups.len(), 1001);
+        assert_eq!(foreground(&groups, 1000), Some(red));
+    }
+
+    #[test]
+    fn highlight_copies_are_only_updated_when_the_version_changes() {
+        let (red, blue) = (Color::from_rgb(255, 0, 0), Color::from_rgb(0, 0, 255));
+        let mut groups = HighlightGroups::default();
+        define(&mut groups, 1, red, true);
+
+        let mut copy = groups.clone();
+        assert_eq!(copy.version(), groups.version());
+        assert_eq!(foreground(&copy, 1), Some(red));
+
+        // A copy of the same version is left alone.
+        copy.groups[1].foreground = Some(blue);
+        copy.clone_from(&groups);
+        assert_eq!(foreground(&copy, 1), Some(blue));
+
+        define(&mut groups, 4, blue, false);
+        copy.clone_from(&groups);
+        assert_eq!(copy.version(), groups.version());
+        assert_eq!(foreground(&copy, 1), Some(red));
+        assert_eq!(foreground(&copy, 4), Some(blue));
+        assert!(!copy.ligatures(4));
+
+        // New default colors are copied too.
+        groups.update_default(DefaultColorSet {
+            foreground: Color::WHITE,
+            background: Color::BLACK,
+            special: Color::WHITE,
+        });
+        copy.clone_from(&groups);
+        assert_eq!(foreground(&copy, 0), Some(Color::WHITE));
+    }
+}
@@ -1,32 +1,93 @@
-pub use self::lines::LineCell;
-use crate::cursor::Cursor;
 use crate::editor::HighlightGroups;
 use crate::neovim::{GridLine, GridScroll};
-use fnv::FnvHashSet;
+use crate::width::WidthOptions;
+use fnv::FnvHashMap;
 use std::cmp::Ordering;
+use std::sync::atomic::{self, AtomicU64};
 
+mod dirty;
 mod lines;
+pub mod links;
 pub mod rendered;
+pub mod text;
 
+use links::Link;
 use rendered::RenderedLines;
+use text::{CellRange, GridText};
+
+/// Source of the grid generations.
+///
+/// Generations are unique across all grids, so equal generations always mean
+/// equal contents, even if a grid was destroyed and created again.
+static GENERATION_COUNTER: AtomicU64 = AtomicU64::new(1);
+
+fn next_generation() -> u64 {
+    GENERATION_COUNTER.fetch_add(1, atomic::Ordering::Relaxed)
+}
 
 /// A grid line sectioned by a property `H` with underlying text `T`.
 ///
 /// "Sectioned" here means that we found all the continuous slices of text
 /// that have the same value for some property `H`. Normally `H` will be
 /// the highlight group of the text.
+///
+/// As cells can have more than one byte of text, and double-width cells cover
+/// two columns, the line also keeps the mapping between text and grid columns.
 #[derive(Debug, Default)]
 pub struct SectionedLine<H, T = String> {
     /// The full line text.
     pub text: T,
     /// The sections of text with same value for `H`.
     pub sections: Vec<Section<H>>,
+    /// The byte offset in `text` where the cell of each column starts.
+    ///
+    /// The right half of a double-width cell has the same offset as its left half.
+    pub columns: Vec<usize>,
 }
 
 impl<H> SectionedLine<H> {
     fn clear(&mut self) {
         self.text.clear();
         self.sections.clear();
+        self.columns.clear();
+    }
+}
+
+impl<H, T: AsRef<str>> SectionedLine<H, T> {
+    /// The range of bytes of `text` of the cell at column `col`.
+    ///
+    /// Both halves of a double-width cell return the range of the full cell.
+    pub fn cell_range(&self, col: usize) -> Option<std::ops::Range<usize>> {
+        let start = *self.columns.get(col)?;
+        let end = self.columns[col..]
+            .iter()
+            .find(|offset| **offset != start)
+            .copied()
+            .unwrap_or_else(|| self.text.as_ref().len());
+
+        Some(start..end)
+    }
+
+    /// The column of the cell containing the byte `offset` of `text`.
+    ///
+    /// For double-width cells, the column of the left half is returned.
+    pub fn column_at(&self, offset: usize) -> Option<usize> {
+        if offset >= self.text.as_ref().len() {
+            return None;
+        }
+
+        // `columns` is sorted, find the last column starting at or before `offset`,
+        // and then go back to the left half if it is a double-width cell.
+        let last = match self.columns.binary_search(&offset) {
+            Ok(col) => col,
+            Err(col) => col.checked_sub(1)?,
+        };
+        let start = self.columns[last];
+
+        self.columns[..=last]
+            .iter()
+            .rposition(|offset| *offset != start)
+            .map_or(Some(0), |col| Some(col + 1))
     }
 }
 
@@ -35,12 +96,14 @@ impl<T: Clone, H: Clone> Clone for SectionedLine<H, T> {
         Self {
             text: self.text.clone(),
             sections: self.sections.clone(),
+            columns: self.columns.clone(),
         }
     }
 
     fn clone_from(&mut self, source: &Self) {
         self.text.clone_from(&source.text);
         self.sections.clone_from(&source.sections);
+        self.columns.clone_from(&source.columns);
     }
 }
 
@@ -49,10 +112,87 @@ impl<T: Clone, H: Clone> Clone for SectionedLine<H, T> {
 pub struct Section<H> {
     /// The value of the property.
     pub hl: H,
-    /// The start of the slice.
+    /// The start of the slice, in bytes of the line text.
     pub start: usize,
-    /// The end of the slice, exclusive.
+    /// The end of the slice, exclusive, in bytes of the line text.
     pub end: usize,
+    /// The first grid column covered by the slice.
+    pub col: usize,
+    /// Number of grid columns covered by the slice.
+    ///
+    /// This may be bigger than the number of characters in the slice, as
+    /// double-width cells cover two columns.
+    pub width: usize,
+}
+
+/// All the grids of the editor, indexed by their Neovim handle.
+///
+/// Without `ext_multigrid` only the [`Grids::DEFAULT_GRID`] exists, otherwise every
+/// window has its own grid. Grids are created by their first `grid_resize` and freed
+/// by `grid_destroy`.
+#[derive(Debug, Default)]
+pub struct Grids {
+    grids: FnvHashMap<u64, Lines>,
+}
+
+impl Clone for Grids {
+    fn clone(&self) -> Self {
+        Self {
+            grids: self.grids.clone(),
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        // Re-use the allocations of the grids that are still alive, instead of
+        // dropping and cloning the entire map.
+        self.grids.retain(|grid, _| source.grids.contains_key(grid));
+
+        for (grid, lines) in &source.grids {
+            match self.grids.get_mut(grid) {
+                Some(current) => current.clone_from(lines),
+                None => {
+                    self.grids.insert(*grid, lines.clone());
+                }
+            }
+        }
+    }
+}
+
+impl Grids {
+    /// The global grid, used for all the windows when `ext_multigrid` isn't active.
+    pub const DEFAULT_GRID: u64 = 1;
+
+    /// Get a grid by its handle.
+    pub fn get(&self, grid: u64) -> Option<&Lines> {
+        self.grids.get(&grid)
+    }
+
+    /// Get a mutable reference to a grid by its handle.
+    pub fn get_mut(&mut self, grid: u64) -> Option<&mut Lines> {
+        self.grids.get_mut(&grid)
+    }
+
+    /// Resize a grid to `rows x columns`, creating it if it wasn't seen before.
+    pub fn resize(&mut self, grid: u64, rows: usize, columns: usize) {
+        self.grids.entry(grid).or_default().resize(rows, columns);
+    }
+
+    /// Free all data associated with a grid.
+    pub fn destroy(&mut self, grid: u64) {
+        self.grids.remove(&grid);
+    }
+
+    /// Iterate over all existing grids, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &Lines)> {
+        self.grids.iter().map(|(grid, lines)| (*grid, lines))
+    }
+
+    /// Render the dirty lines of every grid.
+    pub fn render(&mut self) {
+        for lines in self.grids.values_mut() {
+            lines.render();
+        }
+    }
 }
 
 /// A line grid.
@@ -66,63 +206,149 @@ pub struct Lines {
     ///
     /// The `Vec` should always have size `rows * cols`.
     cells: Vec<lines::LineCell>,
+    /// Grapheme clusters referenced by the cells that need more than one code point.
+    graphemes: lines::GraphemeTable,
     /// Number of rows (that is, lines) in the grid.
     rows: usize,
     /// Number of columns in each line of the grid.
     cols: usize,
-    /// The cursor state of this grid.
-    cursor: Cursor,
     /// Cached rendering of lines.
     ///
     /// Allocations are re-used between renderings.
     cached_sections: Vec<SectionedLine<u64>>,
     /// Lines that were modified and should be re-renderized.
-    dirty_lines: FnvHashSet<usize>,
+    dirty_lines: dirty::DirtyRows,
+    /// Generation of the last rendering of each row.
+    row_generations: Vec<u64>,
+    /// Generation of the last rendering or resize of the grid.
+    generation: u64,
 }
 
 impl Clone for Lines {
     fn clone(&self) -> Self {
         Self {
             cells: self.cells.clone(),
+            graphemes: self.graphemes.clone(),
             rows: self.rows,
             cols: self.cols,
-            cursor: self.cursor,
             cached_sections: self.cached_sections.clone(),
             dirty_lines: self.dirty_lines.clone(),
+            row_generations: self.row_generations.clone(),
+            generation: self.generation,
         }
     }
 
+    /// Bring `self` up to date with `source`.
+    ///
+    /// Only rows rendered since `self` was last up to date are copied, as
+    /// per-row generations tell which rows differ.
     fn clone_from(&mut self, source: &Self) {
-        self.cells.clone_from(&source.cells);
+        if self.generation == source.generation {
+            return;
+        }
+
+        if self.rows != source.rows || self.cols != source.cols {
+            self.cells.clone_from(&source.cells);
+            self.cached_sections.clone_from(&source.cached_sections);
+            self.row_generations.clone_from(&source.row_generations);
+        } else {
+            for row in 0..self.rows {
+                if self.row_generations[row] == source.row_generations[row] {
+                    continue;
+                }
+
+                let start = row * self.cols;
+                let end = start + self.cols;
+
+                self.cells[start..end].copy_from_slice(&source.cells[start..end]);
+                self.cached_sections[row].clone_from(&source.cached_sections[row]);
+                self.row_generations[row] = source.row_generations[row];
+            }
+        }
+
+        self.graphemes.clone_from(&source.graphemes);
         self.rows = source.rows;
         self.cols = source.cols;
-        self.cursor = source.cursor;
-        self.cached_sections.clone_from(&source.cached_sections);
         self.dirty_lines.clone_from(&source.dirty_lines);
+        self.generation = source.generation;
     }
 }
 
 impl Lines {
-    pub fn cursor(&self) -> Cursor {
-        self.cursor
+    /// Number of rows in the grid.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Number of columns in the grid.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// The highlight group of the cell at `row` and `col`, if it is inside the grid.
+    pub fn hl_id_at(&self, row: usize, col: usize) -> Option<u64> {
+        if row < self.rows && col < self.cols {
+            Some(self.cells[row * self.cols + col].hl_id())
+        } else {
+            None
+        }
     }
 
-    pub fn cursor_mut(&mut self) -> &mut Cursor {
-        &mut self.cursor
+    /// The rendered text of the cell at `row` and `col`, if it is inside the grid.
+    ///
+    /// The right half of a double-width cell has no text. Only rendered lines have
+    /// text, as is the case for all lines of a published grid.
+    pub fn cell_text(&self, row: usize, col: usize) -> Option<&str> {
+        let sectioned = self.cached_sections.get(row)?;
+        let start = *sectioned.columns.get(col)?;
+
+        if col > 0 && sectioned.columns[col - 1] == start {
+            return Some("");
+        }
+
+        Some(&sectioned.text[sectioned.cell_range(col)?])
     }
 
     /// Updates the grid with the received neovim update.
     pub fn update_line(&mut self, grid_line: GridLine) {
-        self.dirty_line(grid_line.row as usize);
-        lines::update(self.line_at_mut(grid_line.row as usize), grid_line)
+        let row = grid_line.row as usize;
+        self.dirty_line(row);
+
+        let start = row * self.cols;
+        let end = start + self.cols;
+
+        lines::update(&mut self.cells[start..end], &mut self.graphemes, grid_line);
+
+        // The table only grows until the next resize, drop the clusters of the
+        // overwritten cells once they outnumber the cells.
+        if self.graphemes.len() > 2 * self.cells.len() {
+            self.compact_graphemes();
+        }
+    }
+
+    /// Drop the clusters that no cell references anymore.
+    ///
+    /// Cells of interned clusters are renumbered, their rows are rendered again so
+    /// that the copies of the grid pick up the new numbers.
+    fn compact_graphemes(&mut self) {
+        self.graphemes.compact(&mut self.cells);
+
+        for row in 0..self.rows {
+            let start = row * self.cols;
+            let cells = &self.cells[start..start + self.cols];
+
+            if cells.iter().any(lines::LineCell::is_interned) {
+                self.dirty_line(row);
+            }
+        }
     }
 
     /// Resize the grid to `rows x columns`.
     pub fn resize(&mut self, rows: usize, columns: usize) {
-        self.dirty_all();
         self.cached_sections.resize_with(rows, || SectionedLine {
             text: String::with_capacity(columns),
             sections: Vec::with_capacity(columns / 2),
+            columns: Vec::with_capacity(columns),
         });
 
         self.rows = rows;
@@ -130,6 +356,18 @@ impl Lines {
 
         self.cells
             .resize_with(rows * columns, lines::LineCell::default);
+        self.row_generations.resize(rows, 0);
+
+        // Clusters of the cells that were cut off aren't referenced anymore.
+        self.graphemes.compact(&mut self.cells);
+
+        // Rows that don't exist anymore can't be rendered.
+        self.dirty_lines.resize(rows);
+        self.dirty_all();
+
+        // A grid without rows is never rendered, but the copies of the grid must still
+        // catch up with its new size.
+        self.generation = next_generation();
     }
 
     /// Clears the entire grid.
@@ -139,6 +377,9 @@ impl Lines {
         for cell in &mut self.cells {
             cell.clear();
         }
+
+        // No cell references an interned cluster anymore.
+        self.graphemes.reset();
     }
 
     /// Scroll a region of `grid`.
@@ -156,74 +397,117 @@ impl Lines {
     /// If `rows` is bigger than 0, move a rectangle in the SR up, this can
     /// happen while scrolling down.
     ///
-    ///         +-------------------------+
-    ///         | (clipped above SR)      |            ^
-    ///         |=========================| dst_top    |
-    ///         | dst (still in SR)       |            |
-    ///         +-------------------------+ src_top    |
-    ///         | src (moved up) and dst  |            |
-    ///         |-------------------------| dst_bot    |
-    ///         | src (invalid)           |            |
-    ///         +=========================+ src_bot
+    /// ```text
+    /// +-------------------------+
+    /// | (clipped above SR)      |            ^
+    /// |=========================| dst_top    |
+    /// | dst (still in SR)       |            |
+    /// +-------------------------+ src_top    |
+    /// | src (moved up) and dst  |            |
+    /// |-------------------------| dst_bot    |
+    /// | src (invalid)           |            |
+    /// +=========================+ src_bot
+    /// ```
     ///
     /// If `rows` is less than zero, move a rectangle in the SR down, this can
     /// happen while scrolling up.
     ///
-    ///         +=========================+ src_top
-    ///         | src (invalid)           |            |
-    ///         |------------------------ | dst_top    |
-    ///         | src (moved down) and dst|            |
-    ///         +-------------------------+ src_bot    |
-    ///         | dst (still in SR)       |            |
-    ///         |=========================| dst_bot    |
-    ///         | (clipped below SR)      |            v
-    ///         +-------------------------+
+    /// ```text
+    /// +=========================+ src_top
+    /// | src (invalid)           |            |
+    /// |------------------------ | dst_top    |
+    /// | src (moved down) and dst|            |
+    /// +-------------------------+ src_bot    |
+    /// | dst (still in SR)       |            |
+    /// |=========================| dst_bot    |
+    /// | (clipped below SR)      |            v
+    /// +-------------------------+
+    /// ```
+    ///
+    /// `bottom` and `right` are exclusive. Only the cells in the columns
+    /// `left..right` are moved, the rest of the lines are left untouched, as are
+    /// the "src (invalid)" rows, which Neovim redraws afterwards.
+    ///
+    /// Regions that exceed the grid are clipped to it.
     ///
     /// `cols` is always zero in this version of Nvim, and reserved for future
     /// use.
     pub fn scroll(&mut self, scroll: GridScroll) {
-        let range = match 0.cmp(&scroll.rows) {
-            Ordering::Greater => {
-                Stride::Asc(scroll.top as usize, (scroll.bottom as i64 - scroll.rows + 1) as usize)
-            }
-            Ordering::Less => Stride::Desc(scroll.bottom as usize, (scroll.top as i64 - scroll.rows - 1) as usize),
-            // When `rows == 0`, we aren't scrolling anything, so we can just return.
-            Ordering::Equal => return,
-        };
+        let top = (scroll.top as usize).min(self.rows);
+        let bottom = (scroll.bottom as usize).min(self.rows);
+        let left = (scroll.left as usize).min(self.cols);
+        let right = (scroll.right as usize).min(self.cols);
 
-        // This is needed to guarantee the safety of the unsafe block.
-        if self.cols < scroll.left as usize {
-            panic!("Called scroll with region bigger that line!");
+        if top >= bottom || left >= right {
+            return;
         }
 
-        let line_range = (scroll.right - scroll.left) as usize;
-
-        for i in range {
-            self.dirty_line(i);
-
-            // SAFETY: As rows != 0, src and dst will guaranteed be different
-            // lines and thus, non-overlapping regions (src_idx != i).
-            unsafe {
-                let src_idx = (i as i64 + scroll.rows) as usize;
-
-                let dst = self.line_at_mut(i).as_mut_ptr().add(scroll.left as usize);
-                let src = self.line_at_mut(src_idx).as_mut_ptr().add(scroll.left as usize);
+        let height = bottom - top;
+        let distance = match scroll.rows.checked_abs() {
+            Some(distance) if (distance as u64) < height as u64 => distance as usize,
+            // All the rows of the region were scrolled out of it, nothing to move.
+            _ => return,
+        };
 
-                std::ptr::swap_nonoverlapping(src, dst, line_range);
+        // Copy rows in the direction of the scroll, so that every source row is
+        // read before it is overwritten.
+        match 0.cmp(&scroll.rows) {
+            Ordering::Less => {
+                for dst in top..bottom - distance {
+                    self.copy_row_region(dst + distance, dst, left..right);
+                }
             }
+            Ordering::Greater => {
+                for dst in (top + distance..bottom).rev() {
+                    self.copy_row_region(dst - distance, dst, left..right);
+                }
+            }
+            // When `rows == 0`, we aren't scrolling anything.
+            Ordering::Equal => {}
         }
     }
 
     /// Render the dirty grid lines.
     pub fn render(&mut self) {
-        for row in &self.dirty_lines {
+        if self.dirty_lines.is_empty() {
+            return;
+        }
+
+        let generation = next_generation();
+
+        for row in self.dirty_lines.iter() {
             let start = row * self.cols;
             let end = start + self.cols;
 
-            let line = &mut self.cells[start..end];
-            lines::render(line, &mut self.cached_sections[*row]);
+            let line = &self.cells[start..end];
+            lines::render(line, &self.graphemes, &mut self.cached_sections[row]);
+            self.row_generations[row] = generation;
         }
+
         self.dirty_lines.clear();
+        self.generation = generation;
+    }
+
+    /// The plain text of a range of cells.
+    ///
+    /// Unlike [`Lines::rendered_lines`], this reflects the latest updates even if
+    /// the grid wasn't rendered yet.
+    pub fn text(&self, range: CellRange) -> GridText {
+        GridText::extract(self, range)
+    }
+
+    /// The links found in the text of `row`, with the text measured by `width`.
+    pub fn links(&self, row: usize, width: &WidthOptions) -> Vec<Link> {
+        if row >= self.rows || self.cols == 0 {
+            return Vec::new();
+        }
+
+        let range = CellRange::Linear {
+            start: (row, 0),
+            end: (row, self.cols - 1),
+        };
+
+        links::find(&self.text(range), width)
     }
 
     /// Create an iterator over all rendered lines.
@@ -231,17 +515,23 @@ impl Lines {
         RenderedLines::new(&self.cached_sections, hl_groups)
     }
 
-    fn line_at_mut(&mut self, row: usize) -> &mut lines::Line {
-        let start = row * self.cols;
-        let end = start + self.cols;
+    /// Copy the cells in `cols` of row `src` to the same columns of row `dst`.
+    fn copy_row_region(&mut self, src: usize, dst: usize, cols: std::ops::Range<usize>) {
+        let src_start = src * self.cols;
+        let dst_start = dst * self.cols;
 
-        &mut self.cells[start..end]
+        self.cells.copy_within(
+            src_start + cols.start..src_start + cols.end,
+            dst_start + cols.start,
+        );
+        self.dirty_line(dst);
     }
 
     fn dirty_all(&mut self) {
-        for i in 0..self.rows {
-            self.dirty_lines.insert(i);
-            self.cached_sections[i].clear();
+        self.dirty_lines.insert_all();
+
+        for sectioned in &mut self.cached_sections {
+            sectioned.clear();
         }
     }
 
@@ -252,27 +542,254 @@ impl Lines {
     }
 }
 
-enum Stride {
-    Asc(usize, usize),
-    Desc(usize, usize),
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neovim::GridCell;
+    use bumpalo::{collections::Vec as BumpVec, Bump};
+
+    /// A grid whose cells all have a different highlight, to tell them apart.
+    fn numbered_lines(rows: usize, cols: usize) -> Lines {
+        let arena = Bump::new();
+        let mut lines = Lines::default();
+        lines.resize(rows, cols);
+
+        for row in 0..rows {
+            let mut cells = BumpVec::new_in(&arena);
+            cells.extend((0..cols).map(|col| GridCell {
+                text: "x",
+                hl_id: (row * cols + col) as u64,
+                repeated: 1,
+            }));
+
+            lines.update_line(GridLine {
+                grid: 1,
+                row: row as u64,
+                col_start: 0,
+                cells,
+            });
+        }
+
+        lines
+    }
 
-impl Iterator for Stride {
-    type Item = usize;
+    fn hl_ids(lines: &Lines) -> std::vec::Vec<u64> {
+        (0..lines.rows())
+            .flat_map(|row| (0..lines.cols()).map(move |col| (row, col)))
+            .map(|(row, col)| lines.hl_id_at(row, col).unwrap())
+            .collect()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match *self {
-            Self::Asc(start, stop) if start < stop => {
-                (*self) = Self::Asc(start + 1, stop);
+    /// Scroll `cells` by copying each destination row from a copy of the grid
+    /// before the scroll.
+    fn scroll_model(cells: &mut [u64], rows: usize, cols: usize, scroll: &GridScroll) {
+        let top = (scroll.top as usize).min(rows);
+        let bottom = (scroll.bottom as usize).min(rows);
+        let left = (scroll.left as usize).min(cols);
+        let right = (scroll.right as usize).min(cols);
+        let before = cells.to_vec();
+
+        for dst in top..bottom {
+            let src = dst as i64 + scroll.rows;
+            if src < top as i64 || src >= bottom as i64 {
+                continue;
+            }
 
-                Some(start)
+            for col in left..right {
+                cells[dst * cols + col] = before[src as usize * cols + col];
             }
-            Self::Desc(start, stop) if start > stop => {
-                (*self) = Self::Desc(start - 1, stop);
+        }
+    }
+
+    /// A xorshift generator, good enough to pick regions.
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
+
+    fn assert_scroll_matches_model(rows: usize, cols: usize, scroll: GridScroll) {
+        let mut lines = numbered_lines(rows, cols);
+        let mut expected = hl_ids(&lines);
+
+        scroll_model(&mut expected, rows, cols, &scroll);
+        lines.scroll(scroll);
 
-                Some(start)
+        assert_eq!(hl_ids(&lines), expected);
+    }
+
+    #[test]
+    fn scroll_up_and_down_full_width() {
+        for &rows in &[1, 3, -1, -3] {
+            assert_scroll_matches_model(
+                10,
+                8,
+                GridScroll {
+                    grid: 1,
+                    top: 2,
+                    bottom: 9,
+                    left: 0,
+                    right: 8,
+                    rows,
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn scroll_only_moves_the_region_columns() {
+        let scroll = GridScroll {
+            grid: 1,
+            top: 0,
+            bottom: 6,
+            left: 2,
+            right: 5,
+            rows: 2,
+        };
+        let mut lines = numbered_lines(6, 8);
+        let before = hl_ids(&lines);
+        lines.scroll(scroll);
+
+        for row in 0..6 {
+            for col in (0..2).chain(5..8) {
+                assert_eq!(lines.hl_id_at(row, col), Some(before[row * 8 + col]));
             }
-            _ => None,
         }
+        assert_eq!(lines.hl_id_at(0, 2), Some(before[2 * 8 + 2]));
+    }
+
+    #[test]
+    fn scroll_of_the_whole_region_height_moves_nothing() {
+        for &rows in &[5, 6, 100, -5, -100, i64::MIN] {
+            let mut lines = numbered_lines(8, 4);
+            let before = hl_ids(&lines);
+
+            lines.scroll(GridScroll {
+                grid: 1,
+                top: 1,
+                bottom: 6,
+                left: 0,
+                right: 4,
+                rows,
+            });
+
+            assert_eq!(hl_ids(&lines), before, "rows = {}", rows);
+        }
+    }
+
+    #[test]
+    fn scroll_random_regions() {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+
+        for _ in 0..500 {
+            let rows = 1 + random.below(12) as usize;
+            let cols = 1 + random.below(12) as usize;
+            // Regions may exceed the grid, and be empty.
+            let top = random.below(rows as u64 + 2);
+            let left = random.below(cols as u64 + 2);
+            let scroll = GridScroll {
+                grid: 1,
+                top,
+                bottom: top + random.below(rows as u64 + 2),
+                left,
+                right: left + random.below(cols as u64 + 2),
+                rows: random.below(2 * rows as u64 + 3) as i64 - rows as i64 - 1,
+            };
+
+            assert_scroll_matches_model(rows, cols, scroll);
+        }
+    }
+
+    /// `a`, the double-width `世`, `e` with a combining acute accent and `b`.
+    fn mixed_line() -> SectionedLine<u64> {
+        SectionedLine {
+            text: String::from("a世e\u{301}b"),
+            sections: Vec::new(),
+            columns: vec![0, 1, 1, 4, 7],
+        }
+    }
+
+    #[test]
+    fn cell_ranges_cover_wide_cells_and_combining_marks() {
+        let line = mixed_line();
+
+        assert_eq!(line.cell_range(0), Some(0..1));
+        // Both halves of the double-width cell.
+        assert_eq!(line.cell_range(1), Some(1..4));
+        assert_eq!(line.cell_range(2), Some(1..4));
+        assert_eq!(line.cell_range(3), Some(4..7));
+        assert_eq!(line.cell_range(4), Some(7..8));
+        assert_eq!(line.cell_range(5), None);
+
+        // A double-width cell at the end of the line.
+        let line = SectionedLine::<u64, &str> {
+            text: "a世",
+            sections: Vec::new(),
+            columns: vec![0, 1, 1],
+        };
+        assert_eq!(line.cell_range(2), Some(1..4));
+    }
+
+    #[test]
+    fn columns_at_offsets_are_the_left_of_their_cell() {
+        let line = mixed_line();
+        let columns: Vec<_> = (0..line.text.len())
+            .map(|offset| line.column_at(offset).unwrap())
+            .collect();
+
+        // The bytes of `世`, then of `e` and its accent, belong to a single cell.
+        assert_eq!(columns, [0, 1, 1, 1, 3, 3, 3, 4]);
+        assert_eq!(line.column_at(line.text.len()), None);
+
+        // A double-width cell at the start of the line.
+        let line = SectionedLine::<u64, &str> {
+            text: "世\u{301}a",
+            sections: Vec::new(),
+            columns: vec![0, 0, 5],
+        };
+        assert_eq!(line.column_at(0), Some(0));
+        assert_eq!(line.column_at(4), Some(0));
+        assert_eq!(line.column_at(5), Some(2));
+
+        let empty = SectionedLine::<u64, &str> {
+            text: "",
+            sections: Vec::new(),
+            columns: Vec::new(),
+        };
+        assert_eq!(empty.column_at(0), None);
+        assert_eq!(empty.cell_range(0), None);
+    }
+
+    #[test]
+    fn rendered_lines_map_columns_to_their_cells() {
+        let arena = Bump::new();
+        let mut lines = Lines::default();
+        lines.resize(1, 5);
+
+        let mut cells = BumpVec::new_in(&arena);
+        for text in ["a", "世", "", "e\u{301}", "b"] {
+            cells.push(GridCell {
+                text,
+                hl_id: 0,
+                repeated: 1,
+            });
+        }
+        lines.update_line(GridLine {
+            grid: 1,
+            row: 0,
+            col_start: 0,
+            cells,
+        });
+        lines.render();
+
+        let rendered = &lines.cached_sections[0];
+        let expected = mixed_line();
+        assert_eq!(rendered.text, expected.text);
+        assert_eq!(rendered.columns, expected.columns);
     }
 }
@@ -0,0 +1,170 @@
+/// A fixed-size set of rows, stored as a bitset.
+///
+/// Grids are small enough that a bit per row is cheaper than hashing, and
+/// iterating the set yields rows in order, which is friendlier to the cache
+/// when rendering.
+#[derive(Debug, Default)]
+pub(super) struct DirtyRows {
+    words: Vec<u64>,
+    /// Number of rows in the set.
+    len: usize,
+}
+
+impl Clone for DirtyRows {
+    fn clone(&self) -> Self {
+        Self {
+            words: self.words.clone(),
+            len: self.len,
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.words.clone_from(&source.words);
+        self.len = source.len;
+    }
+}
+
+const WORD_BITS: usize = 64;
+
+impl DirtyRows {
+    /// Change the number of rows that can be tracked, marking all of them as clean.
+    pub(super) fn resize(&mut self, rows: usize) {
+        self.words.clear();
+        self.words.resize(rows.div_ceil(WORD_BITS), 0);
+        self.len = rows;
+    }
+
+    /// Mark a row as dirty, returning if it was clean before.
+    pub(super) fn insert(&mut self, row: usize) -> bool {
+        debug_assert!(row < self.len, "row {} outside of {} rows", row, self.len);
+
+        let word = &mut self.words[row / WORD_BITS];
+        let mask = 1 << (row % WORD_BITS);
+        let was_clean = *word & mask == 0;
+        *word |= mask;
+
+        was_clean
+    }
+
+    /// Mark all rows as dirty.
+    pub(super) fn insert_all(&mut self) {
+        for word in &mut self.words {
+            *word = u64::MAX;
+        }
+
+        let trailing = self.len % WORD_BITS;
+        if let (Some(last), true) = (self.words.last_mut(), trailing != 0) {
+            *last = (1 << trailing) - 1;
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.words.iter().all(|word| *word == 0)
+    }
+
+    /// Mark all rows as clean.
+    pub(super) fn clear(&mut self) {
+        for word in &mut self.words {
+            *word = 0;
+        }
+    }
+
+    /// Iterate over the dirty rows, in ascending order.
+    pub(super) fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words
+            .iter()
+            .enumerate()
+            .filter(|(_, word)| **word != 0)
+            .flat_map(|(idx, word)| {
+                let mut word = *word;
+
+                std::iter::from_fn(move || {
+                    if word == 0 {
+                        return None;
+                    }
+
+                    let bit = word.trailing_zeros() as usize;
+                    word &= word - 1;
+
+                    Some(idx * WORD_BITS + bit)
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(dirty: &DirtyRows) -> Vec<usize> {
+        dirty.iter().collect()
+    }
+
+    #[test]
+    fn rows_are_iterated_in_order_across_words() {
+        let mut dirty = DirtyRows::default();
+        dirty.resize(200);
+        assert!(dirty.is_empty());
+
+        for row in [130, 0, 64, 63, 199, 65] {
+            assert!(dirty.insert(row));
+        }
+        assert!(!dirty.insert(64));
+
+        assert!(!dirty.is_empty());
+        assert_eq!(rows(&dirty), vec![0, 63, 64, 65, 130, 199]);
+
+        dirty.clear();
+        assert!(dirty.is_empty());
+        assert_eq!(rows(&dirty), Vec::<usize>::new());
+        assert!(dirty.insert(63));
+    }
+
+    #[test]
+    fn all_rows_stop_at_the_last_row() {
+        let mut dirty = DirtyRows::default();
+
+        for len in [0, 1, 63, 64, 65, 128, 130] {
+            dirty.resize(len);
+            dirty.insert_all();
+
+            assert_eq!(rows(&dirty), (0..len).collect::<Vec<_>>());
+            assert_eq!(dirty.is_empty(), len == 0);
+        }
+    }
+
+    #[test]
+    fn resizes_clean_all_rows() {
+        let mut dirty = DirtyRows::default();
+        dirty.resize(130);
+        dirty.insert_all();
+
+        // Shrinking drops the rows past the end.
+        dirty.resize(10);
+        assert!(dirty.is_empty());
+        dirty.insert_all();
+        assert_eq!(rows(&dirty), (0..10).collect::<Vec<_>>());
+
+        // Growing doesn't bring back the rows from before the shrink.
+        dirty.resize(100);
+        assert!(dirty.is_empty());
+        dirty.insert(99);
+        dirty.insert(3);
+        assert_eq!(rows(&dirty), vec![3, 99]);
+    }
+
+    #[test]
+    fn clones_copy_the_rows() {
+        let mut dirty = DirtyRows::default();
+        dirty.resize(70);
+        dirty.insert(66);
+
+        let mut copy = DirtyRows::default();
+        copy.resize(3);
+        copy.insert_all();
+        copy.clone_from(&dirty);
+
+        assert_eq!(rows(&copy), vec![66]);
+        assert_eq!(rows(&dirty.clone()), vec![66]);
+    }
+}
@@ -1,95 +1,430 @@
 use super::*;
 use crate::neovim::{GridCell, GridLine};
 
-const NEOVIM_MAXCOMBINE: usize = 6;
+/// Marks a [`LineCell`] code as an index in the [`GraphemeTable`].
+const INTERNED_BIT: u32 = 1 << 31;
 
-/// A sequence of Unicode code points that represent a single grapheme.
-/// Holds up to six (`maxcombine` in Neovim) UTF-8 encoded code points.
-type GraphemeCluster = [char; NEOVIM_MAXCOMBINE];
+/// The code of the right half of a double-width cell.
+///
+/// Neovim never sends NUL in a cell, so we can use it as the marker.
+const CONTINUATION_CODE: u32 = 0;
 
-const EMPTY_GRAPHEME: GraphemeCluster = [' '; 6];
-
-#[derive(Debug, Copy, Clone)]
+/// A compact grid cell.
+///
+/// Cells holding a single code point, which are the vast majority, store it
+/// inline. Grapheme clusters with more than one code point are interned in
+/// the grid's [`GraphemeTable`], and the cell stores their index.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct LineCell {
-    chr: GraphemeCluster,
-    size: usize,
-    hl_id: u64,
+    /// Either a `char`, an interned cluster index with `INTERNED_BIT` set or
+    /// `CONTINUATION_CODE`.
+    code: u32,
+    hl_id: u32,
 }
 
 impl Default for LineCell {
     fn default() -> Self {
         LineCell {
-            chr: EMPTY_GRAPHEME,
-            size: 0,
-            hl_id: u64::max_value(),
+            code: ' ' as u32,
+            hl_id: 0,
         }
     }
 }
 
 impl LineCell {
-    fn new(cell: GridCell<'_>) -> Self {
-        let mut default = Self::default();
-        default.update(cell);
+    fn new(cell: GridCell<'_>, graphemes: &mut GraphemeTable) -> Self {
+        let mut chars = cell.text.chars();
+
+        let code = match (chars.next(), chars.next()) {
+            (None, _) => CONTINUATION_CODE,
+            (Some(chr), None) => chr as u32,
+            (Some(_), Some(_)) => graphemes.intern(cell.text) | INTERNED_BIT,
+        };
+
+        LineCell {
+            code,
+            hl_id: cell.hl_id as u32,
+        }
+    }
 
-        default
+    pub(super) fn hl_id(&self) -> u64 {
+        self.hl_id as u64
     }
 
     pub(super) fn clear(&mut self) {
         *self = Self::default();
     }
 
-    fn update(&mut self, cell: GridCell<'_>) {
-        self.size = 0;
+    /// Is this cell the right half of a double-width cell?
+    ///
+    /// Neovim sends these cells with empty text.
+    pub(super) fn is_continuation(&self) -> bool {
+        self.code == CONTINUATION_CODE
+    }
+
+    /// Does this cell reference a cluster of the [`GraphemeTable`]?
+    pub(super) fn is_interned(&self) -> bool {
+        self.code & INTERNED_BIT != 0
+    }
+
+    /// Does this cell hold a single ASCII character?
+    fn is_ascii(&self) -> bool {
+        self.code != CONTINUATION_CODE && self.code < 0x80
+    }
+
+    /// Push the text of the cell to `text`.
+    ///
+    /// The right half of a double-width cell has no text.
+    pub(super) fn push_text(self, graphemes: &GraphemeTable, text: &mut String) {
+        if self.is_continuation() {
+            return;
+        }
+
+        if self.is_interned() {
+            text.push_str(graphemes.get(self.code & !INTERNED_BIT));
+        } else if let Some(chr) = std::char::from_u32(self.code) {
+            text.push(chr);
+        }
+    }
+}
+
+/// Interned grapheme clusters of a grid.
+///
+/// The table is append-only between resets, so a copy of it can be brought up
+/// to date by only copying the clusters it is missing.
+#[derive(Debug)]
+pub(super) struct GraphemeTable {
+    clusters: Vec<Box<str>>,
+    indexes: FnvHashMap<Box<str>, u32>,
+    /// A new grid generation every time the table is reset, so the epochs of the
+    /// tables of different grids never match.
+    epoch: u64,
+}
+
+impl Default for GraphemeTable {
+    fn default() -> Self {
+        Self {
+            clusters: Vec::new(),
+            indexes: FnvHashMap::default(),
+            epoch: next_generation(),
+        }
+    }
+}
+
+impl Clone for GraphemeTable {
+    fn clone(&self) -> Self {
+        Self {
+            clusters: self.clusters.clone(),
+            indexes: self.indexes.clone(),
+            epoch: self.epoch,
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        if self.epoch != source.epoch || self.clusters.len() > source.clusters.len() {
+            self.clusters.clone_from(&source.clusters);
+            self.indexes.clone_from(&source.indexes);
+            self.epoch = source.epoch;
+            return;
+        }
+
+        for cluster in &source.clusters[self.clusters.len()..] {
+            self.indexes
+                .insert(cluster.clone(), self.clusters.len() as u32);
+            self.clusters.push(cluster.clone());
+        }
+    }
+}
 
-        for (idx, chr) in cell.text.chars().take(NEOVIM_MAXCOMBINE).enumerate() {
-            self.chr[idx] = chr;
-            self.size += 1;
+impl GraphemeTable {
+    fn intern(&mut self, cluster: &str) -> u32 {
+        if let Some(idx) = self.indexes.get(cluster) {
+            return *idx;
         }
 
-        self.hl_id = cell.hl_id;
+        let idx = self.clusters.len() as u32;
+        debug_assert!(idx & INTERNED_BIT == 0, "grapheme table overflow");
+
+        self.clusters.push(cluster.into());
+        self.indexes.insert(cluster.into(), idx);
+
+        idx
+    }
+
+    /// Number of interned clusters, including the ones no cell references anymore.
+    pub(super) fn len(&self) -> usize {
+        self.clusters.len()
+    }
+
+    fn get(&self, idx: u32) -> &str {
+        &self.clusters[idx as usize]
     }
 
-    fn render_in(self, sectioned: &mut SectionedLine<u64>) {
-        for ch in &self.chr[..self.size] {
-            sectioned.text.push(*ch);
+    /// Remove all clusters. Only valid when no cell references the table.
+    pub(super) fn reset(&mut self) {
+        self.clusters.clear();
+        self.indexes.clear();
+        self.epoch = next_generation();
+    }
+
+    /// Drop the clusters no cell of `cells` references anymore.
+    pub(super) fn compact(&mut self, cells: &mut [LineCell]) {
+        let old = std::mem::take(&mut self.clusters);
+        self.reset();
+
+        for cell in cells {
+            if cell.is_interned() {
+                cell.code = self.intern(&old[(cell.code & !INTERNED_BIT) as usize]) | INTERNED_BIT;
+            }
         }
     }
 }
 
 pub(super) type Line = [LineCell];
 
-pub(super) fn update(line: &mut Line, new_line: GridLine<'_>) {
+pub(super) fn update(line: &mut Line, graphemes: &mut GraphemeTable, new_line: GridLine<'_>) {
     let mut cells = &mut line[new_line.col_start as usize..];
 
     for gc in new_line.cells {
         let (cells_to_update, remainers) = cells.split_at_mut(gc.repeated as usize);
         cells = remainers;
 
-        let new_cell = LineCell::new(gc);
+        let new_cell = LineCell::new(gc, graphemes);
         cells_to_update.fill(new_cell);
     }
 }
 
-pub(super) fn render(line: &Line, sectioned: &mut SectionedLine<u64>) {
-    if let Some((fc, cells)) = line.split_first() {
-        fc.render_in(sectioned);
+pub(super) fn render(line: &Line, graphemes: &GraphemeTable, sectioned: &mut SectionedLine<u64>) {
+    let mut current = match line.first() {
+        Some(first) => Section {
+            hl: first.hl_id(),
+            start: 0,
+            end: 0,
+            col: 0,
+            width: 0,
+        },
+        None => return,
+    };
+
+    // Most lines are pure ASCII, for which there is exactly one byte per column.
+    sectioned.text.reserve(line.len());
+    sectioned.columns.reserve(line.len());
+
+    let mut col = 0;
+    while col < line.len() {
+        let cell = line[col];
+
+        // The right half of a double-width cell belongs to the same section of
+        // its left half, whatever its highlight is.
+        if cell.is_continuation() && col != 0 {
+            let left_half = sectioned.columns[col - 1];
+            sectioned.columns.push(left_half);
+            current.width += 1;
+            col += 1;
+            continue;
+        }
 
-        let mut hl = fc.hl_id;
-        let mut old_len = 0;
+        if cell.hl_id() != current.hl && current.width != 0 {
+            current.end = sectioned.text.len();
+            sectioned.sections.push(current);
 
+            current = Section {
+                hl: cell.hl_id(),
+                start: current.end,
+                end: current.end,
+                col,
+                width: 0,
+            };
+        }
+
+        let run = ascii_run(&line[col..], cell.hl_id);
+        if run != 0 {
+            push_ascii(&line[col..col + run], sectioned);
+            current.width += run;
+            col += run;
+            continue;
+        }
+
+        sectioned.columns.push(sectioned.text.len());
+        current.width += 1;
+
+        if cell.is_continuation() {
+            // A right half without its left half, can only happen if the
+            // line starts with it. Show it as a blank cell.
+            sectioned.text.push(' ');
+        } else {
+            cell.push_text(graphemes, &mut sectioned.text);
+        }
+
+        col += 1;
+    }
+
+    current.end = sectioned.text.len();
+    sectioned.sections.push(current);
+}
+
+/// Length of the run of ASCII cells with highlight `hl_id` at the start of `cells`.
+fn ascii_run(cells: &[LineCell], hl_id: u32) -> usize {
+    cells
+        .iter()
+        .take_while(|cell| cell.hl_id == hl_id && cell.is_ascii())
+        .count()
+}
+
+/// Push a run of ASCII cells in bulk, skipping `char` encoding.
+fn push_ascii(cells: &[LineCell], sectioned: &mut SectionedLine<u64>) {
+    let offset = sectioned.text.len();
+    sectioned.columns.extend(offset..offset + cells.len());
+
+    // SAFETY: all cells are ASCII, so each code is a single byte of valid UTF-8.
+    unsafe {
+        sectioned
+            .text
+            .as_mut_vec()
+            .extend(cells.iter().map(|cell| cell.code as u8));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bumpalo::{collections::Vec as BumpVec, Bump};
+
+    fn cell(text: &str, graphemes: &mut GraphemeTable) -> LineCell {
+        LineCell::new(
+            GridCell {
+                text,
+                hl_id: 0,
+                repeated: 1,
+            },
+            graphemes,
+        )
+    }
+
+    fn text(cells: &[LineCell], graphemes: &GraphemeTable) -> String {
+        let mut text = String::new();
         for cell in cells {
-            if cell.hl_id != hl && sectioned.text.len() != old_len {
-                sectioned.sections.push(Section {
-                    hl,
-                    start: old_len,
-                    end: sectioned.text.len(),
-                });
-
-                hl = cell.hl_id;
-                old_len = sectioned.text.len();
-            }
+            cell.push_text(graphemes, &mut text);
+        }
+
+        text
+    }
+
+    #[test]
+    fn clusters_are_interned_once() {
+        let mut graphemes = GraphemeTable::default();
+
+        let first = graphemes.intern("e\u{301}");
+        assert_eq!(graphemes.intern("a\u{308}"), first + 1);
+        assert_eq!(graphemes.intern("e\u{301}"), first);
+        assert_eq!(graphemes.len(), 2);
+        assert_eq!(graphemes.get(first), "e\u{301}");
+    }
+
+    #[test]
+    fn only_clusters_of_several_code_points_are_interned() {
+        let mut graphemes = GraphemeTable::default();
+        // A family, four emoji joined by zero width joiners.
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}\u{200d}\u{1f466}";
+        let texts = ["a", "é", "世", "", "\u{1f44d}\u{1f3fd}", family, "e\u{301}"];
+
+        let cells: Vec<_> = texts.iter().map(|t| cell(t, &mut graphemes)).collect();
+        let interned: Vec<_> = cells.iter().map(LineCell::is_interned).collect();
+
+        assert_eq!(interned, [false, false, false, false, true, true, true]);
+        assert!(cells[3].is_continuation());
+        assert_eq!(graphemes.len(), 3);
+        assert_eq!(text(&cells, &graphemes), texts.concat());
+    }
+
+    #[test]
+    fn compacting_keeps_the_referenced_clusters() {
+        let mut graphemes = GraphemeTable::default();
+        let mut cells = vec![
+            cell("e\u{301}", &mut graphemes),
+            cell("a\u{308}", &mut graphemes),
+            cell("o\u{302}", &mut graphemes),
+            cell("x", &mut graphemes),
+        ];
+        cells[0] = cell("a\u{308}", &mut graphemes);
+        cells[2] = cell("y", &mut graphemes);
+
+        let epoch = graphemes.epoch;
+        graphemes.compact(&mut cells);
+
+        assert_ne!(graphemes.epoch, epoch);
+        assert_eq!(graphemes.len(), 1);
+        assert_eq!(cells[0], cells[1]);
+        assert_eq!(text(&cells, &graphemes), "a\u{308}a\u{308}yx");
+    }
+
+    #[test]
+    fn copies_append_new_clusters_until_the_table_is_reset() {
+        let mut graphemes = GraphemeTable::default();
+        graphemes.intern("e\u{301}");
+
+        let mut copy = graphemes.clone();
+        graphemes.intern("a\u{308}");
+        copy.clone_from(&graphemes);
+
+        assert_eq!(copy.epoch, graphemes.epoch);
+        assert_eq!(copy.clusters, graphemes.clusters);
+        assert_eq!(copy.indexes.get("a\u{308}"), Some(&1));
+
+        // The same number of clusters, but different ones.
+        graphemes.reset();
+        graphemes.intern("o\u{302}");
+        graphemes.intern("u\u{308}");
+        copy.clone_from(&graphemes);
+
+        assert_eq!(copy.epoch, graphemes.epoch);
+        assert_eq!(copy.clusters, graphemes.clusters);
+        assert_eq!(copy.indexes.get("e\u{301}"), None);
+
+        // A table of another grid.
+        let other = GraphemeTable::default();
+        copy.clone_from(&other);
+        assert_eq!(copy.len(), 0);
+    }
+
+    #[test]
+    fn grids_compact_their_clusters_as_they_are_overwritten() {
+        let arena = Bump::new();
+        let mut lines = Lines::default();
+        lines.resize(2, 2);
+        let mut copy = lines.clone();
+
+        // More clusters than the cells can hold, as a fast scrolling buffer sends.
+        for idx in 0..100 {
+            let text = arena.alloc_str(&format!("{}\u{301}", idx % 10));
+            let mut cells = BumpVec::new_in(&arena);
+            cells.push(GridCell {
+                text,
+                hl_id: 0,
+                repeated: 1,
+            });
+            cells.push(GridCell {
+                text: "x",
+                hl_id: 0,
+                repeated: 1,
+            });
+
+            lines.update_line(GridLine {
+                grid: 1,
+                row: (idx % 2) as u64,
+                col_start: 0,
+                cells,
+            });
+            assert!(lines.graphemes.len() <= 2 * 4);
+
+            lines.render();
+            copy.clone_from(&lines);
+        }
 
-            cell.render_in(sectioned);
+        for copy in [&copy, &lines] {
+            assert_eq!(copy.cell_text(0, 0), Some("8\u{301}"));
+            assert_eq!(copy.cell_text(1, 0), Some("9\u{301}"));
+            assert_eq!(text(&copy.cells, &copy.graphemes), "8\u{301}x9\u{301}x");
         }
     }
 }
@@ -0,0 +1,323 @@
+//! # Link Detection
+//!
+//! Finds URLs and `path:line:col` references in the text of the rows of a grid, so
+//! that frontends can underline them on hover and open them on click.
+use super::text::GridText;
+use crate::width::WidthOptions;
+use std::ops::Range;
+
+/// Characters that may end a token, but are almost never part of a link.
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', '\'', '"', '`'];
+/// Characters that may start a token, but are never part of a link.
+const LEADING_PUNCTUATION: &[char] = &['(', '[', '{', '<', '\'', '"', '`'];
+/// Characters that are never part of an URL, like the quote closing an attribute.
+const URL_END_CHARS: &[char] = &['"', '<', '>', '`'];
+
+/// What a link points to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkTarget {
+    /// An URL with an explicit scheme, like `https://neovim.io`.
+    Url(String),
+    /// A file reference, like `src/main.rs:12:5`.
+    File {
+        path: String,
+        line: u64,
+        col: Option<u64>,
+    },
+}
+
+/// A link found in a grid row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// The row of the link.
+    pub row: usize,
+    /// The columns covered by the link.
+    pub cols: Range<usize>,
+    /// What the link points to.
+    pub target: LinkTarget,
+}
+
+/// Find all the links of the text of a single row, measured with `width`.
+pub(super) fn find(text: &GridText, width: &WidthOptions) -> Vec<Link> {
+    let source = text.as_str();
+
+    detect(source)
+        .into_iter()
+        .filter_map(|(range, target)| {
+            let (row, start) = text.position_at(range.start)?;
+            let end = start + width.width(&source[range]);
+
+            Some(Link {
+                row,
+                cols: start..end,
+                target,
+            })
+        })
+        .collect()
+}
+
+/// Find all links in `text`, returning their byte ranges.
+pub fn detect(text: &str) -> Vec<(Range<usize>, LinkTarget)> {
+    let mut links = Vec::new();
+    let mut rest = text;
+    let mut offset = 0;
+
+    while !rest.is_empty() {
+        let start = rest.len() - rest.trim_start().len();
+        let end = rest[start..]
+            .find(char::is_whitespace)
+            .map_or(rest.len(), |end| start + end);
+
+        let token = trim_token(&rest[start..end]);
+        if !token.is_empty() {
+            // `token` is a sub-slice of `rest`.
+            let token_start = offset + (token.as_ptr() as usize - rest.as_ptr() as usize);
+
+            if let Some((range, target)) = url(token).or_else(|| file_reference(token)) {
+                let range = token_start + range.start..token_start + range.end;
+                links.push((range, target));
+            }
+        }
+
+        rest = &rest[end..];
+        offset += end;
+    }
+
+    links
+}
+
+/// Remove the punctuation around a token.
+///
+/// Closing brackets are only removed when they don't match an opening one,
+/// as in `https://en.wikipedia.org/wiki/Vim_(text_editor)`.
+fn trim_token(mut token: &str) -> &str {
+    token = token.trim_start_matches(LEADING_PUNCTUATION);
+
+    loop {
+        let last = match token.chars().last() {
+            Some(last) => last,
+            None => return token,
+        };
+
+        let opening = match last {
+            ')' => '(',
+            ']' => '[',
+            '}' => '{',
+            '>' => '<',
+            chr if TRAILING_PUNCTUATION.contains(&chr) => {
+                token = &token[..token.len() - 1];
+                continue;
+            }
+            _ => return token,
+        };
+
+        if token.matches(opening).count() < token.matches(last).count() {
+            token = &token[..token.len() - 1];
+        } else {
+            return token;
+        }
+    }
+}
+
+fn url(token: &str) -> Option<(Range<usize>, LinkTarget)> {
+    let separator = token.find("://")?;
+
+    // The scheme starts after the last character that can't be part of it,
+    // so that `href=https://neovim.io` links to the URL only.
+    let start = token[..separator]
+        .rfind(|chr: char| !(chr.is_ascii_alphanumeric() || "+-.".contains(chr)))
+        .map_or(0, |idx| idx + 1);
+
+    let end = token[start..]
+        .find(URL_END_CHARS)
+        .map_or(token.len(), |end| start + end);
+    // Brackets are only balanced with those of the URL, not with those before it, as
+    // in `[link](https://en.wikipedia.org/wiki/Vim_(text_editor))`.
+    let end = start + trim_token(&token[start..end]).len();
+
+    let scheme = &token[start..separator];
+    let has_address = end > separator + 3;
+
+    if scheme.starts_with(|chr: char| chr.is_ascii_alphabetic()) && has_address {
+        let url = token[start..end].to_string();
+        Some((start..end, LinkTarget::Url(url)))
+    } else {
+        None
+    }
+}
+
+fn file_reference(token: &str) -> Option<(Range<usize>, LinkTarget)> {
+    let mut parts = token.rsplitn(3, ':');
+    let last = parts.next()?;
+    let middle = parts.next()?;
+
+    let (path, line, col) = match (middle.parse(), last.parse(), parts.next()) {
+        // `path:line:col`
+        (Ok(line), Ok(col), Some(path)) => (path, line, Some(col)),
+        // `path:line`, where the path may have a `:`.
+        (_, Ok(line), _) => (&token[..token.len() - last.len() - 1], line, None),
+        _ => return None,
+    };
+
+    let is_path_char = |chr: char| chr.is_alphanumeric() || "/\\._-~+@:".contains(chr);
+    // Numbers, like versions or addresses in `127.0.0.1:8080`, aren't paths.
+    let is_number = !path.contains('/') && path.starts_with(|chr: char| chr.is_ascii_digit());
+    let looks_like_path =
+        path.contains(&['/', '.'][..]) && !is_number && path.chars().all(is_path_char);
+
+    if looks_like_path && line > 0 {
+        let target = LinkTarget::File {
+            path: path.to_string(),
+            line,
+            col,
+        };
+
+        Some((0..token.len(), target))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text and the target of the links of `text`.
+    fn links(text: &str) -> Vec<(&str, LinkTarget)> {
+        detect(text)
+            .into_iter()
+            .map(|(range, target)| (&text[range], target))
+            .collect()
+    }
+
+    fn url(url: &str) -> (&str, LinkTarget) {
+        (url, LinkTarget::Url(url.to_string()))
+    }
+
+    fn file<'a>(text: &'a str, path: &str, line: u64, col: Option<u64>) -> (&'a str, LinkTarget) {
+        let target = LinkTarget::File {
+            path: path.to_string(),
+            line,
+            col,
+        };
+        (text, target)
+    }
+
+    #[test]
+    fn trailing_punctuation_and_quotes_are_not_part_of_urls() {
+        assert_eq!(links("see https://neovim.io."), [url("https://neovim.io")]);
+        assert_eq!(links("https://neovim.io, or"), [url("https://neovim.io")]);
+        assert_eq!(
+            links("is it https://neovim.io?!"),
+            [url("https://neovim.io")]
+        );
+        assert_eq!(links("'https://neovim.io'"), [url("https://neovim.io")]);
+        assert_eq!(links("\"https://neovim.io\";"), [url("https://neovim.io")]);
+        assert_eq!(links("`https://neovim.io`"), [url("https://neovim.io")]);
+    }
+
+    #[test]
+    fn balanced_parentheses_are_part_of_urls() {
+        let wiki = "https://en.wikipedia.org/wiki/Vim_(text_editor)";
+
+        assert_eq!(links(wiki), [url(wiki)]);
+        assert_eq!(links(&format!("({})", wiki)), [url(wiki)]);
+        assert_eq!(links(&format!("[link]({}).", wiki)), [url(wiki)]);
+        assert_eq!(
+            links("(see https://neovim.io/doc)"),
+            [url("https://neovim.io/doc")]
+        );
+        assert_eq!(links("<https://neovim.io>"), [url("https://neovim.io")]);
+    }
+
+    #[test]
+    fn urls_of_attributes_start_at_their_scheme() {
+        assert_eq!(
+            links("<a href=\"https://neovim.io/news\">News</a>"),
+            [url("https://neovim.io/news")]
+        );
+        assert_eq!(
+            links("src='ftp://example.com/a'"),
+            [url("ftp://example.com/a")]
+        );
+    }
+
+    #[test]
+    fn urls_need_a_scheme_and_an_address() {
+        assert_eq!(
+            links("git+ssh://example.com/repo"),
+            [url("git+ssh://example.com/repo")]
+        );
+        assert_eq!(links("https://"), []);
+        assert_eq!(links("://example.com"), []);
+        assert_eq!(links("1http://example.com"), []);
+    }
+
+    #[test]
+    fn bare_hosts_and_addresses_are_not_links() {
+        for text in &[
+            "neovim.io",
+            "www.example.com",
+            "127.0.0.1",
+            "127.0.0.1:8080",
+            "192.168.0.1:22",
+            "[::1]:8080",
+            "localhost:3000",
+            "v0.5.0",
+            "1.2.3:4",
+        ] {
+            assert_eq!(links(text), [], "{}", text);
+        }
+    }
+
+    #[test]
+    fn file_references_have_a_line_and_maybe_a_column() {
+        assert_eq!(
+            links("src/main.rs:12:5: error"),
+            [file("src/main.rs:12:5", "src/main.rs", 12, Some(5))]
+        );
+        assert_eq!(
+            links("at main.rs:12."),
+            [file("main.rs:12", "main.rs", 12, None)]
+        );
+        assert_eq!(
+            links("(../lib/mod.rs:3)"),
+            [file("../lib/mod.rs:3", "../lib/mod.rs", 3, None)]
+        );
+        // Lines start at 1.
+        assert_eq!(links("main.rs:0"), []);
+        assert_eq!(links("src/main.rs"), []);
+        assert_eq!(links("main:12"), []);
+    }
+
+    #[test]
+    fn windows_paths_keep_their_drive() {
+        assert_eq!(
+            links("C:\\src\\main.rs:12:5"),
+            [file(
+                "C:\\src\\main.rs:12:5",
+                "C:\\src\\main.rs",
+                12,
+                Some(5)
+            )]
+        );
+        assert_eq!(
+            links("D:/work/lib.rs:7"),
+            [file("D:/work/lib.rs:7", "D:/work/lib.rs", 7, None)]
+        );
+    }
+
+    #[test]
+    fn every_link_of_the_text_is_found() {
+        let found = links("https://neovim.io and src/a.rs:1 then https://github.com");
+
+        assert_eq!(
+            found,
+            [
+                url("https://neovim.io"),
+                file("src/a.rs:1", "src/a.rs", 1, None),
+                url("https://github.com"),
+            ]
+        );
+    }
+}
@@ -27,43 +27,73 @@ impl<'l> Iterator for RenderedLines<'l> {
     }
 }
 
+#[derive(Clone)]
 pub struct RenderedLine<'l> {
     hl_groups: &'l HighlightGroups,
-    text: &'l str,
+    line: &'l SectionedLine<u64>,
     sections: &'l [Section<u64>],
 }
 
 impl<'l> RenderedLine<'l> {
     fn new(sectioned: &'l SectionedLine<u64>, hl_groups: &'l HighlightGroups) -> Self {
         Self {
-            text: &sectioned.text,
+            line: sectioned,
             sections: &sectioned.sections,
             hl_groups,
         }
     }
 
     pub fn text(&self) -> &'l str {
-        self.text
+        &self.line.text
     }
 
     pub fn n_sections(&self) -> usize {
         self.sections.len()
     }
+
+    /// The underlying sectioned line, with the mapping between text and columns.
+    pub fn sectioned(&self) -> &'l SectionedLine<u64> {
+        self.line
+    }
 }
 
 impl<'l> Iterator for RenderedLine<'l> {
-    type Item = (&'l str, RgbAttr);
+    type Item = RenderedSection<'l>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some((next, rem)) = self.sections.split_first() {
             self.sections = rem;
 
-            let content = &self.text[next.start..next.end];
-            let rgb_attr = self.hl_groups.group_color_set(next.hl);
-
-            Some((content, rgb_attr))
+            Some(RenderedSection {
+                text: &self.line.text[next.start..next.end],
+                rgb_attr: self.hl_groups.group_color_set(next.hl),
+                hl_id: next.hl,
+                ligatures: self.hl_groups.ligatures(next.hl),
+                start: next.start,
+                col: next.col,
+                width: next.width,
+            })
         } else {
             None
         }
     }
 }
+
+/// A slice of a line with the same highlight, ready to be drawn.
+#[derive(Debug, Copy, Clone)]
+pub struct RenderedSection<'l> {
+    /// The text of the section.
+    pub text: &'l str,
+    /// The final colors and styles of the section.
+    pub rgb_attr: RgbAttr,
+    /// The highlight group of the section.
+    pub hl_id: u64,
+    /// Can the section be drawn with ligatures?
+    pub ligatures: bool,
+    /// The byte offset of the section in the line text.
+    pub start: usize,
+    /// The first grid column covered by the section.
+    pub col: usize,
+    /// Number of grid columns covered by the section.
+    pub width: usize,
+}
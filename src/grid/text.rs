@@ -0,0 +1,312 @@
+//! # Grid Text Extraction
+//!
+//! Plain text of ranges of grid cells, for copying selections, searching the
+//! screen and assistive tools.
+//!
+//! Rows are joined with `'\n'` and their trailing whitespace is removed. Double-width
+//! cells are never split: a range that covers any of their halves contains their
+//! full text.
+use super::Lines;
+
+/// A range of cells of a grid, with positions as `(row, col)`.
+///
+/// Both ends of the range are inclusive and may be given in any order. Positions
+/// outside of the grid are clipped to it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CellRange {
+    /// All cells between `start` and `end` in reading order, like a text selection.
+    Linear {
+        start: (usize, usize),
+        end: (usize, usize),
+    },
+    /// The rectangle with corners at `start` and `end`, like a block selection.
+    Block {
+        start: (usize, usize),
+        end: (usize, usize),
+    },
+}
+
+/// The text of a [`CellRange`] of a grid.
+#[derive(Debug, Default, Clone)]
+pub struct GridText {
+    text: String,
+    rows: Vec<TextRow>,
+}
+
+/// The part of the text that came from a single grid row.
+#[derive(Debug, Clone)]
+struct TextRow {
+    row: usize,
+    /// Byte offset of the start of the row in the text.
+    start: usize,
+    /// First grid column of the row in the text.
+    first_col: usize,
+    /// Byte offset, relative to `start`, of the cell of each column, starting at
+    /// `first_col`. Columns of trimmed whitespace aren't included.
+    columns: Vec<usize>,
+}
+
+impl GridText {
+    pub(super) fn extract(lines: &Lines, range: CellRange) -> Self {
+        let mut text = Self::default();
+
+        if lines.rows == 0 || lines.cols == 0 {
+            return text;
+        }
+
+        let last_col = lines.cols - 1;
+        let clip = |(row, col): (usize, usize)| (row.min(lines.rows - 1), col.min(last_col));
+
+        match range {
+            CellRange::Linear { start, end } => {
+                let (start, end) = (clip(start.min(end)), clip(start.max(end)));
+
+                for row in start.0..=end.0 {
+                    let first = if row == start.0 { start.1 } else { 0 };
+                    let last = if row == end.0 { end.1 } else { last_col };
+
+                    text.push_row(lines, row, first, last);
+                }
+            }
+            CellRange::Block { start, end } => {
+                let (top, left) = clip((start.0.min(end.0), start.1.min(end.1)));
+                let (bottom, right) = clip((start.0.max(end.0), start.1.max(end.1)));
+
+                for row in top..=bottom {
+                    text.push_row(lines, row, left, right);
+                }
+            }
+        }
+
+        text
+    }
+
+    /// The extracted text.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// The grid position, as `(row, col)`, of the byte `offset` of the text.
+    ///
+    /// Offsets inside a double-width cell map to its left half. The offset of a
+    /// line break maps to the column right after the text of its row.
+    pub fn position_at(&self, offset: usize) -> Option<(usize, usize)> {
+        if offset > self.text.len() {
+            return None;
+        }
+
+        let idx = match self.rows.binary_search_by_key(&offset, |row| row.start) {
+            Ok(idx) => idx,
+            Err(idx) => idx.checked_sub(1)?,
+        };
+        let row = &self.rows[idx];
+        let offset = offset - row.start;
+
+        if offset >= self.row_len(idx) {
+            return Some((row.row, row.first_col + row.columns.len()));
+        }
+
+        // The cell containing `offset` is the last one starting at or before it,
+        // and its first column is the left half of double-width cells.
+        let cell_start = row.columns.iter().rev().find(|start| **start <= offset)?;
+        let col = row.columns.iter().position(|start| start == cell_start)?;
+
+        Some((row.row, row.first_col + col))
+    }
+
+    fn row_len(&self, idx: usize) -> usize {
+        let end = match self.rows.get(idx + 1) {
+            // Don't count the line break.
+            Some(next) => next.start - 1,
+            None => self.text.len(),
+        };
+
+        end - self.rows[idx].start
+    }
+
+    fn push_row(&mut self, lines: &Lines, row: usize, mut first: usize, last: usize) {
+        let line = &lines.cells[row * lines.cols..(row + 1) * lines.cols];
+
+        // Don't start in the middle of a double-width cell.
+        while first > 0 && line[first].is_continuation() {
+            first -= 1;
+        }
+
+        if !self.rows.is_empty() {
+            self.text.push('\n');
+        }
+
+        let start = self.text.len();
+        let mut columns = Vec::with_capacity(last + 1 - first);
+
+        for (col, cell) in line[first..=last].iter().enumerate() {
+            if cell.is_continuation() && col != 0 {
+                columns.push(columns[col - 1]);
+                continue;
+            }
+
+            columns.push(self.text.len() - start);
+
+            if cell.is_continuation() {
+                // An orphan right half, as in `lines::render`.
+                self.text.push(' ');
+            } else {
+                cell.push_text(&lines.graphemes, &mut self.text);
+            }
+        }
+
+        let trimmed = self.text[start..].trim_end().len();
+        self.text.truncate(start + trimmed);
+        columns.retain(|offset| *offset < trimmed);
+
+        self.rows.push(TextRow {
+            row,
+            start,
+            first_col: first,
+            columns,
+        });
+    }
+}
+
+impl AsRef<str> for GridText {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<GridText> for String {
+    fn from(text: GridText) -> Self {
+        text.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neovim::{GridCell, GridLine};
+    use crate::width::WidthOptions;
+    use bumpalo::{collections::Vec as BumpVec, Bump};
+
+    /// A grid with a row for each list of cells. The right halves of double-width
+    /// cells are empty strings, as sent by Neovim.
+    fn grid(rows: &[&[&str]]) -> Lines {
+        let arena = Bump::new();
+        let mut lines = Lines::default();
+        lines.resize(rows.len(), rows[0].len());
+
+        for (row, cells) in rows.iter().enumerate() {
+            let mut grid_cells = BumpVec::new_in(&arena);
+            grid_cells.extend(cells.iter().map(|text| GridCell {
+                text,
+                hl_id: 0,
+                repeated: 1,
+            }));
+
+            lines.update_line(GridLine {
+                grid: 1,
+                row: row as u64,
+                col_start: 0,
+                cells: grid_cells,
+            });
+        }
+
+        lines
+    }
+
+    fn linear(start: (usize, usize), end: (usize, usize)) -> CellRange {
+        CellRange::Linear { start, end }
+    }
+
+    #[test]
+    fn wide_cells_are_never_split() {
+        let lines = grid(&[&["a", "世", "", "界", "", "b"]]);
+
+        // Starting at a right half includes the left half.
+        let text = lines.text(linear((0, 2), (0, 3)));
+        assert_eq!(text.as_str(), "世界");
+
+        let text = lines.text(linear((0, 0), (0, 5)));
+        assert_eq!(text.as_str(), "a世界b");
+        assert_eq!(text.position_at(0), Some((0, 0)));
+        assert_eq!(text.position_at(1), Some((0, 1)));
+        // Inside the bytes of `世`.
+        assert_eq!(text.position_at(2), Some((0, 1)));
+        assert_eq!(text.position_at(4), Some((0, 3)));
+        assert_eq!(text.position_at(7), Some((0, 5)));
+        assert_eq!(text.position_at(8), Some((0, 6)));
+        assert_eq!(text.position_at(9), None);
+    }
+
+    #[test]
+    fn combining_marks_are_a_single_cell() {
+        let lines = grid(&[&["e\u{301}", "x", " "]]);
+        let text = lines.text(linear((0, 0), (0, 2)));
+
+        assert_eq!(text.as_str(), "e\u{301}x");
+        assert_eq!(text.position_at(1), Some((0, 0)));
+        assert_eq!(text.position_at(3), Some((0, 1)));
+    }
+
+    #[test]
+    fn linear_ranges_span_rows() {
+        let lines = grid(&[
+            &["a", "b", "c", " "],
+            &["d", " ", " ", " "],
+            &["g", "h", "i", "j"],
+        ]);
+
+        // The ends may come in any order.
+        let text = lines.text(linear((2, 1), (0, 1)));
+        assert_eq!(text.as_str(), "bc\nd\ngh");
+        assert_eq!(text.position_at(0), Some((0, 1)));
+        // The line breaks map to the column after the text of their rows.
+        assert_eq!(text.position_at(2), Some((0, 3)));
+        assert_eq!(text.position_at(4), Some((1, 1)));
+        assert_eq!(text.position_at(5), Some((2, 0)));
+        assert_eq!(text.position_at(6), Some((2, 1)));
+    }
+
+    #[test]
+    fn block_ranges_take_the_same_columns_of_each_row() {
+        let lines = grid(&[
+            &["a", "b", "c", "d"],
+            &["世", "", "f", "g"],
+            &["h", "i", "j", "k"],
+        ]);
+
+        let text = lines.text(CellRange::Block {
+            start: (2, 2),
+            end: (0, 1),
+        });
+        assert_eq!(text.as_str(), "bc\n世f\nij");
+        assert_eq!(text.position_at(3), Some((1, 0)));
+        assert_eq!(text.position_at(6), Some((1, 2)));
+    }
+
+    #[test]
+    fn ranges_are_clipped_to_the_grid() {
+        let lines = grid(&[&["a", "b"], &["c", "d"]]);
+
+        assert_eq!(lines.text(linear((0, 1), (9, 9))).as_str(), "b\ncd");
+        assert_eq!(Lines::default().text(linear((0, 0), (1, 1))).as_str(), "");
+    }
+
+    #[test]
+    fn links_use_the_columns_of_wide_cells() {
+        let lines = grid(&[&["世", "", " ", "a", "/", "b", ":", "3", " "]]);
+        let links = lines.links(0, &WidthOptions::default());
+
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].row, 0);
+        assert_eq!(links[0].cols, 3..8);
+    }
+
+    #[test]
+    fn links_measure_wide_text() {
+        let lines = grid(&[&["a", "/", "世", "", ":", "3", " "]]);
+        let links = lines.links(0, &WidthOptions::default());
+
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].cols, 0..6);
+    }
+}
@@ -0,0 +1,264 @@
+//! # `guifont` Parsing
+//!
+//! Parses the `guifont` and `guifontwide` options, as described in `:h 'guifont'`:
+//!
+//! ```text
+//! set guifont=Fira\ Code:h10.5,Noto_Color_Emoji:h10:b,*
+//! ```
+//!
+//! Fonts are separated by commas and form a fallback chain, in order. Commas and spaces
+//! in a name are escaped with a backslash, and `_` can be used instead of a space. Each
+//! name may be followed by options, separated by colons:
+//!
+//! * `:h{size}`, the font height in points, may be fractional.
+//! * `:w{size}`, the cell width in points, may be fractional.
+//! * `:b` and `:i`, for bold and italic text.
+//!
+//! The underline (`:u`), strikeout (`:s`), character set (`:cANSI`) and quality (`:q`)
+//! options are accepted, but ignored: they only apply to the fonts of Windows.
+//!
+//! A `*` asks for a font picker.
+
+/// The parsed value of a `guifont` option.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GuiFont {
+    /// The option is empty, the default font should be used.
+    Default,
+    /// `guifont=*`, the user wants to pick a font.
+    Picker,
+    /// The fonts of the option, in fallback order.
+    Fonts(Vec<FontSpec>),
+}
+
+/// A font of the `guifont` option, with its options.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FontSpec {
+    /// The family name, unescaped.
+    pub family: String,
+    /// The height of the font, in points.
+    pub height: Option<f32>,
+    /// The width of the cells, in points.
+    pub width: Option<f32>,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+impl GuiFont {
+    /// Parse the value of the option.
+    ///
+    /// Invalid options are ignored, with a warning.
+    pub fn parse(value: &str) -> Self {
+        let mut fonts = Vec::new();
+
+        for entry in split_unescaped(value, ',') {
+            // Spaces after a comma are ignored.
+            let entry = entry.trim_start();
+
+            if entry == "*" {
+                // The picker only makes sense when no other font was found first.
+                if fonts.is_empty() {
+                    return Self::Picker;
+                }
+                continue;
+            }
+
+            if let Some(spec) = FontSpec::parse(entry) {
+                fonts.push(spec);
+            }
+        }
+
+        if fonts.is_empty() {
+            Self::Default
+        } else {
+            Self::Fonts(fonts)
+        }
+    }
+}
+
+impl FontSpec {
+    /// Parse a single entry of the option, already split on the commas.
+    fn parse(entry: &str) -> Option<Self> {
+        let mut parts = split_unescaped(entry, ':');
+        let family = unescape_name(parts.next()?);
+
+        if family.is_empty() {
+            log::warn!("Ignoring guifont entry without a font name: {:?}", entry);
+            return None;
+        }
+
+        let mut spec = Self {
+            family,
+            ..Self::default()
+        };
+
+        for option in parts {
+            let mut chars = option.chars();
+            let kind = chars.next();
+            let arg = chars.as_str();
+
+            match kind {
+                Some('h') => spec.height = parse_size(arg, option),
+                Some('w') => spec.width = parse_size(arg, option),
+                Some('b') => spec.bold = true,
+                Some('i') => spec.italic = true,
+                // Only meaningful for the fonts of Windows.
+                Some('u') | Some('s') => {}
+                Some('c') if !arg.is_empty() => {}
+                // Antialiasing quality, we always use the best.
+                Some('q') => {}
+                _ => log::warn!(
+                    "Ignoring invalid guifont option {:?} in {:?}",
+                    option,
+                    entry
+                ),
+            }
+        }
+
+        Some(spec)
+    }
+}
+
+fn parse_size(arg: &str, option: &str) -> Option<f32> {
+    match arg.parse::<f32>() {
+        Ok(size) if size.is_finite() && size > 0.0 => Some(size),
+        _ => {
+            log::warn!("Ignoring invalid guifont size {:?}", option);
+            None
+        }
+    }
+}
+
+/// Split `value` on the occurrences of `separator` that aren't escaped.
+///
+/// The escapes are kept in the parts.
+fn split_unescaped(value: &str, separator: char) -> impl Iterator<Item = &str> {
+    let mut rest = Some(value);
+
+    std::iter::from_fn(move || {
+        let current = rest?;
+        let mut escaped = false;
+
+        for (idx, chr) in current.char_indices() {
+            if escaped {
+                escaped = false;
+            } else if chr == '\\' {
+                escaped = true;
+            } else if chr == separator {
+                rest = Some(&current[idx + chr.len_utf8()..]);
+                return Some(&current[..idx]);
+            }
+        }
+
+        rest = None;
+        Some(current)
+    })
+}
+
+/// Remove the escapes of a font name, replacing `_` with spaces.
+fn unescape_name(name: &str) -> String {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+
+    while let Some(chr) = chars.next() {
+        match chr {
+            '\\' => unescaped.extend(chars.next()),
+            '_' => unescaped.push(' '),
+            chr => unescaped.push(chr),
+        }
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl GuiFont {
+        /// The fonts of the option, in fallback order.
+        fn fonts(&self) -> &[FontSpec] {
+            match self {
+                Self::Fonts(fonts) => fonts,
+                Self::Default | Self::Picker => &[],
+            }
+        }
+    }
+
+    fn family(family: &str) -> FontSpec {
+        FontSpec {
+            family: String::from(family),
+            ..FontSpec::default()
+        }
+    }
+
+    #[test]
+    fn empty_options_use_the_default_font() {
+        assert_eq!(GuiFont::parse(""), GuiFont::Default);
+        assert_eq!(GuiFont::parse(":h12"), GuiFont::Default);
+        assert!(GuiFont::parse("").fonts().is_empty());
+    }
+
+    #[test]
+    fn fonts_are_split_on_unescaped_commas() {
+        let guifont = GuiFont::parse("Fira Code, Noto\\,Sans,Mono");
+
+        assert_eq!(
+            guifont.fonts(),
+            [family("Fira Code"), family("Noto,Sans"), family("Mono")]
+        );
+    }
+
+    #[test]
+    fn spaces_can_be_escaped_or_underscores() {
+        assert_eq!(
+            GuiFont::parse("Fira\\ Code,Noto_Color_Emoji").fonts(),
+            [family("Fira Code"), family("Noto Color Emoji")]
+        );
+    }
+
+    #[test]
+    fn sizes_may_be_fractional() {
+        let guifont = GuiFont::parse("Mono:h10.5:w7");
+        let spec = &guifont.fonts()[0];
+
+        assert_eq!(spec.height, Some(10.5));
+        assert_eq!(spec.width, Some(7.0));
+    }
+
+    #[test]
+    fn invalid_sizes_are_ignored() {
+        let guifont = GuiFont::parse("Mono:h:w-1:hbig");
+        let spec = &guifont.fonts()[0];
+
+        assert_eq!(spec.height, None);
+        assert_eq!(spec.width, None);
+    }
+
+    #[test]
+    fn style_options() {
+        let guifont = GuiFont::parse("Mono:b:i:u:s:cANSI:qDRAFT");
+        let spec = &guifont.fonts()[0];
+
+        assert!(spec.bold);
+        assert!(spec.italic);
+        assert_eq!(spec.family, "Mono");
+    }
+
+    #[test]
+    fn escaped_colons_are_part_of_the_name() {
+        assert_eq!(
+            GuiFont::parse("Weird\\:Font:h9").fonts(),
+            [FontSpec {
+                height: Some(9.0),
+                ..family("Weird:Font")
+            }]
+        );
+    }
+
+    #[test]
+    fn star_asks_for_a_picker_only_without_fonts_before_it() {
+        assert_eq!(GuiFont::parse("*"), GuiFont::Picker);
+        assert_eq!(GuiFont::parse("*,Mono"), GuiFont::Picker);
+        assert_eq!(GuiFont::parse("Mono,*").fonts(), [family("Mono")]);
+    }
+}
@@ -0,0 +1,15 @@
+#![feature(const_fn, never_type, slice_fill, str_split_once)]
+
+mod color;
+mod compositor;
+mod cursor;
+pub mod editor;
+mod grid;
+mod guifont;
+mod ligatures;
+pub mod neovim;
+mod opener;
+pub mod snapshot;
+pub mod tui;
+pub mod ui;
+mod width;
@@ -0,0 +1,114 @@
+//! # Ligature Settings
+//!
+//! Which text is drawn with ligatures, configured in the environment:
+//!
+//! * `WEOVIM_LIGATURES`, set to `0`, `false` or `off` to disable ligatures everywhere.
+//! * `WEOVIM_NO_LIGATURES_HL`, comma separated highlight groups drawn without
+//!   ligatures, like `String,Comment`.
+//! * `WEOVIM_DISABLED_FONT_FEATURES`, comma separated tags of other font features to
+//!   disable, like `calt,ss01`.
+//!
+//! The editor marks the highlight groups drawn without ligatures, and the shaper of the
+//! GPU frontend disables the font features.
+
+const LIGATURES_ENV_VAR: &str = "WEOVIM_LIGATURES";
+const NO_LIGATURES_HL_ENV_VAR: &str = "WEOVIM_NO_LIGATURES_HL";
+const DISABLED_FEATURES_ENV_VAR: &str = "WEOVIM_DISABLED_FONT_FEATURES";
+
+/// The ligature settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LigatureConfig {
+    /// Are ligatures drawn at all?
+    pub enabled: bool,
+    /// Highlight groups whose text is drawn without ligatures.
+    pub no_ligatures_hl: Vec<String>,
+    /// Tags of the font features to disable.
+    pub disabled_features: Vec<String>,
+}
+
+impl Default for LigatureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            no_ligatures_hl: Vec::new(),
+            disabled_features: Vec::new(),
+        }
+    }
+}
+
+impl LigatureConfig {
+    /// The settings configured in the environment.
+    pub fn from_env() -> Self {
+        let var = |name| std::env::var(name).ok();
+
+        Self::from_vars(
+            var(LIGATURES_ENV_VAR).as_deref(),
+            var(NO_LIGATURES_HL_ENV_VAR).as_deref(),
+            var(DISABLED_FEATURES_ENV_VAR).as_deref(),
+        )
+    }
+
+    fn from_vars(
+        ligatures: Option<&str>,
+        no_ligatures_hl: Option<&str>,
+        disabled_features: Option<&str>,
+    ) -> Self {
+        Self {
+            enabled: ligatures.is_none_or(|value| !matches!(value.trim(), "0" | "false" | "off")),
+            no_ligatures_hl: split_list(no_ligatures_hl),
+            disabled_features: split_list(disabled_features),
+        }
+    }
+
+    /// Can text highlighted with the groups `names` be drawn with ligatures?
+    pub fn allowed_for<'n>(&self, names: impl IntoIterator<Item = &'n str>) -> bool {
+        self.enabled
+            && !names
+                .into_iter()
+                .any(|name| self.no_ligatures_hl.iter().any(|group| group == name))
+    }
+}
+
+/// The items of a comma separated `list`, without blanks.
+fn split_list(list: Option<&str>) -> Vec<String> {
+    list.unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ligatures_are_enabled_by_default() {
+        let config = LigatureConfig::from_vars(None, None, None);
+
+        assert_eq!(config, LigatureConfig::default());
+        assert!(config.allowed_for(vec!["String"]));
+    }
+
+    #[test]
+    fn ligatures_can_be_disabled() {
+        for value in &["0", "false", " off "] {
+            let config = LigatureConfig::from_vars(Some(value), None, None);
+            assert!(!config.enabled, "{:?}", value);
+            assert!(!config.allowed_for(vec![]));
+        }
+
+        assert!(LigatureConfig::from_vars(Some("1"), None, None).enabled);
+    }
+
+    #[test]
+    fn lists_are_comma_separated() {
+        let config = LigatureConfig::from_vars(None, Some("String, Comment,,"), Some("calt,ss01"));
+
+        assert_eq!(config.no_ligatures_hl, ["String", "Comment"]);
+        assert_eq!(config.disabled_features, ["calt", "ss01"]);
+        assert!(!config.allowed_for(vec!["Normal", "Comment"]));
+        assert!(config.allowed_for(vec!["Normal"]));
+    }
+}
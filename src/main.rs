@@ -1,19 +1,42 @@
-#![feature(const_fn, never_type, slice_fill, str_split_once)]
-
-use self::editor::Editor;
-use self::neovim::UiOptions;
 use mimalloc::MiMalloc;
-
-mod color;
-mod cursor;
-mod editor;
-mod grid;
-mod neovim;
-mod ui;
+use std::fs::File;
+use std::sync::Arc;
+use weovim::editor::Editor;
+use weovim::neovim::{self, UiOptions};
+use weovim::{tui, ui};
 
 #[global_allocator]
 static GLOBAL_ALLOCATOR: MiMalloc = MiMalloc;
 
+/// Path of a file to write the logs to, instead of stderr.
+const LOG_FILE_ENV_VAR: &str = "WEOVIM_LOG_FILE";
+
+/// Log to the file in `WEOVIM_LOG_FILE`, or to stderr.
+///
+/// The TUI draws to the terminal stderr is usually attached to, so it only logs to
+/// a file.
+fn init_logger(tui: bool) {
+    let mut builder = env_logger::Builder::from_default_env();
+
+    match std::env::var_os(LOG_FILE_ENV_VAR) {
+        Some(path) => match File::create(&path) {
+            Ok(file) => {
+                builder.target(env_logger::Target::Pipe(Box::new(file)));
+            }
+            Err(error) => {
+                if !tui {
+                    eprintln!("Failed to create log file {:?}: {}", path, error);
+                }
+                return;
+            }
+        },
+        None if tui => return,
+        None => {}
+    }
+
+    builder.init();
+}
+
 fn start_runtime() -> std::io::Result<tokio::runtime::Runtime> {
     tokio::runtime::Builder::new_multi_thread()
         .worker_threads(2)
@@ -23,32 +46,62 @@ fn start_runtime() -> std::io::Result<tokio::runtime::Runtime> {
 }
 
 fn main() -> std::io::Result<()> {
-    env_logger::init();
+    let tui = std::env::args().any(|arg| arg == "--tui");
+    init_logger(tui);
 
     let runtime = start_runtime()?;
     let _rt_guard = runtime.enter();
 
-    let (editor, mut ui_state) = Editor::new();
-    let (mut neovim, recv) = neovim::Neovim::start(editor)?;
+    let (editor, ui_state) = Editor::new();
+    let (neovim, recv) = neovim::Neovim::start(editor)?;
+
+    if tui {
+        return runtime.block_on(tui::run(neovim, recv, ui_state));
+    }
 
+    // Both the UI and the attach task below send requests to Neovim.
+    let neovim = Arc::new(tokio::sync::Mutex::new(neovim));
+    let (_, event_loop) = runtime.block_on(ui::Ui::new(
+        neovim.clone(),
+        ui_state.reader,
+        ui_state.shared,
+    ));
+    let proxy = event_loop.proxy();
+
+    let mut editor_events = ui_state.recv;
     runtime.spawn(async move {
-        while let Some(ev) = ui_state.recv.recv().await {
-            log::info!("Received UiEditorEvent: {:?}", ev)
+        while let Some(ev) = editor_events.recv().await {
+            log::debug!("Received UiEditorEvent: {:?}", ev);
+
+            if proxy.send_event(ev).is_err() {
+                break;
+            }
         }
     });
 
-    let (_, event_loop) = runtime.block_on(ui::Ui::new());
+    runtime.spawn(async move {
+        if let Err(error) = recv.run_loop().await {
+            log::error!("Error in neovim event loop: {}", error);
+            std::process::exit(1);
+        }
+    });
 
+    // Defining the commands waits for a response, read by the event loop above.
     runtime.spawn(async move {
+        let mut neovim = neovim.lock().await;
         neovim
-            .ui_attach(80, 30, UiOptions::RGB | UiOptions::EXT_LINEGRID)
+            .ui_attach(
+                80,
+                30,
+                // `ext_hlstate` gives us the names of the highlight groups.
+                UiOptions::RGB | UiOptions::EXT_MULTIGRID | UiOptions::EXT_HLSTATE,
+            )
             .await
             .expect("failed to attach to UI");
         log::info!("UI attached");
 
-        if let Err(error) = recv.run_loop().await {
-            log::error!("Error in neovim event loop: {}", error);
-            std::process::exit(1);
+        if let Err(error) = neovim.define_commands().await {
+            log::error!("Failed to define the GUI commands: {}", error);
         }
     });
 
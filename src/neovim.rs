@@ -3,6 +3,8 @@ pub use self::api::UiOptions;
 #[doc(inline)]
 pub use self::events::*;
 #[doc(inline)]
+pub use self::record::Recording;
+#[doc(inline)]
 pub use self::rpc::{EventListener, LoggerEventListener};
 use self::rpc::{EventReceiver, RpcProcess};
 use std::sync::{
@@ -13,6 +15,7 @@ use std::sync::{
 mod api;
 mod events;
 pub(self) mod msg;
+mod record;
 mod rpc;
 
 /// A Neovim session instance.
@@ -102,4 +105,8 @@ impl<L: EventListener> EventListener for NeovimEventListener<L> {
             event => self.listener.on_redraw_event(event),
         }
     }
+
+    fn on_zoom(&mut self, zoom: Zoom) {
+        self.listener.on_zoom(zoom)
+    }
 }
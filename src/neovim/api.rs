@@ -1,4 +1,4 @@
-use super::Neovim;
+use super::{msg, Neovim, Zoom};
 use std::io;
 
 bitflags::bitflags! {
@@ -47,7 +47,19 @@ impl Neovim {
     /// - `width`: Requested screen columns
     /// - `height`: Requested screen rows
     /// - `options`: `UiOptions` to use for this client.
-    pub async fn ui_attach(&mut self, width: u64, height: u64, opts: UiOptions) -> io::Result<()> {
+    ///
+    /// `UiOptions::EXT_MULTIGRID` depends on the line based grid events, thus requesting it
+    /// also requests `UiOptions::EXT_LINEGRID`.
+    pub async fn ui_attach(
+        &mut self,
+        width: u64,
+        height: u64,
+        mut opts: UiOptions,
+    ) -> io::Result<()> {
+        if opts.contains(UiOptions::EXT_MULTIGRID) {
+            opts |= UiOptions::EXT_LINEGRID;
+        }
+
         log::debug!(
             "nvim_ui_attach width={}, height={}, opts={:?}",
             width,
@@ -104,3 +116,127 @@ impl Neovim {
         rpc.send().await
     }
 }
+
+// Input RPC methods
+impl Neovim {
+    /// Queues raw user-input. Unlike `nvim_feedkeys`, this uses a low-level input buffer
+    /// and the call is non-blocking (input is processed asynchronously by the eventloop).
+    ///
+    /// ### Parameters:
+    ///
+    /// - `keys`: to be typed, using the key notation, e.g. `<C-o>`. A literal `<` must be
+    ///   sent as `<lt>`.
+    pub async fn input(&mut self, keys: &str) -> io::Result<()> {
+        let mut rpc = self.rpc.rpc_method_forget("nvim_input", 1);
+
+        rpc.add_str_arg(keys);
+
+        rpc.send().await
+    }
+
+    /// Send mouse event from GUI. Non-blocking: does not wait on any result, but queues
+    /// the event to be processed soon by the event loop.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `button`: Mouse button: one of "left", "right", "middle", "wheel".
+    /// - `action`: For ordinary buttons, one of "press", "drag", "release". For the wheel,
+    ///   one of "up", "down", "left", "right".
+    /// - `modifier`: String of modifiers each represented by a single char. The same
+    ///   specifiers are used as for a key press, e.g. "C-A-".
+    /// - `grid`: Grid number if the client uses `ext_multigrid`, else 0.
+    /// - `row`: Mouse row-position (zero-based, like redraw events)
+    /// - `col`: Mouse column-position (zero-based, like redraw events)
+    pub async fn input_mouse(
+        &mut self,
+        button: &str,
+        action: &str,
+        modifier: &str,
+        grid: u64,
+        row: u64,
+        col: u64,
+    ) -> io::Result<()> {
+        let mut rpc = self.rpc.rpc_method_forget("nvim_input_mouse", 6);
+
+        rpc.add_str_arg(button);
+        rpc.add_str_arg(action);
+        rpc.add_str_arg(modifier);
+        rpc.add_u64_arg(grid);
+        rpc.add_u64_arg(row);
+        rpc.add_u64_arg(col);
+
+        rpc.send().await
+    }
+}
+
+// Editing RPC methods
+impl Neovim {
+    /// Executes an Ex command.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `command`: Ex command string, without the leading `:`.
+    pub async fn command(&mut self, command: &str) -> io::Result<()> {
+        let mut rpc = self.rpc.rpc_method_forget("nvim_command", 1);
+
+        rpc.add_str_arg(command);
+
+        rpc.send().await
+    }
+}
+
+// GUI commands
+impl Neovim {
+    /// The id of the channel Neovim uses to talk to us.
+    ///
+    /// Waits for the response of Neovim, the event loop must be running.
+    pub async fn channel_id(&mut self) -> io::Result<u64> {
+        let result = self.rpc.rpc_method("nvim_get_api_info", 0).call().await?;
+        let mut raw = result.as_slice();
+
+        // The channel id and the API metadata.
+        msg::read_array_len(&mut raw)?;
+        msg::read_u64(&mut raw)
+    }
+
+    /// Executes a multi-line Vimscript `script`, discarding its output.
+    pub async fn exec(&mut self, script: &str) -> io::Result<()> {
+        let mut rpc = self.rpc.rpc_method_forget("nvim_exec", 2);
+
+        rpc.add_str_arg(script);
+        rpc.add_bool_arg(false);
+
+        rpc.send().await
+    }
+
+    /// Define the commands that control the GUI, like `:WeovimZoom`.
+    ///
+    /// Their requests are delivered to the [`EventListener`](super::EventListener). As
+    /// they are sent to our channel only, the event loop must be running to get its id.
+    pub async fn define_commands(&mut self) -> io::Result<()> {
+        let channel = self.channel_id().await?;
+
+        // `:WeovimZoom in`, `:WeovimZoom out` and `:WeovimZoom reset`.
+        self.exec(&format!(
+            r#"
+            function! WeovimZoomComplete(arg, line, pos) abort
+                return filter(['in', 'out', 'reset'], 'v:val =~# "^" . a:arg')
+            endfunction
+
+            function! WeovimZoom(step) abort
+                if index(['in', 'out', 'reset'], a:step) < 0
+                    echoerr 'WeovimZoom: expected in, out or reset, got ' . a:step
+                    return
+                endif
+                call rpcnotify({channel}, '{notification}', a:step)
+            endfunction
+
+            command! -nargs=1 -complete=customlist,WeovimZoomComplete WeovimZoom
+                \ call WeovimZoom(<q-args>)
+            "#,
+            channel = channel,
+            notification = Zoom::NOTIFICATION,
+        ))
+        .await
+    }
+}
@@ -82,7 +82,7 @@ pub enum RedrawEvent<'a> {
     /// Add a new highlight group to the highlight table.
     ///
     /// See [`HighlightAttr`] for more info.
-    HlAttrDefine(HighlightAttr),
+    HlAttrDefine(HighlightAttr<'a>),
     /// The builtin highlight group `name` was set to use the attributes `hl_id`
     /// defined by a previous `hl_attr_define` call.
     ///
@@ -135,10 +135,12 @@ pub enum RedrawEvent<'a> {
         /// The window that should be shown.
         win: WinNr,
     },
-    /// Stop displaying the window.
-    WinHide(WinNr),
-    /// Close the window.
-    WinClose(WinNr),
+    /// Stop displaying the window of a grid.
+    ///
+    /// The window will be shown again on the next `WinPos` or `WinFloatPos` event.
+    WinHide(u64),
+    /// Close the window of a grid.
+    WinClose(u64),
     /// Display messages on a grid.
     ///
     /// When `UiOptions::MESSAGES` is active, no message grid is used, and this event
@@ -245,11 +247,16 @@ pub struct DefaultColorSet {
 /// are affected by redefined ids, so UIs do not need to keep track of this
 /// themselves.
 #[derive(Debug)]
-pub struct HighlightAttr {
+pub struct HighlightAttr<'a> {
     /// Index of the highlight group.
     pub id: u64,
     /// RGB properties of the group.
     pub rgb_attr: RgbAttr,
+    /// Names of the highlight groups that make up this one, like `Comment` or
+    /// `CursorLine`.
+    ///
+    /// Only sent with `ext_hlstate`.
+    pub names: Vec<'a, &'a str>,
 }
 
 /// RGB properties of a highlight group.
@@ -310,6 +317,38 @@ impl RgbAttr {
         std::mem::swap(&mut copy.foreground, &mut copy.background);
         copy
     }
+
+    /// An attribute with the styles named like the keys of `hl_attr_define`, like
+    /// `bold` or `undercurl`.
+    #[cfg(test)]
+    pub(crate) fn with_styles(styles: &[&str]) -> Self {
+        let mut attr = Self::default();
+
+        for style in styles {
+            attr.flags.insert(match *style {
+                "reverse" => RgbAttrFlags::REVERSE,
+                "italic" => RgbAttrFlags::ITALIC,
+                "bold" => RgbAttrFlags::BOLD,
+                "strikethrough" => RgbAttrFlags::STRIKETHROUGH,
+                "underline" => RgbAttrFlags::UNDERLINE,
+                "undercurl" => RgbAttrFlags::UNDERCURL,
+                style => panic!("unknown style {:?}", style),
+            });
+        }
+
+        attr
+    }
+}
+
+impl From<DefaultColorSet> for RgbAttr {
+    fn from(default: DefaultColorSet) -> Self {
+        Self {
+            foreground: Some(default.foreground),
+            background: Some(default.background),
+            special: Some(default.special),
+            ..Self::default()
+        }
+    }
 }
 
 bitflags::bitflags! {
@@ -422,11 +461,19 @@ pub struct WinFloatPos {
     /// The grid to which the floating window should be anchored.
     pub anchor_grid: u64,
     /// The anchor row of the anchor grid.
-    pub anchor_row: u64,
+    ///
+    /// This may be fractional, when the floating window is positioned between cells.
+    pub anchor_row: f64,
     /// The anchor column of the anchor grid.
-    pub anchor_col: u64,
+    ///
+    /// This may be fractional, when the floating window is positioned between cells.
+    pub anchor_col: f64,
     /// Can the floating window be focused?
     pub focusable: bool,
+    /// Stacking order of the floating window, higher values are drawn on top.
+    ///
+    /// Only sent by newer Neovim versions.
+    pub zindex: Option<u64>,
 }
 
 /// Anchor modes of a floating window.
@@ -488,6 +535,36 @@ impl WinNr {
     }
 }
 
+/// A change of the font size, requested with the `:WeovimZoom` command.
+///
+/// Sent by Neovim as a `weovim_zoom` notification, with `in`, `out` or `reset` as
+/// its only argument.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Zoom {
+    /// Increase the font size.
+    In,
+    /// Decrease the font size.
+    Out,
+    /// Go back to the `guifont` size.
+    Reset,
+}
+
+impl Zoom {
+    /// Name of the notification sent by `:WeovimZoom`.
+    pub const NOTIFICATION: &'static str = "weovim_zoom";
+
+    pub(super) fn decode(raw: &mut &[u8]) -> io::Result<Self> {
+        msg::ensure_parameters_count(raw, 1)?;
+
+        match msg::read_string(raw)? {
+            "in" => Ok(Self::In),
+            "out" => Ok(Self::Out),
+            "reset" => Ok(Self::Reset),
+            _ => msg::err_invalid_input(),
+        }
+    }
+}
+
 impl<'a> RedrawEvent<'a> {
     pub(super) fn decode(raw: &mut &'a [u8], arena: &'a Bump) -> io::Result<Vec<'a, Self>> {
         let n_events = msg::read_array_len(raw)?;
@@ -571,7 +648,7 @@ impl<'a> RedrawEvent<'a> {
             }
             "hl_attr_define" => {
                 for _ in 0..n_events {
-                    events.push(Self::decode_hl_attr_define(raw)?);
+                    events.push(Self::decode_hl_attr_define(raw, arena)?);
                 }
             }
             "hl_group_set" => {
@@ -782,7 +859,7 @@ impl<'a> RedrawEvent<'a> {
         }))
     }
 
-    fn decode_hl_attr_define(raw: &mut &'a [u8]) -> io::Result<Self> {
+    fn decode_hl_attr_define(raw: &mut &'a [u8], arena: &'a Bump) -> io::Result<Self> {
         fn consume_attr<'b>(raw: &mut &'b [u8]) -> io::Result<RgbAttr> {
             let mut attr = RgbAttr::default();
 
@@ -816,7 +893,8 @@ impl<'a> RedrawEvent<'a> {
                         msg::read_bool(raw)?;
                         attr.flags.insert(RgbAttrFlags::UNDERCURL);
                     }
-                    _ => return msg::err_invalid_input(),
+                    // Attributes of newer versions, like `nocombine` or `url`.
+                    _ => msg::skip_value(raw)?,
                 }
             }
 
@@ -829,10 +907,34 @@ impl<'a> RedrawEvent<'a> {
         let rgb_attr = consume_attr(raw)?;
         let _cterm_attr = consume_attr(raw)?;
 
-        // consume info parameter.
-        msg::ensure_parameters_count(raw, 0)?;
+        let n_infos = msg::read_array_len(raw)?;
+        let mut names = Vec::with_capacity_in(n_infos, arena);
 
-        Ok(Self::HlAttrDefine(HighlightAttr { id, rgb_attr }))
+        for _ in 0..n_infos {
+            for _ in 0..msg::read_map_len(raw)? {
+                match msg::read_string(raw)? {
+                    "kind" => {
+                        msg::read_string(raw)?;
+                    }
+                    "id" => {
+                        msg::read_u64(raw)?;
+                    }
+                    "hi_name" | "ui_name" => {
+                        let name = msg::read_string(raw)?;
+                        if !name.is_empty() && !names.contains(&name) {
+                            names.push(name);
+                        }
+                    }
+                    _ => msg::skip_value(raw)?,
+                }
+            }
+        }
+
+        Ok(Self::HlAttrDefine(HighlightAttr {
+            id,
+            rgb_attr,
+            names,
+        }))
     }
 
     fn decode_hl_group_set(raw: &mut &'a [u8]) -> io::Result<Self> {
@@ -939,7 +1041,11 @@ impl<'a> RedrawEvent<'a> {
     }
 
     fn decode_win_float_pos(raw: &mut &'a [u8]) -> io::Result<Self> {
-        msg::ensure_parameters_count(raw, 7)?;
+        // Newer Neovim versions append more parameters, like `zindex`.
+        let n_params = msg::read_array_len(raw)?;
+        if n_params < 7 {
+            return msg::err_invalid_input();
+        }
 
         let mut win_float_pos = WinFloatPos::default();
         win_float_pos.grid = msg::read_u64(raw)?;
@@ -954,10 +1060,17 @@ impl<'a> RedrawEvent<'a> {
         };
 
         win_float_pos.anchor_grid = msg::read_u64(raw)?;
-        win_float_pos.anchor_row = msg::read_u64(raw)?;
-        win_float_pos.anchor_col = msg::read_u64(raw)?;
+        win_float_pos.anchor_row = msg::read_f64(raw)?;
+        win_float_pos.anchor_col = msg::read_f64(raw)?;
         win_float_pos.focusable = msg::read_bool(raw)?;
 
+        if n_params > 7 {
+            win_float_pos.zindex = Some(msg::read_u64(raw)?);
+        }
+        for _ in 8..n_params {
+            msg::skip_value(raw)?;
+        }
+
         Ok(Self::WinFloatPos(win_float_pos))
     }
 
@@ -973,13 +1086,13 @@ impl<'a> RedrawEvent<'a> {
     fn decode_win_hide(raw: &mut &'a [u8]) -> io::Result<Self> {
         msg::ensure_parameters_count(raw, 1)?;
 
-        Ok(Self::WinHide(WinNr::decode(raw)?))
+        Ok(Self::WinHide(msg::read_u64(raw)?))
     }
 
     fn decode_win_close(raw: &mut &'a [u8]) -> io::Result<Self> {
         msg::ensure_parameters_count(raw, 1)?;
 
-        Ok(Self::WinClose(WinNr::decode(raw)?))
+        Ok(Self::WinClose(msg::read_u64(raw)?))
     }
 
     fn decode_msg_set_pos(raw: &mut &'a [u8]) -> io::Result<Self> {
@@ -1012,3 +1125,134 @@ impl<'a> RedrawEvent<'a> {
         Ok(Self::WinViewPort(viewport))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmp::encode;
+
+    fn float_pos_batch(
+        extra: impl FnOnce(&mut std::vec::Vec<u8>),
+        n_params: u32,
+    ) -> std::vec::Vec<u8> {
+        let mut raw = std::vec::Vec::new();
+        encode::write_array_len(&mut raw, 2).unwrap();
+
+        encode::write_array_len(&mut raw, 2).unwrap();
+        encode::write_str(&mut raw, "win_float_pos").unwrap();
+        encode::write_array_len(&mut raw, n_params).unwrap();
+        encode::write_uint(&mut raw, 4).unwrap();
+        encode::write_ext_meta(&mut raw, 1, 1).unwrap();
+        raw.push(7);
+        encode::write_str(&mut raw, "SE").unwrap();
+        encode::write_uint(&mut raw, 2).unwrap();
+        encode::write_f64(&mut raw, 10.0).unwrap();
+        encode::write_f64(&mut raw, 20.5).unwrap();
+        encode::write_bool(&mut raw, false).unwrap();
+        extra(&mut raw);
+
+        encode::write_array_len(&mut raw, 2).unwrap();
+        encode::write_str(&mut raw, "flush").unwrap();
+        encode::write_array_len(&mut raw, 0).unwrap();
+
+        raw
+    }
+
+    fn decode_float_pos(raw: &[u8]) -> WinFloatPos {
+        let arena = Bump::new();
+        let mut raw = raw;
+        let events = RedrawEvent::decode(&mut raw, &arena).unwrap();
+
+        assert!(raw.is_empty());
+        assert!(matches!(events[1], RedrawEvent::Flush));
+        match events[0] {
+            RedrawEvent::WinFloatPos(float) => float,
+            ref event => panic!("expected win_float_pos, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn win_float_pos_without_zindex() {
+        let float = decode_float_pos(&float_pos_batch(|_| {}, 7));
+
+        assert_eq!(float.grid, 4);
+        assert_eq!(float.win.0, 7);
+        assert!(matches!(float.anchor, WinFloatAnchor::Southeast));
+        assert_eq!((float.anchor_grid, float.anchor_row, float.anchor_col), (2, 10.0, 20.5));
+        assert!(!float.focusable);
+        assert_eq!(float.zindex, None);
+    }
+
+    #[test]
+    fn win_float_pos_skips_newer_parameters() {
+        let raw = float_pos_batch(
+            |raw| {
+                // zindex, compindex, screen_row, screen_col and an unknown map.
+                encode::write_uint(raw, 50).unwrap();
+                encode::write_uint(raw, 3).unwrap();
+                encode::write_uint(raw, 12).unwrap();
+                encode::write_uint(raw, 30).unwrap();
+                encode::write_map_len(raw, 1).unwrap();
+                encode::write_str(raw, "future").unwrap();
+                encode::write_array_len(raw, 2).unwrap();
+                encode::write_str(raw, "nested").unwrap();
+                encode::write_f32(raw, 1.5).unwrap();
+            },
+            12,
+        );
+        let float = decode_float_pos(&raw);
+
+        assert_eq!(float.grid, 4);
+        assert_eq!(float.zindex, Some(50));
+    }
+
+    #[test]
+    fn hl_attr_define_skips_unknown_keys() {
+        let mut raw = std::vec::Vec::new();
+        encode::write_array_len(&mut raw, 2).unwrap();
+
+        encode::write_array_len(&mut raw, 2).unwrap();
+        encode::write_str(&mut raw, "hl_attr_define").unwrap();
+        encode::write_array_len(&mut raw, 4).unwrap();
+        encode::write_uint(&mut raw, 5).unwrap();
+        // rgb_attr
+        encode::write_map_len(&mut raw, 3).unwrap();
+        encode::write_str(&mut raw, "url").unwrap();
+        encode::write_str(&mut raw, "https://neovim.io").unwrap();
+        encode::write_str(&mut raw, "bold").unwrap();
+        encode::write_bool(&mut raw, true).unwrap();
+        encode::write_str(&mut raw, "nocombine").unwrap();
+        encode::write_bool(&mut raw, true).unwrap();
+        // cterm_attr
+        encode::write_map_len(&mut raw, 1).unwrap();
+        encode::write_str(&mut raw, "underdouble").unwrap();
+        encode::write_bool(&mut raw, true).unwrap();
+        // info
+        encode::write_array_len(&mut raw, 1).unwrap();
+        encode::write_map_len(&mut raw, 2).unwrap();
+        encode::write_str(&mut raw, "future").unwrap();
+        encode::write_array_len(&mut raw, 1).unwrap();
+        encode::write_f64(&mut raw, 0.5).unwrap();
+        encode::write_str(&mut raw, "hi_name").unwrap();
+        encode::write_str(&mut raw, "Error").unwrap();
+
+        encode::write_array_len(&mut raw, 2).unwrap();
+        encode::write_str(&mut raw, "flush").unwrap();
+        encode::write_array_len(&mut raw, 0).unwrap();
+
+        let arena = Bump::new();
+        let mut raw = raw.as_slice();
+        let events = RedrawEvent::decode(&mut raw, &arena).unwrap();
+
+        assert!(raw.is_empty());
+        assert!(matches!(events[1], RedrawEvent::Flush));
+        match &events[0] {
+            RedrawEvent::HlAttrDefine(attr) => {
+                assert_eq!(attr.id, 5);
+                assert!(attr.rgb_attr.flags.contains(RgbAttrFlags::BOLD));
+                assert_eq!(attr.names.as_slice(), ["Error"]);
+            }
+            event => panic!("expected hl_attr_define, got {:?}", event),
+        }
+    }
+}
@@ -26,8 +26,8 @@ pub fn read_string<'a>(raw: &mut &'a [u8]) -> io::Result<&'a str> {
 
 pub fn read_f64(raw: &mut &[u8]) -> io::Result<f64> {
     Ok(match read_marker(raw)? {
-        Marker::F32 => dec::read_f32(raw).map_err(value_read_error_to_io_error)? as f64,
-        Marker::F64 => dec::read_f64(raw).map_err(value_read_error_to_io_error)?,
+        Marker::F32 => dec::read_data_f32(raw).map_err(value_read_error_to_io_error)? as f64,
+        Marker::F64 => dec::read_data_f64(raw).map_err(value_read_error_to_io_error)?,
         _ => return err_invalid_input(),
     })
 }
@@ -52,6 +52,58 @@ pub fn read_ext_meta(raw: &mut &[u8]) -> io::Result<dec::ExtMeta> {
     dec::read_ext_meta(raw).map_err(value_read_error_to_io_error)
 }
 
+/// Skip a value of any type, including all the elements of arrays and maps.
+pub fn skip_value(raw: &mut &[u8]) -> io::Result<()> {
+    let read_len = |raw: &mut &[u8], bytes| -> io::Result<usize> {
+        Ok(match bytes {
+            1 => dec::read_data_u8(raw).map_err(value_read_error_to_io_error)? as usize,
+            2 => dec::read_data_u16(raw).map_err(value_read_error_to_io_error)? as usize,
+            _ => dec::read_data_u32(raw).map_err(value_read_error_to_io_error)? as usize,
+        })
+    };
+
+    let (elements, data_len) = match read_marker(raw)? {
+        Marker::Null | Marker::True | Marker::False | Marker::FixPos(_) | Marker::FixNeg(_) => {
+            (0, 0)
+        }
+        Marker::U8 | Marker::I8 => (0, 1),
+        Marker::U16 | Marker::I16 => (0, 2),
+        Marker::U32 | Marker::I32 | Marker::F32 => (0, 4),
+        Marker::U64 | Marker::I64 | Marker::F64 => (0, 8),
+        Marker::FixStr(len) => (0, len as usize),
+        Marker::Str8 | Marker::Bin8 => (0, read_len(raw, 1)?),
+        Marker::Str16 | Marker::Bin16 => (0, read_len(raw, 2)?),
+        Marker::Str32 | Marker::Bin32 => (0, read_len(raw, 4)?),
+        Marker::FixArray(len) => (len as usize, 0),
+        Marker::Array16 => (read_len(raw, 2)?, 0),
+        Marker::Array32 => (read_len(raw, 4)?, 0),
+        Marker::FixMap(len) => (2 * len as usize, 0),
+        Marker::Map16 => (2 * read_len(raw, 2)?, 0),
+        Marker::Map32 => (2 * read_len(raw, 4)?, 0),
+        // Extension types have a type byte before their data.
+        Marker::FixExt1 => (0, 2),
+        Marker::FixExt2 => (0, 3),
+        Marker::FixExt4 => (0, 5),
+        Marker::FixExt8 => (0, 9),
+        Marker::FixExt16 => (0, 17),
+        Marker::Ext8 => (0, read_len(raw, 1)? + 1),
+        Marker::Ext16 => (0, read_len(raw, 2)? + 1),
+        Marker::Ext32 => (0, read_len(raw, 4)? + 1),
+        Marker::Reserved => return err_invalid_input(),
+    };
+
+    if raw.len() < data_len {
+        return err_invalid_input();
+    }
+    *raw = &raw[data_len..];
+
+    for _ in 0..elements {
+        skip_value(raw)?;
+    }
+
+    Ok(())
+}
+
 pub fn ensure_parameters_count(raw: &mut &[u8], count: usize) -> io::Result<()> {
     if read_array_len(raw)? == count {
         Ok(())
@@ -0,0 +1,121 @@
+//! # Redraw Recordings
+//!
+//! Recordings of the redraw notifications sent by a real Neovim session, used to
+//! replay the exact same `grid_line` streams when measuring the editor throughput.
+//!
+//! Recording is enabled by setting `WEOVIM_RECORD_REDRAW` to the path of the file
+//! to write. The file is a sequence of notifications, each one being the raw
+//! msgpack of its parameters prefixed by its length as a little-endian `u32`.
+use super::events::RedrawEvent;
+use super::rpc::EventListener;
+use bumpalo::Bump;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+const RECORD_ENV_VAR: &str = "WEOVIM_RECORD_REDRAW";
+
+/// Writes received redraw notifications to a file.
+pub(super) struct Recorder {
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    /// Create a recorder if requested by the environment.
+    pub(super) fn from_env() -> Option<Self> {
+        let path = std::env::var_os(RECORD_ENV_VAR)?;
+
+        match File::create(&path) {
+            Ok(file) => {
+                log::info!("Recording redraw notifications to {:?}", path);
+                Some(Self {
+                    out: BufWriter::new(file),
+                })
+            }
+            Err(error) => {
+                log::warn!("Failed to create redraw recording {:?}: {}", path, error);
+                None
+            }
+        }
+    }
+
+    /// Record the raw parameters of a redraw notification.
+    pub(super) fn record(&mut self, raw: &[u8]) {
+        let len = (raw.len() as u32).to_le_bytes();
+
+        let result = self
+            .out
+            .write_all(&len)
+            .and_then(|_| self.out.write_all(raw))
+            .and_then(|_| self.out.flush());
+
+        if let Err(error) = result {
+            log::warn!("Failed to record redraw notification: {}", error);
+        }
+    }
+}
+
+/// A redraw recording loaded in memory.
+pub struct Recording {
+    notifications: Vec<Vec<u8>>,
+}
+
+impl Recording {
+    /// Load a recording written by a previous session.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut raw = Vec::new();
+        File::open(path)?.read_to_end(&mut raw)?;
+
+        let mut notifications = Vec::new();
+        let mut rest = &raw[..];
+        while !rest.is_empty() {
+            if rest.len() < 4 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "truncated notification length",
+                ));
+            }
+
+            let (len, tail) = rest.split_at(4);
+            let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+            if tail.len() < len {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "truncated notification",
+                ));
+            }
+
+            let (notification, tail) = tail.split_at(len);
+            notifications.push(notification.to_vec());
+            rest = tail;
+        }
+
+        Ok(Self { notifications })
+    }
+
+    /// Number of redraw notifications in the recording.
+    pub fn len(&self) -> usize {
+        self.notifications.len()
+    }
+
+    /// Is the recording free of redraw notifications?
+    pub fn is_empty(&self) -> bool {
+        self.notifications.is_empty()
+    }
+
+    /// Send all the recorded redraw events to `listener`, in order.
+    pub fn replay<L: EventListener>(&self, listener: &mut L) -> io::Result<()> {
+        let mut arena = Bump::new();
+
+        for notification in &self.notifications {
+            arena.reset();
+
+            let mut raw = &notification[..];
+            for event in RedrawEvent::decode(&mut raw, &arena)? {
+                listener.on_redraw_event(event);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,19 +1,33 @@
 use super::events::*;
 use super::msg;
+use super::record::Recorder;
 use bumpalo::Bump;
+use fnv::FnvHashMap;
+use rmp::Marker;
+use std::sync::{Arc, Mutex};
 use std::{io, process::Stdio};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::process::{ChildStdin, ChildStdout, Command};
+use tokio::sync::oneshot;
 
 const MEM_ARENA_INITIAL_CAPACITY: usize = 2 * 1024;
 const RAW_IO_BUF_INITIAL_CAPACITY: usize = 16 * 1024;
 
+/// The requests waiting for a response, by message id.
+///
+/// Responses are the raw message pack of the result.
+type PendingResponses = Arc<Mutex<FnvHashMap<u32, oneshot::Sender<io::Result<Vec<u8>>>>>>;
+
 /// A type that can react to neovim events.
 ///
-/// Currently, only redraw events are handled.
+/// Besides redraw events, listeners get the requests of the commands defined by
+/// [`Neovim::define_commands`](super::Neovim::define_commands).
 pub trait EventListener: Send + 'static {
     /// A redraw event was received.
     fn on_redraw_event<'e>(&mut self, event: RedrawEvent<'e>);
+
+    /// The user asked to change the font size.
+    fn on_zoom(&mut self, _zoom: Zoom) {}
 }
 
 /// A [`EventListener`] that logs every event received.
@@ -29,21 +43,26 @@ pub(super) struct RpcProcess {
     stdin: ChildStdin,
     rpc_buf: Vec<u8>,
     msg_id_counter: u32,
+    pending: PendingResponses,
 }
 
 impl RpcProcess {
     pub(super) fn spawn() -> io::Result<(Self, EventReceiver)> {
         let (stdin, stdout) = nvim_process()?;
+        let pending = PendingResponses::default();
 
         let rpc = Self {
             stdin,
             msg_id_counter: 0,
             rpc_buf: Vec::with_capacity(RAW_IO_BUF_INITIAL_CAPACITY),
+            pending: pending.clone(),
         };
 
         let recv = EventReceiver {
             stdout,
             mem_arena: Bump::with_capacity(MEM_ARENA_INITIAL_CAPACITY),
+            recorder: Recorder::from_env(),
+            pending,
         };
 
         Ok((rpc, recv))
@@ -89,7 +108,9 @@ impl RpcProcess {
         RpcMethod {
             stdin: &mut self.stdin,
             method,
+            id,
             buf: &mut self.rpc_buf,
+            pending: &self.pending,
         }
     }
 }
@@ -97,7 +118,9 @@ impl RpcProcess {
 pub(super) struct RpcMethod<'p, 'm> {
     stdin: &'p mut ChildStdin,
     method: &'m str,
+    id: u32,
     buf: &'p mut Vec<u8>,
+    pending: &'p PendingResponses,
 }
 
 impl RpcMethod<'_, '_> {
@@ -158,11 +181,30 @@ impl RpcMethod<'_, '_> {
 
         Ok(())
     }
+
+    /// Send the method and wait for its result, as raw message pack.
+    ///
+    /// The response is read by the [`EventReceiver`], which must be running.
+    pub(super) async fn call(self) -> io::Result<Vec<u8>> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(self.id, sender);
+
+        self.send().await?;
+
+        receiver.await.unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "neovim process closed before responding",
+            ))
+        })
+    }
 }
 
 pub(super) struct EventReceiver {
     stdout: ChildStdout,
     mem_arena: Bump,
+    recorder: Option<Recorder>,
+    pending: PendingResponses,
 }
 
 impl EventReceiver {
@@ -172,8 +214,11 @@ impl EventReceiver {
             raw_buf.clear();
             let n = self.stdout.read_buf(&mut raw_buf).await?;
             if n == 0 {
-                std::sync::atomic::spin_loop_hint();
-                continue;
+                // The buffer was empty, thus this can only be the end of the stream.
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "neovim process closed its output",
+                ));
             }
 
             log::trace!("Read {} bytes from stdout", n);
@@ -189,26 +234,47 @@ impl EventReceiver {
                         recv = &[];
                     }
                     // responses
-                    1 => {
-                        log::warn!("received response from neovim process");
-                        recv = &[];
-                    }
+                    1 => self.respond(&mut recv)?,
                     // notifications
                     2 => {
                         match msg::read_string(&mut recv)? {
-                            "redraw" => match RedrawEvent::decode(&mut recv, &self.mem_arena) {
-                                Ok(events) => {
-                                    events.into_iter()
-                                        .for_each(|e| listener.on_redraw_event(e))
+                            "redraw" => {
+                                let params = recv;
+                                match RedrawEvent::decode(&mut recv, &self.mem_arena) {
+                                    Ok(events) => {
+                                        if let Some(recorder) = &mut self.recorder {
+                                            let len = params.len() - recv.len();
+                                            recorder.record(&params[..len]);
+                                        }
+
+                                        events
+                                            .into_iter()
+                                            .for_each(|e| listener.on_redraw_event(e))
+                                    }
+                                    Err(error) => {
+                                        log::error!("Error while decoding RPC message: {}", error);
+                                        recv = &[];
+                                    }
                                 }
-                                Err(error) => {
-                                    log::error!("Error while decoding RPC message: {}", error);
-                                    recv = &[];
+                            }
+                            Zoom::NOTIFICATION => {
+                                // Skipped first, so an invalid argument only drops
+                                // this notification.
+                                let mut params = recv;
+                                msg::skip_value(&mut recv)?;
+
+                                match Zoom::decode(&mut params) {
+                                    Ok(zoom) => listener.on_zoom(zoom),
+                                    Err(error) => log::error!(
+                                        "Invalid {} notification: {}",
+                                        Zoom::NOTIFICATION,
+                                        error
+                                    ),
                                 }
-                            },
+                            }
                             not => {
                                 log::warn!("received unknown notification type '{}'", not);
-                                recv = &[];
+                                msg::skip_value(&mut recv)?;
                             }
                         };
                     }
@@ -217,6 +283,38 @@ impl EventReceiver {
             }
         }
     }
+
+    /// Deliver the response at the start of `recv`, after its type, to its request.
+    fn respond(&self, recv: &mut &[u8]) -> io::Result<()> {
+        let id = msg::read_u64(recv)? as u32;
+
+        let error = *recv;
+        let failed = msg::read_marker(&mut &error[..])? != Marker::Null;
+        msg::skip_value(recv)?;
+        let error = &error[..error.len() - recv.len()];
+
+        let result = *recv;
+        msg::skip_value(recv)?;
+        let result = &result[..result.len() - recv.len()];
+
+        let response = if failed {
+            Err(io::Error::other(format!(
+                "neovim responded with an error: {:?}",
+                error
+            )))
+        } else {
+            Ok(result.to_vec())
+        };
+
+        match self.pending.lock().unwrap().remove(&id) {
+            Some(sender) => {
+                let _ = sender.send(response);
+            }
+            None => log::warn!("received response to unknown request {}", id),
+        }
+
+        Ok(())
+    }
 }
 
 fn nvim_process() -> io::Result<(ChildStdin, ChildStdout)> {
@@ -242,6 +340,8 @@ fn nvim_process() -> io::Result<(ChildStdin, ChildStdout)> {
         .take()
         .expect("child neovim process stdout not configured");
 
+    // The event loop stops once the process closes its output, letting the UI
+    // clean up before exiting.
     tokio::spawn(async move {
         log::info!("Waiting for neovim process to finish");
         match nvim.wait().await {
@@ -254,8 +354,6 @@ fn nvim_process() -> io::Result<(ChildStdin, ChildStdout)> {
             log::error!("{:?}", stderr.read_to_string(&mut buf).await);
             log::error!("NVIM stderr: {}", buf);
         }
-
-        std::process::exit(1);
     });
 
     Ok((stdin, stdout))
@@ -0,0 +1,152 @@
+//! # Link Opener
+//!
+//! Opens the links detected in the grids: URLs are given to an external opener
+//! command, while file references are edited in Neovim.
+//!
+//! The opener command can be set with `WEOVIM_OPENER`, e.g. `WEOVIM_OPENER="firefox --new-tab"`.
+//! The URL is appended as its last argument.
+use crate::grid::links::LinkTarget;
+use crate::neovim::Neovim;
+use std::io;
+use std::process::Stdio;
+use tokio::process::Command;
+
+const OPENER_ENV_VAR: &str = "WEOVIM_OPENER";
+
+#[cfg(target_os = "macos")]
+const DEFAULT_OPENER: &[&str] = &["open"];
+// Not `cmd /C start`, cmd would run the commands after the `&` of a URL.
+#[cfg(target_os = "windows")]
+const DEFAULT_OPENER: &[&str] = &["explorer"];
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+const DEFAULT_OPENER: &[&str] = &["xdg-open"];
+
+/// Characters with special meaning in Ex file arguments, see `fnameescape()`.
+const EX_SPECIAL_CHARS: &[char] = &[
+    ' ', '\t', '\n', '*', '?', '[', '{', '`', '$', '\\', '%', '#', '\'', '"', '|', '!', '<',
+];
+
+/// Opens links in the appropriate program.
+#[derive(Debug, Clone)]
+pub struct Opener {
+    program: String,
+    args: Vec<String>,
+}
+
+impl Opener {
+    /// The opener configured in the environment, or the platform default.
+    pub fn from_env() -> Self {
+        let configured = std::env::var(OPENER_ENV_VAR).ok().and_then(|command| {
+            let mut words = command.split_whitespace().map(String::from);
+            let program = words.next()?;
+
+            Some(Self {
+                program,
+                args: words.collect(),
+            })
+        });
+
+        configured.unwrap_or_else(Self::platform_default)
+    }
+
+    /// The default opener of the platform.
+    fn platform_default() -> Self {
+        Self {
+            program: DEFAULT_OPENER[0].to_string(),
+            args: DEFAULT_OPENER[1..]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect(),
+        }
+    }
+
+    /// Open the target of a link.
+    pub async fn open(&self, target: &LinkTarget, neovim: &mut Neovim) -> io::Result<()> {
+        match target {
+            LinkTarget::Url(url) => {
+                log::info!("Opening {} with {}", url, self.program);
+                let argv = self.argv(url);
+
+                // The child isn't waited, the runtime reaps it once it exits.
+                Command::new(argv[0])
+                    .args(&argv[1..])
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()?;
+
+                Ok(())
+            }
+            LinkTarget::File { path, line, .. } => {
+                let command = format!("edit +{} {}", line, escape_path(path));
+                neovim.command(&command).await
+            }
+        }
+    }
+
+    /// The program and arguments that open `url`, given as the last argument.
+    fn argv<'a>(&'a self, url: &'a str) -> Vec<&'a str> {
+        std::iter::once(self.program.as_str())
+            .chain(self.args.iter().map(String::as_str))
+            .chain(std::iter::once(url))
+            .collect()
+    }
+}
+
+/// Escape a path to be used as an Ex command argument.
+fn escape_path(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+
+    for chr in path.chars() {
+        if EX_SPECIAL_CHARS.contains(&chr) {
+            escaped.push('\\');
+        }
+        escaped.push(chr);
+    }
+
+    // A leading `+` would be taken as another command argument.
+    if escaped.starts_with('+') {
+        escaped.insert_str(0, "./");
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Programs that would interpret the URL as a command line.
+    const SHELLS: &[&str] = &["cmd", "cmd.exe", "powershell", "pwsh", "sh", "bash"];
+
+    #[test]
+    fn urls_are_never_given_to_a_shell() {
+        let url = "https://example.com/?a=1&calc|whoami";
+        let opener = Opener::platform_default();
+        let argv = opener.argv(url);
+
+        assert!(!SHELLS.contains(&argv[0]), "{:?}", argv);
+        assert_eq!(argv.last(), Some(&url));
+        assert_eq!(argv.len(), DEFAULT_OPENER.len() + 1);
+    }
+
+    #[test]
+    fn configured_arguments_come_before_the_url() {
+        let opener = Opener {
+            program: String::from("firefox"),
+            args: vec![String::from("--new-tab")],
+        };
+
+        assert_eq!(
+            opener.argv("https://neovim.io"),
+            ["firefox", "--new-tab", "https://neovim.io"]
+        );
+    }
+
+    #[test]
+    fn paths_are_escaped_for_ex() {
+        assert_eq!(escape_path("src/main.rs"), "src/main.rs");
+        assert_eq!(escape_path("my file%#.rs"), "my\\ file\\%\\#.rs");
+        assert_eq!(escape_path("+cmd"), "./+cmd");
+    }
+}
@@ -0,0 +1,262 @@
+//! # Headless Snapshots
+//!
+//! Renders a published [`Frame`] without a GPU, either as plain text or as text with
+//! 24-bit ANSI escape sequences. Useful to inspect what the editor thinks is on the
+//! screen, and to compare it against golden files.
+//!
+//! The layers of the frame are first composited into a [`Canvas`] of cells, the size
+//! of the default grid, which can also be used by other cell-based frontends.
+use crate::editor::{Frame, HighlightGroups};
+use crate::grid::{Grids, Lines};
+use crate::neovim::RgbAttr;
+use crate::width::WidthOptions;
+use std::fmt::Write;
+
+/// The plain text of the screen.
+///
+/// Trailing whitespace is removed from every row, and the row below the cursor
+/// marks its column with a `^`.
+pub fn plain_text(frame: &Frame) -> String {
+    let mut canvas = Canvas::default();
+    canvas.compose(frame);
+
+    let mut out = String::new();
+    canvas.write_plain_text(&mut out);
+    out
+}
+
+/// The screen with its colors and text attributes as ANSI escape sequences.
+///
+/// The cursor is drawn with its own colors.
+pub fn ansi(frame: &Frame) -> String {
+    let mut canvas = Canvas::default();
+    canvas.compose(frame);
+
+    let mut out = String::new();
+    canvas.write_ansi(&frame.hl_groups, &frame.cursor_color_set(), &mut out);
+    out
+}
+
+/// A cell of the composited screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanvasCell {
+    /// The cell text, empty for the right half of a double-width cell.
+    pub text: String,
+    /// The highlight group of the cell.
+    pub hl_id: u64,
+}
+
+impl Default for CanvasCell {
+    fn default() -> Self {
+        Self {
+            text: String::from(" "),
+            hl_id: 0,
+        }
+    }
+}
+
+impl CanvasCell {
+    fn set(&mut self, text: &str, hl_id: u64) {
+        self.text.clear();
+        self.text.push_str(text);
+        self.hl_id = hl_id;
+    }
+
+    /// Replace the text with a blank, keeping the highlight.
+    fn blank(&mut self) {
+        self.text.clear();
+        self.text.push(' ');
+    }
+}
+
+/// The layers of a frame composited into a single grid of cells.
+///
+/// Allocations are re-used between compositions.
+#[derive(Debug, Default, Clone)]
+pub struct Canvas {
+    rows: usize,
+    cols: usize,
+    /// Cells of the screen, each row stored in `cells[row * cols..(row + 1) * cols]`.
+    cells: Vec<CanvasCell>,
+    /// Position of the cursor, as `(row, col)`, if it is visible.
+    cursor: Option<(usize, usize)>,
+}
+
+impl Canvas {
+    /// Number of rows of the screen.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Number of columns of the screen.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// The cells of a row of the screen.
+    pub fn row(&self, row: usize) -> Option<&[CanvasCell]> {
+        if row < self.rows {
+            Some(&self.cells[row * self.cols..(row + 1) * self.cols])
+        } else {
+            None
+        }
+    }
+
+    /// Position of the cursor on the screen, as `(row, col)`.
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        self.cursor
+    }
+
+    /// Composite the layers of `frame`, from bottom to top.
+    pub fn compose(&mut self, frame: &Frame) {
+        let (rows, cols) = frame
+            .grids
+            .get(Grids::DEFAULT_GRID)
+            .map_or((0, 0), |lines| (lines.rows(), lines.cols()));
+
+        self.rows = rows;
+        self.cols = cols;
+        self.cells.resize_with(rows * cols, CanvasCell::default);
+        for cell in &mut self.cells {
+            cell.set(" ", 0);
+        }
+
+        for layer in &frame.layers {
+            let lines = match frame.grids.get(layer.grid) {
+                Some(lines) => lines,
+                None => continue,
+            };
+
+            let height = layer.height.min(rows.saturating_sub(layer.row));
+            let width = layer.width.min(cols.saturating_sub(layer.col));
+
+            for row in 0..height {
+                for col in 0..width {
+                    let text = layer_cell_text(lines, row, col, width, &frame.width);
+                    let hl_id = lines.hl_id_at(row, col).unwrap_or(0);
+
+                    self.put(layer.row + row, layer.col + col, text, hl_id);
+                }
+            }
+        }
+
+        self.cursor = frame
+            .cursor_position()
+            .filter(|(row, col)| *row < rows && *col < cols);
+    }
+
+    /// Write the plain text of the screen to `out`, see [`plain_text`].
+    pub fn write_plain_text(&self, out: &mut String) {
+        for row in 0..self.rows {
+            let start = out.len();
+            for cell in self.row(row).unwrap_or_default() {
+                out.push_str(&cell.text);
+            }
+
+            let trimmed = out[start..].trim_end().len();
+            out.truncate(start + trimmed);
+            out.push('\n');
+
+            if let Some((_, col)) = self.cursor.filter(|(cursor_row, _)| *cursor_row == row) {
+                out.extend(std::iter::repeat_n(' ', col));
+                out.push_str("^\n");
+            }
+        }
+    }
+
+    /// Write the screen with ANSI escape sequences to `out`, see [`ansi`].
+    pub fn write_ansi(&self, hl_groups: &HighlightGroups, cursor_attr: &RgbAttr, out: &mut String) {
+        for row in 0..self.rows {
+            // Only emit a new style when it changes, `None` forces the first one.
+            let mut current = None;
+
+            for (col, cell) in self.row(row).unwrap_or_default().iter().enumerate() {
+                let is_cursor = self.cursor == Some((row, col));
+                let style = Some((cell.hl_id, is_cursor));
+
+                if style != current {
+                    if is_cursor {
+                        write_sgr(cursor_attr, out);
+                    } else {
+                        write_sgr(&hl_groups.group_color_set(cell.hl_id), out);
+                    }
+
+                    current = style;
+                }
+
+                out.push_str(&cell.text);
+            }
+
+            out.push_str("\x1b[0m\n");
+        }
+    }
+
+    fn put(&mut self, row: usize, col: usize, text: &str, hl_id: u64) {
+        let idx = row * self.cols + col;
+
+        // Don't leave halves of the double-width cells we're covering behind.
+        if !text.is_empty() && col > 0 && self.cells[idx].text.is_empty() {
+            self.cells[idx - 1].blank();
+        }
+        if col + 1 < self.cols && self.cells[idx + 1].text.is_empty() {
+            self.cells[idx + 1].blank();
+        }
+
+        self.cells[idx].set(text, hl_id);
+    }
+}
+
+/// The text of a cell of a layer, replacing double-width cells cut by the layer
+/// bounds with a blank.
+fn layer_cell_text<'l>(
+    lines: &'l Lines,
+    row: usize,
+    col: usize,
+    width: usize,
+    options: &WidthOptions,
+) -> &'l str {
+    let text = lines.cell_text(row, col).unwrap_or(" ");
+
+    let is_cut_right_half = col == 0 && text.is_empty();
+    let (fits, _) = options.truncate(text, width - col);
+    let is_cut_left_half = fits.len() < text.len();
+
+    if is_cut_right_half || is_cut_left_half {
+        " "
+    } else {
+        text
+    }
+}
+
+/// Write the SGR escape sequence that sets all properties of `attr`.
+pub fn write_sgr(attr: &RgbAttr, out: &mut String) {
+    out.push_str("\x1b[0");
+
+    if let Some([r, g, b, _]) = attr.foreground.map(|color| color.to_rgba()) {
+        let _ = write!(out, ";38;2;{};{};{}", r, g, b);
+    }
+    if let Some([r, g, b, _]) = attr.background.map(|color| color.to_rgba()) {
+        let _ = write!(out, ";48;2;{};{};{}", r, g, b);
+    }
+    if attr.bold() {
+        out.push_str(";1");
+    }
+    if attr.italic() {
+        out.push_str(";3");
+    }
+    if attr.undercurl() {
+        out.push_str(";4:3");
+    } else if attr.underline() {
+        out.push_str(";4");
+    }
+    if attr.underline() || attr.undercurl() {
+        if let Some([r, g, b, _]) = attr.special.map(|color| color.to_rgba()) {
+            let _ = write!(out, ";58;2;{};{};{}", r, g, b);
+        }
+    }
+    if attr.strikethrough() {
+        out.push_str(";9");
+    }
+
+    out.push('m');
+}
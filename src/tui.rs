@@ -0,0 +1,401 @@
+//! # Terminal Frontend
+//!
+//! Draws the editor state in the terminal with ANSI escape sequences, and forwards
+//! the keyboard input to Neovim with `nvim_input`. Started with `weovim --tui`.
+//!
+//! Like the GPU frontend, it only consumes the editor through [`UiStateFromEditor`]:
+//! every published frame is composited in a [`Canvas`], and only the rows that
+//! changed since the last drawn frame are written to the terminal.
+//!
+//! Mouse events are forwarded with `nvim_input_mouse`, except for Ctrl-clicks on links,
+//! which are opened by the [`Opener`]. The link under the pointer is underlined, the
+//! screen is only redrawn when the pointer moves to another link.
+//!
+//! Logs would corrupt the screen, so they are only written when `WEOVIM_LOG_FILE` is
+//! set, to that file.
+use crate::color::Color;
+use crate::editor::{Frame, UiEditorEvent, UiStateFromEditor};
+use crate::grid::links::Link;
+use crate::neovim::{CursorShape, EventListener, Neovim, NeovimEventLoop, UiOptions};
+use crate::opener::Opener;
+use crate::snapshot::{self, Canvas};
+use crate::width::{AmbiWidth, WidthOptions};
+use crossterm::event::{
+    DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers, MouseButton,
+    MouseEventKind,
+};
+use crossterm::{cursor, queue, terminal};
+use std::io::{self, Write};
+use tokio::sync::mpsc;
+
+const INPUT_CHANNEL_BUFFER_SIZE: usize = 64;
+
+/// How most terminals measure text, with `wcwidth` and emoji presentation.
+const TERMINAL_WIDTH: WidthOptions = WidthOptions {
+    ambiwidth: AmbiWidth::Single,
+    emoji: true,
+};
+
+/// Run the terminal frontend until Neovim exits.
+pub async fn run<L: EventListener>(
+    mut neovim: Neovim,
+    event_loop: NeovimEventLoop<L>,
+    mut ui_state: UiStateFromEditor,
+) -> io::Result<()> {
+    let (cols, rows) = terminal::size().map_err(crossterm_to_io_error)?;
+    let mut terminal = Terminal::enter()?;
+
+    let mut event_loop = tokio::spawn(event_loop.run_loop());
+
+    neovim
+        .ui_attach(
+            cols as u64,
+            rows as u64,
+            UiOptions::RGB | UiOptions::EXT_MULTIGRID,
+        )
+        .await?;
+    log::info!("UI attached");
+
+    let mut input = spawn_input_reader();
+    let opener = Opener::from_env();
+    // The button of a Ctrl-click that opened a link, until it is released.
+    let mut link_click = None;
+
+    loop {
+        tokio::select! {
+            result = &mut event_loop => {
+                match result {
+                    Ok(Ok(never)) => never,
+                    Ok(Err(error)) => log::info!("Neovim event loop finished: {}", error),
+                    Err(error) => log::error!("Neovim event loop failed: {}", error),
+                }
+
+                return Ok(());
+            }
+            event = ui_state.recv.recv() => match event {
+                Some(UiEditorEvent::Redraw) => terminal.draw(ui_state.reader.buffer())?,
+                Some(event) => log::debug!("Ignoring UiEditorEvent: {:?}", event),
+                None => return Ok(()),
+            },
+            event = input.recv() => match event {
+                Some(Event::Key(key)) => neovim.input(&key_notation(key)).await?,
+                Some(Event::Resize(cols, rows)) => {
+                    terminal.invalidate();
+                    neovim.ui_try_resize(cols as u64, rows as u64).await?;
+                }
+                Some(Event::Mouse(event)) => {
+                    let (row, col) = (event.row as usize, event.column as usize);
+
+                    let frame = ui_state.reader.buffer();
+                    if terminal.point_at(frame, row, col) {
+                        terminal.draw(frame)?;
+                    }
+
+                    let (button, action) = match mouse_event_parts(event.kind) {
+                        Some(parts) => parts,
+                        // Neovim isn't told about the pointer moving without a button.
+                        None => continue,
+                    };
+
+                    // The rest of a Ctrl-click on a link is swallowed, up to its release.
+                    if let Some(held) = link_click {
+                        if action == "release" && button == held {
+                            link_click = None;
+                        }
+                        continue;
+                    }
+
+                    let link = frame.link_at(row, col);
+                    let is_ctrl_click = button == "left"
+                        && action == "press"
+                        && event.modifiers == KeyModifiers::CONTROL;
+
+                    if let (true, Some(link)) = (is_ctrl_click, link) {
+                        link_click = Some(button);
+                        opener.open(&link.target, &mut neovim).await?;
+                    } else if let Some((grid, row, col)) = frame.grid_position(row, col) {
+                        let modifiers = modifiers_notation(event.modifiers);
+                        neovim
+                            .input_mouse(button, action, &modifiers, grid, row as u64, col as u64)
+                            .await?;
+                    }
+                }
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+/// The terminal in raw mode, showing the alternate screen.
+///
+/// The terminal is restored when dropped.
+struct Terminal {
+    out: io::Stdout,
+    /// The frame being drawn.
+    canvas: Canvas,
+    /// The last frame written to the terminal.
+    drawn: Canvas,
+    /// Version of the highlight groups of the last frame written to the terminal.
+    drawn_hl_version: Option<u64>,
+    /// The last reported position of the mouse pointer, as `(row, col)`.
+    pointer: Option<(usize, usize)>,
+    /// The link under the pointer in the last frame written to the terminal.
+    drawn_hovered: Option<Link>,
+    /// The cursor color of the last frame written to the terminal.
+    drawn_cursor_color: Option<Color>,
+    buf: Vec<u8>,
+    sgr: String,
+}
+
+impl Terminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode().map_err(crossterm_to_io_error)?;
+
+        let mut out = io::stdout();
+        queue!(out, terminal::EnterAlternateScreen, EnableMouseCapture)
+            .map_err(crossterm_to_io_error)?;
+        out.flush()?;
+
+        Ok(Self {
+            out,
+            canvas: Canvas::default(),
+            drawn: Canvas::default(),
+            drawn_hl_version: None,
+            pointer: None,
+            drawn_hovered: None,
+            drawn_cursor_color: None,
+            buf: Vec::new(),
+            sgr: String::new(),
+        })
+    }
+
+    /// Forget what is on the terminal, so that the next frame is drawn entirely.
+    fn invalidate(&mut self) {
+        self.drawn_hl_version = None;
+    }
+
+    /// Move the mouse pointer to the screen position `(row, col)`, returning if the
+    /// link under it in `frame` isn't the drawn one.
+    fn point_at(&mut self, frame: &Frame, row: usize, col: usize) -> bool {
+        self.pointer = Some((row, col));
+        frame.link_at(row, col) != self.drawn_hovered
+    }
+
+    fn draw(&mut self, frame: &Frame) -> io::Result<()> {
+        self.canvas.compose(frame);
+
+        let hovered = self.pointer.and_then(|(row, col)| frame.link_at(row, col));
+        let hover_changed = hovered != self.drawn_hovered;
+        let is_hover_row =
+            |link: &Option<Link>, row| link.as_ref().map(|link| link.row) == Some(row);
+
+        let full = self.drawn_hl_version != Some(frame.hl_groups.version())
+            || self.canvas.rows() != self.drawn.rows()
+            || self.canvas.cols() != self.drawn.cols();
+
+        let buf = &mut self.buf;
+        let sgr = &mut self.sgr;
+
+        buf.clear();
+        queue!(buf, cursor::Hide).map_err(crossterm_to_io_error)?;
+        if full {
+            queue!(buf, terminal::Clear(terminal::ClearType::All))
+                .map_err(crossterm_to_io_error)?;
+        }
+
+        for row in 0..self.canvas.rows() {
+            let cells = self.canvas.row(row).unwrap_or_default();
+            let hover_row_changed = hover_changed
+                && (is_hover_row(&hovered, row) || is_hover_row(&self.drawn_hovered, row));
+
+            if !full && !hover_row_changed && self.drawn.row(row) == Some(cells) {
+                continue;
+            }
+
+            let underlined = hovered
+                .as_ref()
+                .filter(|link| link.row == row)
+                .map_or(0..0, |link| link.cols.clone());
+
+            queue!(buf, cursor::MoveTo(0, row as u16)).map_err(crossterm_to_io_error)?;
+
+            let mut current = None;
+            for (col, cell) in cells.iter().enumerate() {
+                let style = Some((cell.hl_id, underlined.contains(&col)));
+
+                if current != style {
+                    sgr.clear();
+                    snapshot::write_sgr(&frame.hl_groups.group_color_set(cell.hl_id), sgr);
+                    if underlined.contains(&col) {
+                        sgr.push_str("\x1b[4m");
+                    }
+
+                    buf.extend_from_slice(sgr.as_bytes());
+                    current = style;
+                }
+
+                buf.extend_from_slice(cell.text.as_bytes());
+
+                // Keep the row aligned when the terminal measures the text differently.
+                let width = frame.width.width(&cell.text);
+                if !cell.text.is_ascii() && width != TERMINAL_WIDTH.width(&cell.text) {
+                    queue!(buf, cursor::MoveTo((col + width) as u16, row as u16))
+                        .map_err(crossterm_to_io_error)?;
+                }
+            }
+        }
+
+        buf.extend_from_slice(b"\x1b[0m");
+
+        if let Some((row, col)) = self.canvas.cursor() {
+            // DECSCUSR, with steady shapes.
+            let shape = match frame.cursor.shape {
+                CursorShape::Block => 2,
+                CursorShape::Horizontal => 4,
+                CursorShape::Vertical => 6,
+            };
+
+            let _ = write!(buf, "\x1b[{} q", shape);
+
+            // OSC 12, the color of the cursor is the background of its colors.
+            let color = frame.cursor_color_set().background;
+            if color != self.drawn_cursor_color {
+                match color.map(|color| color.to_rgba()) {
+                    Some([r, g, b, _]) => {
+                        let _ = write!(buf, "\x1b]12;#{:02x}{:02x}{:02x}\x07", r, g, b);
+                    }
+                    None => buf.extend_from_slice(b"\x1b]112\x07"),
+                }
+                self.drawn_cursor_color = color;
+            }
+            queue!(buf, cursor::MoveTo(col as u16, row as u16), cursor::Show)
+                .map_err(crossterm_to_io_error)?;
+        }
+
+        self.out.write_all(&self.buf)?;
+        self.out.flush()?;
+
+        std::mem::swap(&mut self.canvas, &mut self.drawn);
+        self.drawn_hl_version = Some(frame.hl_groups.version());
+        self.drawn_hovered = hovered;
+
+        Ok(())
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // Restore the default cursor shape and color.
+        let _ = self.out.write_all(b"\x1b[0 q\x1b]112\x07");
+        let _ = queue!(
+            self.out,
+            DisableMouseCapture,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = self.out.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Read terminal events in a separate thread, as crossterm only provides blocking reads.
+fn spawn_input_reader() -> mpsc::Receiver<Event> {
+    let (tx, rx) = mpsc::channel(INPUT_CHANNEL_BUFFER_SIZE);
+
+    std::thread::spawn(move || loop {
+        match crossterm::event::read() {
+            Ok(event) => {
+                if tx.blocking_send(event).is_err() {
+                    break;
+                }
+            }
+            Err(error) => {
+                log::error!("Failed to read terminal event: {}", error);
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+/// The `nvim_input_mouse` button and action of a mouse event, if Neovim has one.
+fn mouse_event_parts(kind: MouseEventKind) -> Option<(&'static str, &'static str)> {
+    let button_name = |button| match button {
+        MouseButton::Left => "left",
+        MouseButton::Right => "right",
+        MouseButton::Middle => "middle",
+    };
+
+    Some(match kind {
+        MouseEventKind::Down(button) => (button_name(button), "press"),
+        MouseEventKind::Up(button) => (button_name(button), "release"),
+        MouseEventKind::Drag(button) => (button_name(button), "drag"),
+        MouseEventKind::ScrollDown => ("wheel", "down"),
+        MouseEventKind::ScrollUp => ("wheel", "up"),
+        MouseEventKind::Moved => return None,
+    })
+}
+
+/// The modifiers prefix of Neovim's key notation, like `C-A-`.
+fn modifiers_notation(modifiers: KeyModifiers) -> String {
+    let mut notation = String::new();
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        notation.push_str("C-");
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        notation.push_str("M-");
+    }
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        notation.push_str("S-");
+    }
+
+    notation
+}
+
+/// Translate a key event to Neovim's key notation.
+fn key_notation(key: KeyEvent) -> String {
+    let modified = key
+        .modifiers
+        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+
+    let name = match key.code {
+        // Shift is already applied to the character.
+        KeyCode::Char('<') if !modified => return "<lt>".to_string(),
+        KeyCode::Char(chr) if !modified => return chr.to_string(),
+        KeyCode::Char('<') => "lt".to_string(),
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(chr) => chr.to_string(),
+        KeyCode::Backspace => "BS".to_string(),
+        KeyCode::Enter => "CR".to_string(),
+        KeyCode::Left => "Left".to_string(),
+        KeyCode::Right => "Right".to_string(),
+        KeyCode::Up => "Up".to_string(),
+        KeyCode::Down => "Down".to_string(),
+        KeyCode::Home => "Home".to_string(),
+        KeyCode::End => "End".to_string(),
+        KeyCode::PageUp => "PageUp".to_string(),
+        KeyCode::PageDown => "PageDown".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::BackTab => "S-Tab".to_string(),
+        KeyCode::Delete => "Del".to_string(),
+        KeyCode::Insert => "Insert".to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::Null => "Nul".to_string(),
+        KeyCode::Esc => "Esc".to_string(),
+    };
+
+    let mut modifiers = key.modifiers;
+    if matches!(key.code, KeyCode::Char(_) | KeyCode::BackTab) {
+        modifiers.remove(KeyModifiers::SHIFT);
+    }
+
+    format!("<{}{}>", modifiers_notation(modifiers), name)
+}
+
+fn crossterm_to_io_error(error: crossterm::ErrorKind) -> io::Error {
+    match error {
+        crossterm::ErrorKind::IoError(error) => error,
+        error => io::Error::other(error.to_string()),
+    }
+}
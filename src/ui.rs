@@ -1,6 +1,9 @@
 use crate::color::Color;
-use crate::editor::{UiStateFromEditor, TripleBufferReader, UiEditorEvent};
-use crate::neovim::Neovim;
+use crate::editor::{TripleBufferReader, UiEditorEvent, UiEditorSharedState, UiStateFromEditor};
+use crate::grid::links::Link;
+use crate::ligatures::LigatureConfig;
+use crate::neovim::{Neovim, Zoom};
+use crate::opener::Opener;
 use std::sync::Arc;
 use std::sync::{
     atomic::{AtomicBool, AtomicU32, Ordering},
@@ -8,38 +11,104 @@ use std::sync::{
 };
 use winit::dpi::*;
 use winit::event::*;
-use winit::event_loop::{ControlFlow, EventLoop};
+use winit::event_loop::{ControlFlow, EventLoop, EventLoopProxy};
 #[cfg(target_os = "macos")]
 use winit::platform::macos::WindowBuilderExtMacOS;
 use winit::window::{CursorIcon, Window, WindowBuilder};
 
+mod atlas;
+mod box_drawing;
+mod color_glyph;
+mod decorations;
+mod fallback;
+mod font;
+mod glyph_cache;
 mod gpu;
+mod metrics;
+mod painter;
 mod renderers;
 mod shaper;
-use self::renderers::Quad;
+use self::fallback::{load_faces, load_primary, FontFallback};
+use self::font::FontFaces;
+use self::glyph_cache::{GlyphCache, PixelFormat, Rasterizer};
+use self::metrics::{CellMetrics, FontMetrics};
+use self::painter::Painter;
+use self::shaper::Shaper;
+
+/// Height of the font when the `guifont` doesn't give one, in points.
+const DEFAULT_FONT_HEIGHT: f32 = 12.0;
+/// Points added or removed from the font height on each zoom step.
+const ZOOM_STEP: f32 = 1.0;
+/// Zooming out never makes the font smaller than this height, in points.
+const MIN_FONT_HEIGHT: f32 = 4.0;
 
 pub struct Ui {
     gpu: Mutex<gpu::Gpu>,
     quad: Mutex<renderers::QuadRenderer>,
-    neovim: Mutex<Neovim>,
+    glyphs: Mutex<renderers::GlyphRenderer>,
+    glyph_cache: Mutex<GlyphCache>,
+    shaper: Mutex<Shaper>,
+    color_glyphs: Mutex<renderers::GlyphRenderer>,
+    color_glyph_cache: Mutex<GlyphCache>,
+    neovim: Arc<tokio::sync::Mutex<Neovim>>,
+    reader: Mutex<TripleBufferReader>,
+    shared: Arc<UiEditorSharedState>,
     window: UiWindow,
     input: UiInput,
+    links: Mutex<UiLinks>,
+    opener: Opener,
+    metrics: Mutex<UiMetrics>,
+    /// Delivers events to the UI from other threads.
+    proxy: Mutex<EventLoopProxy<UiEditorEvent>>,
 }
 
 impl Ui {
-    pub async fn new(neovim: Neovim) -> (Arc<Ui>, UiEventLoop) {
+    pub async fn new(
+        neovim: Arc<tokio::sync::Mutex<Neovim>>,
+        reader: TripleBufferReader,
+        shared: Arc<UiEditorSharedState>,
+    ) -> (Arc<Ui>, UiEventLoop) {
         let event_loop = <EventLoop<UiEditorEvent>>::with_user_event();
         let window = UiWindow::build(&event_loop);
 
         let mut gpu = gpu::Gpu::new(&window).await;
         let quad = renderers::QuadRenderer::new(&mut gpu);
+        let glyph_cache = GlyphCache::default();
+        let glyphs = renderers::GlyphRenderer::new(
+            &mut gpu,
+            glyph_cache.page_size(),
+            glyph_cache::MAX_PAGES,
+            glyph_cache.format(),
+        );
+        let color_glyph_cache = GlyphCache::with_format(
+            glyph_cache::PAGE_SIZE,
+            glyph_cache::MAX_PAGES,
+            PixelFormat::Rgba,
+        );
+        let color_glyphs = renderers::GlyphRenderer::new(
+            &mut gpu,
+            color_glyph_cache.page_size(),
+            glyph_cache::MAX_PAGES,
+            color_glyph_cache.format(),
+        );
 
         let ui = Arc::new(Ui {
             gpu: Mutex::new(gpu),
             quad: Mutex::new(quad),
-            neovim: Mutex::new(neovim),
+            glyphs: Mutex::new(glyphs),
+            glyph_cache: Mutex::new(glyph_cache),
+            shaper: Mutex::new(Shaper::from_config(&LigatureConfig::from_env())),
+            color_glyphs: Mutex::new(color_glyphs),
+            color_glyph_cache: Mutex::new(color_glyph_cache),
+            neovim,
+            reader: Mutex::new(reader),
+            shared,
             window,
             input: Default::default(),
+            links: Default::default(),
+            opener: Opener::from_env(),
+            metrics: Default::default(),
+            proxy: Mutex::new(event_loop.create_proxy()),
         });
 
         let ui_event_loop = UiEventLoop {
@@ -51,21 +120,307 @@ impl Ui {
     }
 
     fn render(&self) {
+        let mut reader = self.reader.lock().unwrap();
+        let frame = reader.buffer();
+
         let mut gpu = self.gpu.lock().unwrap();
-        let (frame, mut encoder) = gpu.begin_render();
-        gpu.clear(&frame.view, &mut encoder, Color::BLACK);
+        let size = gpu.size();
+        let (output, mut encoder) = gpu.begin_render();
+        let background = frame
+            .hl_groups
+            .group_color_set(0)
+            .background
+            .unwrap_or(Color::BLACK);
+        gpu.clear(&output.view, &mut encoder, background.to_linear());
+
+        let mut glyph_cache = self.glyph_cache.lock().unwrap();
+        glyph_cache.begin_frame();
+        let mut color_glyph_cache = self.color_glyph_cache.lock().unwrap();
+        color_glyph_cache.begin_frame();
+
+        let mut glyphs = self.glyphs.lock().unwrap();
+        let mut color_glyphs = self.color_glyphs.lock().unwrap();
+
+        let mut metrics = self.metrics.lock().unwrap();
+        let metrics = &mut *metrics;
+        if let (Some(cell), Some(fonts)) = (metrics.cell, metrics.fonts.as_mut()) {
+            let hovered = self.links.lock().unwrap().hovered.clone();
+            let mut quads = self.quad.lock().unwrap();
+            let mut shaper = self.shaper.lock().unwrap();
+
+            let mut painter = Painter {
+                frame,
+                cell,
+                size,
+                fonts,
+                shaper: &mut shaper,
+                glyph_cache: &mut glyph_cache,
+                color_glyph_cache: &mut color_glyph_cache,
+                rasterizer: Rasterizer::default(),
+                quads: &mut quads,
+                glyphs: &mut glyphs,
+                color_glyphs: &mut color_glyphs,
+            };
+            painter.paint(&output.view, &mut encoder, &mut gpu, hovered.as_ref());
+        }
+
+        // Textures are written before the commands of the frame are executed.
+        let grown = glyphs.upload(&gpu, &mut glyph_cache);
+        let color_grown = color_glyphs.upload(&gpu, &mut color_glyph_cache);
+
+        gpu.finish_render(output, encoder);
+
+        // The glyphs on the new pages were drawn before their texture existed.
+        if grown || color_grown {
+            self.window.request_redraw();
+        }
+    }
+
+    /// Resize the surface to the new size of the window, and the grid to fill it.
+    fn resize(self: &Arc<Self>, size: PhysicalSize<u32>) {
+        // Minimized windows have no size.
+        if size.width > 0 && size.height > 0 {
+            self.gpu.lock().unwrap().resize(size);
+        }
+
+        self.resize_grid();
+    }
+
+    fn handle_editor_event(self: &Arc<Self>, event: UiEditorEvent) {
+        match event {
+            UiEditorEvent::FontChanged(fonts) => {
+                let (primary, faces, fonts) = match load_primary(fonts) {
+                    Some(loaded) => loaded,
+                    None => {
+                        log::warn!("Failed to load the faces of the guifont families");
+                        return;
+                    }
+                };
+
+                let mut metrics = self.metrics.lock().unwrap();
+                metrics.font_height = primary.spec.height.unwrap_or(DEFAULT_FONT_HEIGHT);
+                metrics.font_width = primary.spec.width;
+                match &mut metrics.fonts {
+                    Some(fallback) => fallback.set_guifont(faces, fonts),
+                    None => {
+                        let wide = metrics.wide_faces.clone();
+                        let proxy = self.proxy.lock().unwrap().clone();
+                        let redraw = move || {
+                            let _ = proxy.send_event(UiEditorEvent::Redraw);
+                        };
+                        metrics.fonts = Some(FontFallback::new(faces, fonts, wide, redraw));
+                    }
+                }
+                drop(metrics);
 
+                self.glyph_cache.lock().unwrap().clear();
+                self.color_glyph_cache.lock().unwrap().clear();
+
+                self.update_font_metrics();
+            }
+            UiEditorEvent::WideFontChanged(fonts) => {
+                let wide_faces = fonts.iter().find_map(load_faces);
+                if wide_faces.is_none() && !fonts.is_empty() {
+                    log::warn!("Failed to load the faces of the guifontwide family");
+                }
+
+                let mut metrics = self.metrics.lock().unwrap();
+                if let Some(fallback) = &mut metrics.fonts {
+                    fallback.set_wide(wide_faces.clone());
+                }
+                metrics.wide_faces = wide_faces;
+                drop(metrics);
+
+                self.window.request_redraw();
+            }
+            UiEditorEvent::LinespaceChanged => self.update_cell_metrics(),
+            UiEditorEvent::Zoom(zoom) => self.zoom(zoom),
+            UiEditorEvent::Redraw => self.window.request_redraw(),
+        }
+    }
+
+    /// Change the font size by a step, or go back to the `guifont` size.
+    fn zoom(self: &Arc<Self>, zoom: Zoom) {
         {
-            let mut quad = self.quad.lock().unwrap();
+            let mut metrics = self.metrics.lock().unwrap();
+            metrics.zoom = match zoom {
+                Zoom::In => metrics.zoom + ZOOM_STEP,
+                Zoom::Out => metrics.zoom - ZOOM_STEP,
+                Zoom::Reset => 0.0,
+            };
+        }
+
+        self.update_font_metrics();
+    }
+
+    /// Measure the primary font at the zoomed `guifont` size.
+    ///
+    /// Cells with a width set in the `guifont` are scaled with the font.
+    fn update_font_metrics(self: &Arc<Self>) {
+        let mut metrics = self.metrics.lock().unwrap();
+
+        let min_height = MIN_FONT_HEIGHT.min(metrics.font_height);
+        let height = (metrics.font_height + metrics.zoom).max(min_height);
+
+        let font_metrics = match metrics.fonts.as_ref() {
+            Some(fonts) => FontMetrics::from_face(fonts.primary().regular(), height),
+            None => return,
+        };
+        let mut font_metrics = match font_metrics {
+            Some(font_metrics) => font_metrics,
+            None => {
+                log::warn!("Failed to load the regular face of the guifont family");
+                return;
+            }
+        };
+
+        // Zooming in after reaching the minimum size makes the font bigger right away.
+        metrics.zoom = height - metrics.font_height;
+
+        if let Some(width) = metrics.font_width {
+            font_metrics = font_metrics.with_cell_width(width * height / metrics.font_height);
+        }
+        metrics.font = Some(font_metrics);
+        drop(metrics);
+
+        self.update_cell_metrics();
+    }
 
-            quad.queue(Quad::new(0.25, 0.25, 0.5, 0.5, Color::WHITE));
-            quad.render_in(&frame.view, &mut encoder, &mut gpu);
+    /// Recompute the cell geometry, resizing the grid if it changed.
+    fn update_cell_metrics(self: &Arc<Self>) {
+        let mut metrics = self.metrics.lock().unwrap();
+        let font = match metrics.font {
+            Some(font) => font,
+            None => return,
+        };
+
+        let cell = CellMetrics::new(&font, self.window.scale_factor(), self.shared.linespace());
+        if metrics.cell.replace(cell) != Some(cell) {
+            drop(metrics);
+            self.resize_grid();
         }
+    }
+
+    /// Ask Neovim to resize the grid to fill the window.
+    fn resize_grid(self: &Arc<Self>) {
+        let cell = match self.metrics.lock().unwrap().cell {
+            Some(cell) => cell,
+            None => return,
+        };
+        let (rows, cols) = cell.grid_size(self.window.size());
 
-        gpu.finish_render(frame, encoder);
+        let ui = self.clone();
+        tokio::spawn(async move {
+            if let Err(error) = ui.neovim.lock().await.ui_try_resize(cols, rows).await {
+                log::error!("Failed to resize the grid: {}", error);
+            }
+        });
     }
 }
 
+/// Link handling methods.
+impl Ui {
+    /// Move the mouse pointer to `position`, underlining the link under it.
+    ///
+    /// The window is only redrawn when the pointer moves to another link.
+    fn point_at(&self, position: PhysicalPosition<f64>) {
+        let cell = match self.metrics.lock().unwrap().cell {
+            Some(cell) => cell,
+            None => return,
+        };
+        let (row, col) = cell.cell_at(position);
+        let hovered = self
+            .reader
+            .lock()
+            .unwrap()
+            .buffer()
+            .link_at(row as usize, col as usize);
+
+        let mut links = self.links.lock().unwrap();
+        if links.hovered != hovered {
+            let icon = match hovered {
+                Some(_) => CursorIcon::Hand,
+                None => CursorIcon::Default,
+            };
+            self.window.set_cursor_icon(icon);
+
+            links.hovered = hovered;
+            self.window.request_redraw();
+        }
+    }
+
+    /// The mouse pointer left the window.
+    fn point_away(&self) {
+        if self.links.lock().unwrap().hovered.take().is_some() {
+            self.window.set_cursor_icon(CursorIcon::Default);
+            self.window.request_redraw();
+        }
+    }
+
+    /// Open the hovered link on Ctrl-click, returning if the mouse input was used.
+    ///
+    /// The release of the click that opened a link is also used.
+    fn click_link(
+        self: &Arc<Self>,
+        modifiers: ModifiersState,
+        state: ElementState,
+        button: MouseButton,
+    ) -> bool {
+        let mut links = self.links.lock().unwrap();
+
+        if let Some(held) = links.click {
+            if state == ElementState::Released && button == held {
+                links.click = None;
+            }
+            return true;
+        }
+
+        let is_ctrl_click = button == MouseButton::Left
+            && state == ElementState::Pressed
+            && modifiers == ModifiersState::CTRL;
+        let link = match &links.hovered {
+            Some(link) if is_ctrl_click => link.clone(),
+            _ => return false,
+        };
+        links.click = Some(button);
+
+        let ui = self.clone();
+        tokio::spawn(async move {
+            let mut neovim = ui.neovim.lock().await;
+            if let Err(error) = ui.opener.open(&link.target, &mut neovim).await {
+                log::error!("Failed to open {:?}: {}", link.target, error);
+            }
+        });
+
+        true
+    }
+}
+
+/// The link under the mouse pointer, underlined when drawn.
+#[derive(Debug, Default)]
+struct UiLinks {
+    hovered: Option<Link>,
+    /// The button of a Ctrl-click that opened a link, until it is released.
+    click: Option<MouseButton>,
+}
+
+/// The font faces, their metrics and the cell geometry derived from them.
+#[derive(Debug, Default)]
+struct UiMetrics {
+    /// The faces of the `guifont` and their fallbacks.
+    fonts: Option<FontFallback>,
+    /// Faces for double-width characters, from `guifontwide`.
+    wide_faces: Option<FontFaces>,
+    /// Height and cell width given by the `guifont`, in points.
+    font_height: f32,
+    font_width: Option<f32>,
+    /// Points added to the `guifont` height, kept for the whole session, even when the
+    /// `guifont` changes.
+    zoom: f32,
+    font: Option<FontMetrics>,
+    cell: Option<CellMetrics>,
+}
+
 struct UiInput {
     modifiers_state: ModifiersState,
     mouse_hold: Option<MouseButton>,
@@ -206,8 +561,14 @@ pub struct UiEventLoop {
 }
 
 impl UiEventLoop {
+    /// A proxy to deliver the editor events to the UI.
+    pub fn proxy(&self) -> EventLoopProxy<UiEditorEvent> {
+        self.event_loop.create_proxy()
+    }
+
     pub fn run(self) -> ! {
         let ui = self.ui;
+        let mut modifiers = ModifiersState::empty();
         ui.window.show_window();
         self.event_loop.run(move |event, _, control_flow| {
             *control_flow = ControlFlow::Wait;
@@ -218,7 +579,7 @@ impl UiEventLoop {
                 Event::WindowEvent { event, .. } => match event {
                     WindowEvent::Resized(new_physical_size) => {
                         // TODO: calculate margins to adjust window size to grid size.
-                        // TODO: Discover new grid size and send to neovim.
+                        ui.resize(new_physical_size);
                     }
                     WindowEvent::CloseRequested => {
                         // TODO: Send quit command to neovim.
@@ -238,34 +599,37 @@ impl UiEventLoop {
                         is_synthetic,
                         ..
                     } if !is_synthetic => {
+                        if let Some(zoom) = zoom_binding(modifiers, &input) {
+                            ui.zoom(zoom);
+                        }
                         // TODO: Handle user input.
                     }
                     WindowEvent::ReceivedCharacter(ch) => {
                         // TODO: Handle unicode char input.
                     }
                     WindowEvent::ModifiersChanged(new_modifiers_state) => {
-                        // TODO: Handle modifiers change
+                        modifiers = new_modifiers_state;
                     }
                     WindowEvent::CursorMoved { position, .. } => {
+                        ui.point_at(position);
                         // TODO: Handle mouse movement.
                     }
                     WindowEvent::CursorEntered { .. } => {
                         // TODO: Handle cursor enter
                     }
-                    WindowEvent::CursorLeft { .. } => {
-                        // TODO: Handle cursor left
-                    }
+                    WindowEvent::CursorLeft { .. } => ui.point_away(),
                     WindowEvent::MouseWheel { .. } => {
                         // TODO: Handle mouse scroll.
                     }
-                    WindowEvent::MouseInput { .. } => {
-                        // TODO: Handle mouse input
-                    }
-                    WindowEvent::ScaleFactorChanged { .. } => {
-                        // TODO: Handle dpi changes
+                    WindowEvent::MouseInput { state, button, .. } => {
+                        if !ui.click_link(modifiers, state, button) {
+                            // TODO: Handle mouse input
+                        }
                     }
+                    WindowEvent::ScaleFactorChanged { .. } => ui.update_cell_metrics(),
                     _ => {}
                 },
+                Event::UserEvent(event) => ui.handle_editor_event(event),
                 Event::Resumed => {
                     // TODO: Handle application resume
                 }
@@ -277,3 +641,23 @@ impl UiEventLoop {
         });
     }
 }
+
+/// The zoom key bindings, `Ctrl +`, `Ctrl -` and `Ctrl 0`, with `Cmd` on macOS.
+fn zoom_binding(modifiers: ModifiersState, input: &KeyboardInput) -> Option<Zoom> {
+    let modifier = if cfg!(target_os = "macos") {
+        ModifiersState::LOGO
+    } else {
+        ModifiersState::CTRL
+    };
+    // Shift is allowed, `+` needs it in most layouts.
+    if input.state != ElementState::Pressed || modifiers - ModifiersState::SHIFT != modifier {
+        return None;
+    }
+
+    match input.virtual_keycode? {
+        VirtualKeyCode::Plus | VirtualKeyCode::Equals | VirtualKeyCode::NumpadAdd => Some(Zoom::In),
+        VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => Some(Zoom::Out),
+        VirtualKeyCode::Key0 | VirtualKeyCode::Numpad0 => Some(Zoom::Reset),
+        _ => None,
+    }
+}
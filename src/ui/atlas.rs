@@ -0,0 +1,317 @@
+//! # Texture Atlas
+//!
+//! Allocation of rectangles in fixed-size square pages, used to keep the rasterized
+//! glyphs in a few GPU textures.
+//!
+//! Each page is packed with shelves: rows as tall as the first rectangle placed in
+//! them, filled from left to right. Glyphs of the same font have similar heights,
+//! so little space is lost. When all pages are full, the least recently used page
+//! is cleared and re-used, as long as it wasn't used in the current frame.
+//!
+//! This is only bookkeeping, the pixels of the pages are kept elsewhere.
+
+/// Space between rectangles, so that filtering never samples a neighbour.
+const PADDING: u32 = 1;
+/// Maximum height wasted by placing a rectangle in a taller shelf, in eighths of the
+/// rectangle height.
+const MAX_SHELF_WASTE: u32 = 4;
+
+/// A rectangle allocated in an atlas page.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AtlasRegion {
+    /// The page of the rectangle.
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The result of a successful allocation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Allocation {
+    /// The allocated rectangle.
+    pub region: AtlasRegion,
+    /// A page that was cleared to make room for the rectangle.
+    ///
+    /// All regions previously allocated in it are now invalid.
+    pub evicted: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Shelf {
+    y: u32,
+    height: u32,
+    /// Width already used by the rectangles of the shelf.
+    used: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Page {
+    shelves: Vec<Shelf>,
+    /// The frame where a rectangle of the page was last used.
+    last_used: u64,
+}
+
+impl Page {
+    fn new(frame: u64) -> Self {
+        Self {
+            shelves: Vec::new(),
+            last_used: frame,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.shelves.clear();
+    }
+
+    /// Find a place for a rectangle of `width` and `height`, already padded.
+    fn allocate(&mut self, size: u32, width: u32, height: u32) -> Option<(u32, u32)> {
+        let max_height = height + height * MAX_SHELF_WASTE / 8;
+
+        // The shortest shelf where the rectangle fits, without wasting too much.
+        let best = self
+            .shelves
+            .iter_mut()
+            .filter(|shelf| shelf.height >= height && shelf.height <= max_height)
+            .filter(|shelf| size - shelf.used >= width)
+            .min_by_key(|shelf| shelf.height);
+
+        if let Some(shelf) = best {
+            let x = shelf.used;
+            shelf.used += width;
+            return Some((x, shelf.y));
+        }
+
+        let y = self
+            .shelves
+            .last()
+            .map_or(0, |shelf| shelf.y + shelf.height);
+        if size - y < height {
+            return None;
+        }
+
+        self.shelves.push(Shelf {
+            y,
+            height,
+            used: width,
+        });
+
+        Some((0, y))
+    }
+}
+
+/// An atlas of square pages of `page_size` pixels.
+#[derive(Debug, Clone)]
+pub struct Atlas {
+    page_size: u32,
+    max_pages: usize,
+    pages: Vec<Page>,
+    frame: u64,
+}
+
+impl Atlas {
+    /// An empty atlas, which will allocate up to `max_pages` pages.
+    pub fn new(page_size: u32, max_pages: usize) -> Self {
+        Self {
+            page_size,
+            max_pages: max_pages.max(1),
+            pages: Vec::new(),
+            frame: 0,
+        }
+    }
+
+    /// Size of the sides of the pages.
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    /// Number of pages in use.
+    #[cfg(test)]
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Start a new frame.
+    ///
+    /// Pages used in the current frame are never evicted, as their regions may
+    /// already be queued for drawing.
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Mark a page as used in the current frame.
+    pub fn touch(&mut self, page: usize) {
+        if let Some(page) = self.pages.get_mut(page) {
+            page.last_used = self.frame;
+        }
+    }
+
+    /// Allocate a rectangle of `width` and `height`.
+    ///
+    /// Returns `None` if the rectangle is bigger than a page, or if all pages are
+    /// full and used in the current frame.
+    pub fn allocate(&mut self, width: u32, height: u32) -> Option<Allocation> {
+        let (padded_width, padded_height) = (width + PADDING, height + PADDING);
+        if padded_width > self.page_size || padded_height > self.page_size {
+            return None;
+        }
+
+        let (size, frame) = (self.page_size, self.frame);
+        let region = |page: usize, (x, y): (u32, u32)| AtlasRegion {
+            page,
+            x,
+            y,
+            width,
+            height,
+        };
+
+        // Prefer the most recently used pages, they are the most likely to have room.
+        let mut by_recency: Vec<usize> = (0..self.pages.len()).collect();
+        by_recency.sort_by_key(|idx| std::cmp::Reverse(self.pages[*idx].last_used));
+
+        for idx in by_recency {
+            let page = &mut self.pages[idx];
+
+            if let Some(position) = page.allocate(size, padded_width, padded_height) {
+                page.last_used = frame;
+                return Some(Allocation {
+                    region: region(idx, position),
+                    evicted: None,
+                });
+            }
+        }
+
+        if self.pages.len() < self.max_pages {
+            let mut page = Page::new(frame);
+            let position = page.allocate(size, padded_width, padded_height)?;
+            self.pages.push(page);
+
+            return Some(Allocation {
+                region: region(self.pages.len() - 1, position),
+                evicted: None,
+            });
+        }
+
+        let (idx, lru) = self
+            .pages
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, page)| page.last_used < frame)
+            .min_by_key(|(_, page)| page.last_used)?;
+
+        lru.clear();
+        lru.last_used = frame;
+        let position = lru.allocate(size, padded_width, padded_height)?;
+
+        Some(Allocation {
+            region: region(idx, position),
+            evicted: Some(idx),
+        })
+    }
+
+    /// Free all pages.
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(allocation: Option<Allocation>) -> Option<(usize, u32, u32)> {
+        allocation.map(|allocation| {
+            let region = allocation.region;
+            (region.page, region.x, region.y)
+        })
+    }
+
+    #[test]
+    fn rectangles_share_shelves() {
+        let mut atlas = Atlas::new(64, 1);
+
+        assert_eq!(position(atlas.allocate(10, 10)), Some((0, 0, 0)));
+        assert_eq!(position(atlas.allocate(10, 10)), Some((0, 11, 0)));
+        // Taller than the shelf, it gets its own.
+        assert_eq!(position(atlas.allocate(10, 12)), Some((0, 0, 11)));
+        // Shorter, but within `MAX_SHELF_WASTE` of the shortest shelf.
+        assert_eq!(position(atlas.allocate(10, 8)), Some((0, 22, 0)));
+    }
+
+    #[test]
+    fn short_rectangles_dont_waste_tall_shelves() {
+        let mut atlas = Atlas::new(64, 1);
+        atlas.allocate(10, 20);
+
+        // 5 pixels padded, at most 7 with the waste allowed, the 21 pixel shelf is
+        // too tall.
+        assert_eq!(position(atlas.allocate(10, 4)), Some((0, 0, 21)));
+        // The limit itself is accepted: 14 padded pixels, up to 21.
+        assert_eq!(position(atlas.allocate(10, 13)), Some((0, 11, 0)));
+    }
+
+    #[test]
+    fn full_shelves_start_new_ones() {
+        let mut atlas = Atlas::new(32, 1);
+
+        assert_eq!(position(atlas.allocate(20, 4)), Some((0, 0, 0)));
+        assert_eq!(position(atlas.allocate(20, 4)), Some((0, 0, 5)));
+    }
+
+    #[test]
+    fn pages_are_added_up_to_the_maximum() {
+        let mut atlas = Atlas::new(32, 2);
+
+        assert_eq!(position(atlas.allocate(31, 31)), Some((0, 0, 0)));
+        assert_eq!(position(atlas.allocate(31, 31)), Some((1, 0, 0)));
+        assert_eq!(atlas.pages(), 2);
+
+        // All pages are full and were used in this frame.
+        assert_eq!(atlas.allocate(1, 1), None);
+        assert_eq!(atlas.pages(), 2);
+    }
+
+    #[test]
+    fn rectangles_bigger_than_a_page_are_rejected() {
+        let mut atlas = Atlas::new(32, 2);
+
+        assert_eq!(atlas.allocate(32, 1), None);
+        assert_eq!(atlas.allocate(1, 32), None);
+        assert_eq!(atlas.pages(), 0);
+    }
+
+    #[test]
+    fn the_least_recently_used_page_is_evicted() {
+        let mut atlas = Atlas::new(32, 2);
+
+        atlas.begin_frame();
+        atlas.allocate(31, 31);
+        atlas.begin_frame();
+        atlas.allocate(31, 31);
+
+        atlas.begin_frame();
+        let allocation = atlas.allocate(31, 31).unwrap();
+        assert_eq!(allocation.evicted, Some(0));
+        assert_eq!(allocation.region.page, 0);
+    }
+
+    #[test]
+    fn pages_touched_in_this_frame_are_never_evicted() {
+        let mut atlas = Atlas::new(32, 2);
+
+        atlas.begin_frame();
+        atlas.allocate(31, 31);
+        atlas.begin_frame();
+        atlas.allocate(31, 31);
+
+        // Page 0 is the least recently used, but it is drawn in this frame.
+        atlas.begin_frame();
+        atlas.touch(0);
+        let allocation = atlas.allocate(31, 31).unwrap();
+        assert_eq!(allocation.evicted, Some(1));
+
+        // Both pages are now used in this frame.
+        assert_eq!(atlas.allocate(31, 31), None);
+    }
+}
@@ -0,0 +1,592 @@
+//! # Box Drawing
+//!
+//! Box drawing (U+2500 to U+257F), block elements (U+2580 to U+259F) and the powerline
+//! separators (U+E0B0 to U+E0B7) are drawn procedurally, instead of with the glyphs of
+//! the font. Font glyphs are designed for the line height of the font, so they leave
+//! gaps between rows when `linespace` is set, and rarely line up between fonts.
+//!
+//! The glyphs are rasterized to exactly fill the cells, with the light strokes as thick
+//! as the underline, and go to the same [`GlyphCache`] as the font glyphs.
+//!
+//! [`GlyphCache`]: super::glyph_cache::GlyphCache
+use super::glyph_cache::RasterizedGlyph;
+use super::metrics::CellMetrics;
+
+/// Number of samples per pixel side used to anti-alias curves and diagonals.
+const SAMPLES: u32 = 4;
+
+/// Is `chr` drawn by [`rasterize`] instead of the font?
+pub fn is_procedural(chr: char) -> bool {
+    matches!(chr, '\u{2500}'..='\u{259F}' | '\u{E0B0}'..='\u{E0B7}')
+}
+
+/// Rasterize `chr` to fill `cols` cells.
+///
+/// Returns `None` for characters that aren't drawn procedurally.
+pub fn rasterize(chr: char, cols: u32, cell: &CellMetrics) -> Option<RasterizedGlyph> {
+    if !is_procedural(chr) {
+        return None;
+    }
+
+    let mut canvas = Canvas::new(cell.width * cols.max(1), cell.height);
+    let strokes = Strokes {
+        light: cell.underline_thickness.max(1),
+        heavy: cell.underline_thickness.max(1) * 2,
+    };
+
+    match chr {
+        '\u{2504}' | '\u{2505}' | '\u{2508}' | '\u{2509}' | '\u{254C}' | '\u{254D}' => {
+            canvas.draw_dashes(chr, strokes, true)
+        }
+        '\u{2506}' | '\u{2507}' | '\u{250A}' | '\u{250B}' | '\u{254E}' | '\u{254F}' => {
+            canvas.draw_dashes(chr, strokes, false)
+        }
+        '\u{256D}' => canvas.draw_arc(1.0, 1.0, strokes),
+        '\u{256E}' => canvas.draw_arc(-1.0, 1.0, strokes),
+        '\u{256F}' => canvas.draw_arc(-1.0, -1.0, strokes),
+        '\u{2570}' => canvas.draw_arc(1.0, -1.0, strokes),
+        '\u{2571}' => canvas.draw_diagonal(false, strokes),
+        '\u{2572}' => canvas.draw_diagonal(true, strokes),
+        '\u{2573}' => {
+            canvas.draw_diagonal(false, strokes);
+            canvas.draw_diagonal(true, strokes);
+        }
+        '\u{2500}'..='\u{257F}' => canvas.draw_lines(LINES[chr as usize - 0x2500], strokes),
+        '\u{2580}'..='\u{259F}' => canvas.draw_block(chr),
+        _ => canvas.draw_powerline(chr, strokes),
+    }
+
+    Some(RasterizedGlyph {
+        width: canvas.width,
+        height: canvas.height,
+        left: 0,
+        top: cell.baseline as i32,
+        pixels: canvas.pixels,
+    })
+}
+
+/// The weight of an arm of a box drawing character.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Weight {
+    None,
+    Light,
+    Heavy,
+    Double,
+}
+
+const O: Weight = Weight::None;
+const L: Weight = Weight::Light;
+const H: Weight = Weight::Heavy;
+const D: Weight = Weight::Double;
+
+/// The up, right, down and left arms of U+2500 to U+257F.
+///
+/// Dashes are listed as solid lines, and arcs and diagonals as empty, as they are drawn
+/// differently.
+#[rustfmt::skip]
+const LINES: [[Weight; 4]; 128] = [
+    // ─ ━ │ ┃ ┄ ┅ ┆ ┇
+    [O, L, O, L], [O, H, O, H], [L, O, L, O], [H, O, H, O],
+    [O, L, O, L], [O, H, O, H], [L, O, L, O], [H, O, H, O],
+    // ┈ ┉ ┊ ┋ ┌ ┍ ┎ ┏
+    [O, L, O, L], [O, H, O, H], [L, O, L, O], [H, O, H, O],
+    [O, L, L, O], [O, H, L, O], [O, L, H, O], [O, H, H, O],
+    // ┐ ┑ ┒ ┓ └ ┕ ┖ ┗
+    [O, O, L, L], [O, O, L, H], [O, O, H, L], [O, O, H, H],
+    [L, L, O, O], [L, H, O, O], [H, L, O, O], [H, H, O, O],
+    // ┘ ┙ ┚ ┛ ├ ┝ ┞ ┟
+    [L, O, O, L], [L, O, O, H], [H, O, O, L], [H, O, O, H],
+    [L, L, L, O], [L, H, L, O], [H, L, L, O], [L, L, H, O],
+    // ┠ ┡ ┢ ┣ ┤ ┥ ┦ ┧
+    [H, L, H, O], [H, H, L, O], [L, H, H, O], [H, H, H, O],
+    [L, O, L, L], [L, O, L, H], [H, O, L, L], [L, O, H, L],
+    // ┨ ┩ ┪ ┫ ┬ ┭ ┮ ┯
+    [H, O, H, L], [H, O, L, H], [L, O, H, H], [H, O, H, H],
+    [O, L, L, L], [O, L, L, H], [O, H, L, L], [O, H, L, H],
+    // ┰ ┱ ┲ ┳ ┴ ┵ ┶ ┷
+    [O, L, H, L], [O, L, H, H], [O, H, H, L], [O, H, H, H],
+    [L, L, O, L], [L, L, O, H], [L, H, O, L], [L, H, O, H],
+    // ┸ ┹ ┺ ┻ ┼ ┽ ┾ ┿
+    [H, L, O, L], [H, L, O, H], [H, H, O, L], [H, H, O, H],
+    [L, L, L, L], [L, L, L, H], [L, H, L, L], [L, H, L, H],
+    // ╀ ╁ ╂ ╃ ╄ ╅ ╆ ╇
+    [H, L, L, L], [L, L, H, L], [H, L, H, L], [H, L, L, H],
+    [H, H, L, L], [L, L, H, H], [L, H, H, L], [H, H, L, H],
+    // ╈ ╉ ╊ ╋ ╌ ╍ ╎ ╏
+    [L, H, H, H], [H, L, H, H], [H, H, H, L], [H, H, H, H],
+    [O, L, O, L], [O, H, O, H], [L, O, L, O], [H, O, H, O],
+    // ═ ║ ╒ ╓ ╔ ╕ ╖ ╗
+    [O, D, O, D], [D, O, D, O], [O, D, L, O], [O, L, D, O],
+    [O, D, D, O], [O, O, L, D], [O, O, D, L], [O, O, D, D],
+    // ╘ ╙ ╚ ╛ ╜ ╝ ╞ ╟
+    [L, D, O, O], [D, L, O, O], [D, D, O, O], [L, O, O, D],
+    [D, O, O, L], [D, O, O, D], [L, D, L, O], [D, L, D, O],
+    // ╠ ╡ ╢ ╣ ╤ ╥ ╦ ╧
+    [D, D, D, O], [L, O, L, D], [D, O, D, L], [D, O, D, D],
+    [O, D, L, D], [O, L, D, L], [O, D, D, D], [L, D, O, D],
+    // ╨ ╩ ╪ ╫ ╬ ╭ ╮ ╯
+    [D, L, O, L], [D, D, O, D], [L, D, L, D], [D, L, D, L],
+    [D, D, D, D], [O, O, O, O], [O, O, O, O], [O, O, O, O],
+    // ╰ ╱ ╲ ╳ ╴ ╵ ╶ ╷
+    [O, O, O, O], [O, O, O, O], [O, O, O, O], [O, O, O, O],
+    [O, O, O, L], [L, O, O, O], [O, L, O, O], [O, O, L, O],
+    // ╸ ╹ ╺ ╻ ╼ ╽ ╾ ╿
+    [O, O, O, H], [H, O, O, O], [O, H, O, O], [O, O, H, O],
+    [O, H, O, L], [L, O, H, O], [O, L, O, H], [H, O, L, O],
+];
+
+/// Thickness of the strokes, in pixels.
+#[derive(Debug, Copy, Clone)]
+struct Strokes {
+    light: u32,
+    heavy: u32,
+}
+
+impl Strokes {
+    /// The lines of a stroke of `weight` centered in `size` pixels, as `(start, thickness)`.
+    ///
+    /// Double strokes are two light lines separated by a light gap.
+    fn lines(self, weight: Weight, size: u32) -> Vec<(u32, u32)> {
+        let centered = |thickness: u32| (size.saturating_sub(thickness) / 2, thickness);
+
+        match weight {
+            Weight::None => Vec::new(),
+            Weight::Light => vec![centered(self.light)],
+            Weight::Heavy => vec![centered(self.heavy)],
+            Weight::Double => {
+                let (start, _) = centered(self.light * 3);
+                vec![(start, self.light), (start + self.light * 2, self.light)]
+            }
+        }
+    }
+
+    /// The first and last pixel, exclusive, covered by a stroke of `weight`.
+    fn span(self, weight: Weight, size: u32) -> (u32, u32) {
+        let lines = self.lines(weight, size);
+
+        match (lines.first(), lines.last()) {
+            (Some(first), Some(last)) => (first.0, last.0 + last.1),
+            _ => (size / 2, size / 2),
+        }
+    }
+}
+
+/// An 8-bit coverage mask.
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize],
+        }
+    }
+
+    /// Fill the pixels in `x0..x1` and `y0..y1` with `coverage`.
+    fn fill_rect(&mut self, x0: u32, y0: u32, x1: u32, y1: u32, coverage: u8) {
+        let (x1, y1) = (x1.min(self.width), y1.min(self.height));
+
+        for y in y0.min(y1)..y1 {
+            let row = (y * self.width) as usize;
+            for pixel in &mut self.pixels[row + x0.min(x1) as usize..row + x1 as usize] {
+                *pixel = (*pixel).max(coverage);
+            }
+        }
+    }
+
+    /// Fill the area where `inside` holds, anti-aliased by sampling each pixel.
+    fn fill_with(&mut self, inside: impl Fn(f32, f32) -> bool) {
+        let step = 1.0 / SAMPLES as f32;
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut hits = 0;
+                for sample_y in 0..SAMPLES {
+                    for sample_x in 0..SAMPLES {
+                        let px = x as f32 + (sample_x as f32 + 0.5) * step;
+                        let py = y as f32 + (sample_y as f32 + 0.5) * step;
+                        hits += inside(px, py) as u32;
+                    }
+                }
+
+                let pixel = &mut self.pixels[(y * self.width + x) as usize];
+                *pixel = (*pixel).max((hits * 255 / (SAMPLES * SAMPLES)) as u8);
+            }
+        }
+    }
+
+    /// Draw the arms of a box drawing character, given as up, right, down and left.
+    fn draw_lines(&mut self, [up, right, down, left]: [Weight; 4], strokes: Strokes) {
+        let (width, height) = (self.width, self.height);
+
+        for (weight, opposite, positive) in [(right, left, true), (left, right, false)] {
+            for (along, across) in arm(
+                weight,
+                opposite,
+                [up, down],
+                positive,
+                width,
+                height,
+                strokes,
+            ) {
+                self.fill_rect(along.0, across.0, along.1, across.1, 255);
+            }
+        }
+
+        for (weight, opposite, positive) in [(down, up, true), (up, down, false)] {
+            for (along, across) in arm(
+                weight,
+                opposite,
+                [left, right],
+                positive,
+                height,
+                width,
+                strokes,
+            ) {
+                self.fill_rect(across.0, along.0, across.1, along.1, 255);
+            }
+        }
+    }
+
+    /// Draw the dashed lines, crossing the whole cell.
+    fn draw_dashes(&mut self, chr: char, strokes: Strokes, horizontal: bool) {
+        let (dashes, weight) = match chr {
+            '\u{2504}' | '\u{2506}' => (3, L),
+            '\u{2505}' | '\u{2507}' => (3, H),
+            '\u{2508}' | '\u{250A}' => (4, L),
+            '\u{2509}' | '\u{250B}' => (4, H),
+            '\u{254C}' | '\u{254E}' => (2, L),
+            _ => (2, H),
+        };
+
+        let (length, breadth) = if horizontal {
+            (self.width, self.height)
+        } else {
+            (self.height, self.width)
+        };
+        let (start, end) = strokes.span(weight, breadth);
+        let segment = length as f32 / dashes as f32;
+        // Gaps are split between both ends of the dashes, so they repeat across cells.
+        let gap = segment / 3.0;
+
+        for dash in 0..dashes {
+            let from = (dash as f32 * segment + gap / 2.0).round() as u32;
+            let to = ((dash + 1) as f32 * segment - gap / 2.0).round() as u32;
+
+            if horizontal {
+                self.fill_rect(from, start, to.max(from + 1), end, 255);
+            } else {
+                self.fill_rect(start, from, end, to.max(from + 1), 255);
+            }
+        }
+    }
+
+    /// Draw a rounded corner, joining the arm towards `dx` horizontally and the arm
+    /// towards `dy` vertically.
+    fn draw_arc(&mut self, dx: f32, dy: f32, strokes: Strokes) {
+        let (width, height) = (self.width, self.height);
+        let (x0, thickness) = strokes.lines(L, width)[0];
+        let (y0, _) = strokes.lines(L, height)[0];
+
+        // Center of the straight strokes.
+        let half = thickness as f32 / 2.0;
+        let (stroke_x, stroke_y) = (x0 as f32 + half, y0 as f32 + half);
+
+        let room_x = if dx > 0.0 {
+            width as f32 - stroke_x
+        } else {
+            stroke_x
+        };
+        let room_y = if dy > 0.0 {
+            height as f32 - stroke_y
+        } else {
+            stroke_y
+        };
+        let radius = room_x.min(room_y);
+        let (center_x, center_y) = (stroke_x + dx * radius, stroke_y + dy * radius);
+
+        self.fill_with(|x, y| {
+            let (rel_x, rel_y) = (x - center_x, y - center_y);
+            // Only the quarter of the circle facing the corner.
+            if rel_x * dx > 0.0 || rel_y * dy > 0.0 {
+                return false;
+            }
+
+            (rel_x.hypot(rel_y) - radius).abs() <= half
+        });
+
+        // Straight lines from the ends of the arc to the edges of the cell.
+        let center_x = center_x.round() as u32;
+        let center_y = center_y.round() as u32;
+        if dx > 0.0 {
+            self.fill_rect(center_x, y0, width, y0 + thickness, 255);
+        } else {
+            self.fill_rect(0, y0, center_x, y0 + thickness, 255);
+        }
+        if dy > 0.0 {
+            self.fill_rect(x0, center_y, x0 + thickness, height, 255);
+        } else {
+            self.fill_rect(x0, 0, x0 + thickness, center_y, 255);
+        }
+    }
+
+    /// Draw a diagonal from corner to corner, descending from left to right or not.
+    fn draw_diagonal(&mut self, descending: bool, strokes: Strokes) {
+        let (width, height) = (self.width as f32, self.height as f32);
+        let length = width.hypot(height);
+        let half = strokes.light as f32 / 2.0;
+
+        self.fill_with(|x, y| {
+            let y = if descending { y } else { height - y };
+            // Distance to the line from (0, 0) to (width, height).
+            (x * height - y * width).abs() / length <= half
+        });
+    }
+
+    /// Draw a block element.
+    fn draw_block(&mut self, chr: char) {
+        let (width, height) = (self.width, self.height);
+        let eighths = |size: u32, n: u32| (size * n + 4) / 8;
+        let (half_width, half_height) = (eighths(width, 4), eighths(height, 4));
+
+        match chr {
+            // ▀
+            '\u{2580}' => self.fill_rect(0, 0, width, half_height, 255),
+            // ▁ to █, lower eighths.
+            '\u{2581}'..='\u{2588}' => {
+                let n = chr as u32 - 0x2580;
+                self.fill_rect(0, height - eighths(height, n), width, height, 255)
+            }
+            // ▉ to ▏, left eighths.
+            '\u{2589}'..='\u{258F}' => {
+                let n = 0x2590 - chr as u32;
+                self.fill_rect(0, 0, eighths(width, n), height, 255)
+            }
+            // ▐
+            '\u{2590}' => self.fill_rect(half_width, 0, width, height, 255),
+            // ░ ▒ ▓
+            '\u{2591}'..='\u{2593}' => {
+                let coverage = (chr as u32 - 0x2590) * 64;
+                self.fill_rect(0, 0, width, height, coverage as u8)
+            }
+            // ▔
+            '\u{2594}' => self.fill_rect(0, 0, width, eighths(height, 1), 255),
+            // ▕
+            '\u{2595}' => self.fill_rect(width - eighths(width, 1), 0, width, height, 255),
+            // ▖ to ▟, quadrants.
+            _ => {
+                // Upper left, upper right, lower left and lower right.
+                let quadrants = match chr {
+                    '\u{2596}' => [false, false, true, false],
+                    '\u{2597}' => [false, false, false, true],
+                    '\u{2598}' => [true, false, false, false],
+                    '\u{2599}' => [true, false, true, true],
+                    '\u{259A}' => [true, false, false, true],
+                    '\u{259B}' => [true, true, true, false],
+                    '\u{259C}' => [true, true, false, true],
+                    '\u{259D}' => [false, true, false, false],
+                    '\u{259E}' => [false, true, true, false],
+                    _ => [false, true, true, true],
+                };
+
+                let rects = [
+                    (0, 0, half_width, half_height),
+                    (half_width, 0, width, half_height),
+                    (0, half_height, half_width, height),
+                    (half_width, half_height, width, height),
+                ];
+                for (filled, (x0, y0, x1, y1)) in quadrants.iter().zip(rects) {
+                    if *filled {
+                        self.fill_rect(x0, y0, x1, y1, 255);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw a powerline separator, pointing right or left.
+    fn draw_powerline(&mut self, chr: char, strokes: Strokes) {
+        let (width, height) = (self.width as f32, self.height as f32);
+        let half_height = height / 2.0;
+        let thickness = strokes.light as f32;
+        let pointing_left = matches!(chr, '\u{E0B2}' | '\u{E0B3}' | '\u{E0B6}' | '\u{E0B7}');
+
+        // Work with separators pointing right, mirroring the others.
+        let mirror = |x: f32| if pointing_left { width - x } else { x };
+
+        match chr {
+            // Solid triangles.
+            '\u{E0B0}' | '\u{E0B2}' => self
+                .fill_with(|x, y| mirror(x) / width + (y - half_height).abs() / half_height <= 1.0),
+            // Thin chevrons, the two sides of the triangle.
+            '\u{E0B1}' | '\u{E0B3}' => {
+                let length = width.hypot(half_height);
+                self.fill_with(|x, y| {
+                    let (x, y) = (mirror(x), (y - half_height).abs());
+                    // Distance to the line from (0, half_height) to (width, 0).
+                    (x * half_height + y * width - width * half_height).abs() / length
+                        <= thickness / 2.0
+                })
+            }
+            // Solid half circles.
+            '\u{E0B4}' | '\u{E0B6}' => self.fill_with(|x, y| {
+                (mirror(x) / width).powi(2) + ((y - half_height) / half_height).powi(2) <= 1.0
+            }),
+            // Thin half circles.
+            _ => {
+                let (inner_width, inner_height) = (width - thickness, half_height - thickness);
+                self.fill_with(|x, y| {
+                    let (x, y) = (mirror(x), y - half_height);
+                    let outer = (x / width).powi(2) + (y / half_height).powi(2) <= 1.0;
+                    let inner = (x / inner_width).powi(2) + (y / inner_height).powi(2) <= 1.0;
+                    outer && !inner
+                })
+            }
+        }
+    }
+}
+
+/// The rectangles of an arm of a box drawing character, as `(along, across)` ranges.
+///
+/// The arm goes along an axis of `length` pixels, towards its end if `positive`, and
+/// is centered across `breadth` pixels. `opposite` is the weight of the arm going the
+/// other way, and `perpendicular` of the arms crossing the axis, before and after the
+/// arm.
+fn arm(
+    weight: Weight,
+    opposite: Weight,
+    perpendicular: [Weight; 2],
+    positive: bool,
+    length: u32,
+    breadth: u32,
+    strokes: Strokes,
+) -> Vec<((u32, u32), (u32, u32))> {
+    let [before, after] = perpendicular;
+    let crossing = |weight: Weight| strokes.span(weight, length);
+    // Where the inner lines of a double perpendicular stroke are.
+    let inner_double = || {
+        let lines = strokes.lines(D, length);
+        (lines[1].0, lines[0].0 + lines[0].1)
+    };
+
+    let lines = strokes.lines(weight, breadth);
+
+    lines
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (start, thickness))| {
+            // Where positive arms start, and negative arms end.
+            let (from, to) = if before == O && after == O {
+                crossing(weight)
+            } else if weight == D {
+                // Each line joins the perpendicular stroke on its side, or goes around
+                // the other one.
+                let (facing, other) = if idx == 0 {
+                    (before, after)
+                } else {
+                    (after, before)
+                };
+                match facing {
+                    D => inner_double(),
+                    O => crossing(other),
+                    _ => crossing(facing),
+                }
+            } else if opposite != O || (before != D && after != D) {
+                // Single lines go through the perpendicular strokes.
+                [before, after]
+                    .iter()
+                    .filter(|w| **w != O)
+                    .map(|w| crossing(*w))
+                    .fold((length, 0), |(from, to), span| {
+                        (from.min(span.0), to.max(span.1))
+                    })
+            } else {
+                // Ending at a double stroke, without going through it.
+                inner_double()
+            };
+
+            let along = if positive { (from, length) } else { (0, to) };
+            (along, (start, start + thickness))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CELL: CellMetrics = CellMetrics {
+        width: 10,
+        height: 20,
+        baseline: 16,
+        ascent: 14.0,
+        descent: 4.0,
+        underline_position: 18,
+        underline_thickness: 1,
+        strikethrough_position: 11,
+        strikethrough_thickness: 1,
+        pixels_per_em: 16.0,
+    };
+
+    fn rows(chr: char) -> Vec<Vec<u8>> {
+        let glyph = rasterize(chr, 1, &CELL).unwrap();
+        assert_eq!((glyph.width, glyph.height), (CELL.width, CELL.height));
+
+        glyph
+            .pixels
+            .chunks(glyph.width as usize)
+            .map(<[u8]>::to_vec)
+            .collect()
+    }
+
+    #[test]
+    fn horizontal_lines_cross_the_whole_cell() {
+        let rows = rows('─');
+
+        for (y, row) in rows.iter().enumerate() {
+            let coverage = if y == 9 { 255 } else { 0 };
+            assert!(row.iter().all(|pixel| *pixel == coverage), "row {}", y);
+        }
+    }
+
+    #[test]
+    fn full_blocks_fill_the_cell() {
+        assert!(rows('█').iter().flatten().all(|pixel| *pixel == 255));
+    }
+
+    #[test]
+    fn right_half_blocks_fill_the_right_half() {
+        for row in rows('▐') {
+            assert_eq!(row, [0, 0, 0, 0, 0, 255, 255, 255, 255, 255]);
+        }
+    }
+
+    #[test]
+    fn double_lines_do_not_cross() {
+        let rows = rows('╬');
+
+        // Double lines are on rows 8 and 10, and columns 3 and 5.
+        assert!(rows[9].iter().all(|pixel| *pixel == 0));
+        assert!(rows.iter().all(|row| row[4] == 0));
+
+        for y in [8, 10] {
+            let filled = (0..10).filter(|x| rows[y][*x] == 255).count();
+            assert_eq!(filled, 9, "row {}", y);
+        }
+        for x in [3, 5] {
+            let filled = rows.iter().filter(|row| row[x] == 255).count();
+            assert_eq!(filled, 19, "column {}", x);
+        }
+    }
+
+    #[test]
+    fn other_characters_are_not_procedural() {
+        assert!(rasterize('a', 1, &CELL).is_none());
+        assert!(!is_procedural('a'));
+    }
+}
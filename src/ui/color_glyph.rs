@@ -0,0 +1,436 @@
+//! # Color Glyphs
+//!
+//! Emoji fonts draw their glyphs in color, either as PNG bitmaps, in the `CBDT` or
+//! `sbix` tables, or as layers of outlines filled with the colors of a palette, in the
+//! `COLR` and `CPAL` tables.
+//!
+//! Color glyphs are rasterized as premultiplied RGBA and go to a [`GlyphCache`] of
+//! their own, separate from the coverage masks. They are drawn with their own colors,
+//! never tinted with the color of the text. Glyphs of color fonts without color data
+//! are rasterized as coverage masks, like the glyphs of any other font.
+//!
+//! Bitmaps come in a few fixed sizes, the closest one is scaled to the size of the
+//! text. Layers in the color of the text are drawn with the foreground of the
+//! [`GlyphKey`].
+//!
+//! [`GlyphCache`]: super::glyph_cache::GlyphCache
+use super::font::FontFace;
+use super::glyph_cache::{rasterize_mask, GlyphKey, RasterizedGlyph};
+use font_kit::font::Font;
+use ttf_parser::{GlyphId, RasterImageFormat, TableName, Tag};
+
+const COLR_TAG: Tag = Tag::from_bytes(b"COLR");
+const CPAL_TAG: Tag = Tag::from_bytes(b"CPAL");
+/// Palette index of the layers drawn in the color of the text.
+const FOREGROUND_PALETTE_INDEX: u16 = 0xFFFF;
+
+/// Does the face have color glyphs?
+pub fn is_color_face(face: &ttf_parser::Face<'_>) -> bool {
+    face.has_table(TableName::ColorBitmapData)
+        || face.has_table(TableName::StandardBitmapGraphics)
+        || face.table_data(COLR_TAG).is_some()
+}
+
+/// Rasterize the glyph of `key` from its bitmap, if `face` has one.
+///
+/// Bitmaps don't need the face to be loaded, font-kit can't load most bitmap fonts.
+pub fn rasterize_bitmap(face: &FontFace, key: &GlyphKey) -> Option<RasterizedGlyph> {
+    let parsed = face.parsed()?;
+    let image = parsed.glyph_raster_image(
+        GlyphId(key.glyph as u16),
+        key.pixels_per_em().ceil() as u16,
+    )?;
+
+    decode_bitmap(image, key)
+}
+
+/// Rasterize the glyph of `key` from its layers, if `face` has some, with `font` being
+/// `face` loaded.
+pub fn rasterize_layers(face: &FontFace, font: &Font, key: &GlyphKey) -> Option<RasterizedGlyph> {
+    let parsed = face.parsed()?;
+    let colr = parsed.table_data(COLR_TAG)?;
+    let layers = color_layers(colr, key.glyph as u16)?;
+    let cpal = parsed.table_data(CPAL_TAG);
+
+    fill_layers(font, &layers, cpal, key)
+}
+
+/// Decode a bitmap glyph and scale it to the size of `key`.
+fn decode_bitmap(
+    image: ttf_parser::RasterGlyphImage<'_>,
+    key: &GlyphKey,
+) -> Option<RasterizedGlyph> {
+    let (width, height, pixels) = match image.format {
+        RasterImageFormat::PNG => decode_png(image.data)?,
+    };
+
+    let scale = key.pixels_per_em() / image.pixels_per_em as f32;
+    let glyph = RasterizedGlyph {
+        width,
+        height,
+        left: image.x as i32,
+        // The offsets are from the origin to the bottom left corner of the image.
+        top: image.y as i32 + height as i32,
+        pixels,
+    };
+
+    Some(scale_rgba(glyph, scale))
+}
+
+/// Decode a PNG image to premultiplied RGBA.
+fn decode_png(data: &[u8]) -> Option<(u32, u32, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+    let (info, mut reader) = match decoder.read_info() {
+        Ok(decoded) => decoded,
+        Err(error) => {
+            log::warn!("Failed to decode a color glyph: {}", error);
+            return None;
+        }
+    };
+
+    let mut buffer = vec![0; info.buffer_size()];
+    if let Err(error) = reader.next_frame(&mut buffer) {
+        log::warn!("Failed to decode a color glyph: {}", error);
+        return None;
+    }
+
+    let to_rgba = |pixel: &[u8]| match info.color_type {
+        png::ColorType::Grayscale => [pixel[0], pixel[0], pixel[0], 255],
+        png::ColorType::GrayscaleAlpha => [pixel[0], pixel[0], pixel[0], pixel[1]],
+        png::ColorType::RGB => [pixel[0], pixel[1], pixel[2], 255],
+        // Indexed images are expanded to RGB or RGBA.
+        png::ColorType::RGBA | png::ColorType::Indexed => [pixel[0], pixel[1], pixel[2], pixel[3]],
+    };
+
+    let bytes_per_pixel = info.color_type.samples();
+    let row_bytes = info.width as usize * bytes_per_pixel;
+    let mut pixels = Vec::with_capacity((info.width * info.height * 4) as usize);
+
+    for row in buffer.chunks(info.line_size).take(info.height as usize) {
+        for pixel in row[..row_bytes].chunks_exact(bytes_per_pixel) {
+            pixels.extend_from_slice(&premultiply(to_rgba(pixel)));
+        }
+    }
+
+    Some((info.width, info.height, pixels))
+}
+
+/// Resize `glyph` by `scale`, averaging the pixels covered by each new pixel.
+fn scale_rgba(glyph: RasterizedGlyph, scale: f32) -> RasterizedGlyph {
+    if (scale - 1.0).abs() < f32::EPSILON {
+        return glyph;
+    }
+
+    let width = ((glyph.width as f32 * scale).round() as u32).max(1);
+    let height = ((glyph.height as f32 * scale).round() as u32).max(1);
+    let (src_width, src_height) = (glyph.width as f32, glyph.height as f32);
+
+    // The source pixels covered by `dst` of the `dst_size` pixels of the new size.
+    let footprint = |dst: u32, dst_size: u32, src_size: f32| {
+        let start = (dst as f32 * src_size / dst_size as f32).floor() as usize;
+        let end = ((dst + 1) as f32 * src_size / dst_size as f32).ceil() as usize;
+        start..end.max(start + 1).min(src_size as usize)
+    };
+
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        let rows = footprint(y, height, src_height);
+        for x in 0..width {
+            let cols = footprint(x, width, src_width);
+
+            let mut sum = [0u32; 4];
+            let mut count = 0;
+            for row in rows.clone() {
+                for col in cols.clone() {
+                    let idx = (row * glyph.width as usize + col) * 4;
+                    for (channel, value) in sum.iter_mut().zip(&glyph.pixels[idx..idx + 4]) {
+                        *channel += *value as u32;
+                    }
+                    count += 1;
+                }
+            }
+
+            pixels.extend(sum.iter().map(|channel| (channel / count.max(1)) as u8));
+        }
+    }
+
+    RasterizedGlyph {
+        width,
+        height,
+        left: (glyph.left as f32 * scale).round() as i32,
+        top: (glyph.top as f32 * scale).round() as i32,
+        pixels,
+    }
+}
+
+/// The layers of `glyph` in the `COLR` table, as glyph ids and palette indices, from
+/// the bottom to the top.
+fn color_layers(colr: &[u8], glyph: u16) -> Option<Vec<(u16, u16)>> {
+    let n_base_glyphs = read_u16(colr, 2)? as usize;
+    let base_glyphs = read_u32(colr, 4)? as usize;
+    let layers = read_u32(colr, 8)? as usize;
+
+    // Base glyph records are sorted by glyph id.
+    let (mut low, mut high) = (0, n_base_glyphs);
+    while low < high {
+        let mid = (low + high) / 2;
+        let record = base_glyphs + mid * 6;
+
+        match read_u16(colr, record)? {
+            id if id < glyph => low = mid + 1,
+            id if id > glyph => high = mid,
+            _ => {
+                let first = read_u16(colr, record + 2)? as usize;
+                let count = read_u16(colr, record + 4)? as usize;
+
+                return (first..first + count)
+                    .map(|layer| {
+                        let record = layers + layer * 4;
+                        Some((read_u16(colr, record)?, read_u16(colr, record + 2)?))
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    None
+}
+
+/// The color of `index` in the first palette of the `CPAL` table, as RGBA.
+fn palette_color(cpal: &[u8], index: u16) -> Option<[u8; 4]> {
+    let n_entries = read_u16(cpal, 2)?;
+    let colors = read_u32(cpal, 8)? as usize;
+    let first = read_u16(cpal, 12)? as usize;
+
+    if index >= n_entries {
+        return None;
+    }
+
+    // Colors are stored as BGRA.
+    let record = colors + (first + index as usize) * 4;
+    let bgra = cpal.get(record..record + 4)?;
+
+    Some([bgra[2], bgra[1], bgra[0], bgra[3]])
+}
+
+/// Fill the masks of the layers with their colors, one over the other.
+fn fill_layers(
+    font: &Font,
+    layers: &[(u16, u16)],
+    cpal: Option<&[u8]>,
+    key: &GlyphKey,
+) -> Option<RasterizedGlyph> {
+    let masks: Vec<(RasterizedGlyph, [u8; 4])> = layers
+        .iter()
+        .filter_map(|(glyph, palette_index)| {
+            let color = match *palette_index {
+                FOREGROUND_PALETTE_INDEX => key.foreground,
+                index => cpal.and_then(|cpal| palette_color(cpal, index))?,
+            };
+            let mask = rasterize_mask(font, *glyph as u32, key)?;
+
+            Some((mask, color)).filter(|(mask, _)| !mask.pixels.is_empty())
+        })
+        .collect();
+
+    let left = masks.iter().map(|(mask, _)| mask.left).min()?;
+    let top = masks.iter().map(|(mask, _)| mask.top).max()?;
+    let right = masks
+        .iter()
+        .map(|(mask, _)| mask.left + mask.width as i32)
+        .max()?;
+    let bottom = masks
+        .iter()
+        .map(|(mask, _)| mask.top - mask.height as i32)
+        .min()?;
+
+    let width = (right - left) as u32;
+    let height = (top - bottom) as u32;
+    let mut pixels = vec![0; (width * height * 4) as usize];
+
+    for (mask, color) in &masks {
+        let x = (mask.left - left) as usize;
+        let y = (top - mask.top) as usize;
+
+        for (row, coverages) in mask.pixels.chunks_exact(mask.width as usize).enumerate() {
+            for (col, coverage) in coverages.iter().enumerate() {
+                let idx = ((y + row) * width as usize + x + col) * 4;
+                let src = premultiply([color[0], color[1], color[2], mul(color[3], *coverage)]);
+                let inverse_alpha = 255 - src[3];
+
+                for (dst, src) in pixels[idx..idx + 4].iter_mut().zip(&src) {
+                    *dst = src + mul(*dst, inverse_alpha);
+                }
+            }
+        }
+    }
+
+    Some(RasterizedGlyph {
+        width,
+        height,
+        left,
+        top,
+        pixels,
+    })
+}
+
+/// Multiply the color channels by the alpha.
+fn premultiply([r, g, b, a]: [u8; 4]) -> [u8; 4] {
+    [mul(r, a), mul(g, a), mul(b, a), a]
+}
+
+/// Multiply two 8-bit fractions of 255, rounding.
+fn mul(a: u8, b: u8) -> u8 {
+    let product = a as u32 * b as u32 + 128;
+    ((product + (product >> 8)) >> 8) as u8
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::ui::font::Synthesis;
+    use crate::ui::glyph_cache::Rasterizer;
+    use font_kit::handle::Handle;
+    use std::path::PathBuf;
+
+    /// Glyph ids of the fixture fonts, see `tests/fixtures/fonts/generate.py`.
+    const SMILEY: u32 = 1;
+    const STAR: u32 = 1;
+    const STAR_LAYER: u32 = 2;
+    /// Pixels per em of the `sbix` strike of `bitmap_emoji.ttf`.
+    const STRIKE_PPEM: f32 = 10.0;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    /// Blue at half opacity, premultiplied.
+    const HALF_BLUE: [u8; 4] = [0, 0, 128, 128];
+
+    fn fixture(name: &str) -> FontFace {
+        let fonts = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/fonts"));
+        FontFace::from_handle(&Handle::from_path(fonts.join(name), 0)).unwrap()
+    }
+
+    fn key(face: &FontFace, glyph: u32, pixels_per_em: f32) -> GlyphKey {
+        GlyphKey::new(face.id(), glyph, Synthesis::default(), pixels_per_em, 0.0).0
+    }
+
+    /// The pixel `x` pixels right of the origin, and `y` pixels above the baseline.
+    fn pixel(glyph: &RasterizedGlyph, x: i32, y: i32) -> [u8; 4] {
+        let row = (glyph.top - y - 1) as usize;
+        let col = (x - glyph.left) as usize;
+        let idx = (row * glyph.width as usize + col) * 4;
+
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&glyph.pixels[idx..idx + 4]);
+        pixel
+    }
+
+    fn rows(glyph: &RasterizedGlyph) -> Vec<&[u8]> {
+        glyph.pixels.chunks(glyph.width as usize * 4).collect()
+    }
+
+    #[test]
+    fn fixtures_are_color_faces() {
+        for name in &["bitmap_emoji.ttf", "layered_emoji.ttf"] {
+            assert!(fixture(name).is_color(), "{}", name);
+        }
+        assert!(!fixture("primary.ttf").is_color());
+    }
+
+    #[test]
+    fn png_glyphs_are_decoded_premultiplied() {
+        let face = fixture("bitmap_emoji.ttf");
+
+        let glyph = Rasterizer::default()
+            .rasterize_color(&face, &key(&face, SMILEY, STRIKE_PPEM))
+            .unwrap();
+
+        assert_eq!((glyph.width, glyph.height), (4, 4));
+        assert_eq!((glyph.left, glyph.top), (0, 4));
+        let rows = rows(&glyph);
+        assert_eq!(rows[0], RED.repeat(4).as_slice());
+        assert_eq!(rows[1], RED.repeat(4).as_slice());
+        assert_eq!(rows[2], HALF_BLUE.repeat(4).as_slice());
+        assert_eq!(rows[3], HALF_BLUE.repeat(4).as_slice());
+    }
+
+    #[test]
+    fn png_glyphs_are_scaled_to_the_size_of_the_text() {
+        let face = fixture("bitmap_emoji.ttf");
+
+        let glyph = Rasterizer::default()
+            .rasterize_color(&face, &key(&face, SMILEY, STRIKE_PPEM * 2.0))
+            .unwrap();
+
+        assert_eq!((glyph.width, glyph.height), (8, 8));
+        assert_eq!((glyph.left, glyph.top), (0, 8));
+        let rows = rows(&glyph);
+        for row in &rows[..4] {
+            assert_eq!(*row, RED.repeat(8).as_slice());
+        }
+        for row in &rows[4..] {
+            assert_eq!(*row, HALF_BLUE.repeat(8).as_slice());
+        }
+    }
+
+    #[test]
+    fn bitmaps_are_rasterized_without_loading_the_face() {
+        let face = fixture("bitmap_emoji.ttf");
+
+        // FreeType can't scale faces with `sbix` tables.
+        assert!(face.load().is_none());
+        assert!(rasterize_bitmap(&face, &key(&face, SMILEY, STRIKE_PPEM)).is_some());
+    }
+
+    #[test]
+    fn layers_are_drawn_over_each_other_in_their_colors() {
+        let face = fixture("layered_emoji.ttf");
+        let green = Color::from_rgb(0, 255, 0);
+        let key = key(&face, STAR, STRIKE_PPEM).with_foreground(green);
+
+        let glyph = Rasterizer::default().rasterize_color(&face, &key).unwrap();
+
+        // The red square, from (0.5, 0) to (5.5, 7), and the square in the color of
+        // the text over it, from (2, 2) to (4, 5).
+        assert_eq!((glyph.left, glyph.top), (0, 7));
+        assert_eq!(pixel(&glyph, 1, 5), RED);
+        assert_eq!(pixel(&glyph, 4, 1), RED);
+        assert_eq!(pixel(&glyph, 2, 2), [0, 255, 0, 255]);
+        assert_eq!(pixel(&glyph, 3, 4), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn layers_in_the_color_of_the_text_follow_the_foreground() {
+        let face = fixture("layered_emoji.ttf");
+        let star = key(&face, STAR, STRIKE_PPEM);
+        let mut rasterizer = Rasterizer::default();
+
+        let white = rasterizer.rasterize_color(&face, &star.with_foreground(Color::WHITE));
+        let black = rasterizer.rasterize_color(&face, &star.with_foreground(Color::BLACK));
+
+        assert_eq!(pixel(&white.unwrap(), 3, 3), [255, 255, 255, 255]);
+        assert_eq!(pixel(&black.unwrap(), 3, 3), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn glyphs_without_color_data_are_not_rasterized_in_color() {
+        let face = fixture("layered_emoji.ttf");
+        let mut rasterizer = Rasterizer::default();
+        let layer = key(&face, STAR_LAYER, STRIKE_PPEM);
+
+        assert!(rasterizer.rasterize_color(&face, &layer).is_none());
+        assert!(rasterizer.rasterize(&face, &layer).is_some());
+    }
+}
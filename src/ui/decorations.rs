@@ -0,0 +1,289 @@
+//! # Text Decorations
+//!
+//! Turns the underline, undercurl and strikethrough of the sections of rendered lines
+//! into geometry, in physical pixels relative to the top left corner of the line.
+//!
+//! Underlines and strikethroughs are straight lines, placed and sized with the metrics
+//! of the font, see [`CellMetrics`]. Undercurls are waves of quadratic Bézier curves,
+//! one period per cell, anchored to the grid so that the waves of adjacent sections
+//! join. Underlines and undercurls use the `special` color of the highlight, and
+//! strikethroughs the foreground color.
+//!
+//! The output is plain data, independent of the GPU.
+use super::metrics::CellMetrics;
+use crate::color::Color;
+use crate::grid::rendered::RenderedLine;
+use crate::neovim::RgbAttr;
+
+/// The kinds of straight decorations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LineKind {
+    Underline,
+    Strikethrough,
+}
+
+/// A straight decoration, as a rectangle.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DecorationLine {
+    pub kind: LineKind,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub color: Color,
+}
+
+/// A piece of an undercurl, a quadratic Bézier curve stroked with `thickness`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CurveSegment {
+    pub start: [f32; 2],
+    pub control: [f32; 2],
+    pub end: [f32; 2],
+    pub thickness: f32,
+    pub color: Color,
+}
+
+impl CurveSegment {
+    /// The point of the curve at `t`, from 0 at `start` to 1 at `end`.
+    pub fn point(&self, t: f32) -> [f32; 2] {
+        let lerp = |a: [f32; 2], b: [f32; 2]| [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t];
+        lerp(lerp(self.start, self.control), lerp(self.control, self.end))
+    }
+}
+
+/// The decorations of a line.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LineDecorations {
+    pub lines: Vec<DecorationLine>,
+    pub curves: Vec<CurveSegment>,
+}
+
+impl LineDecorations {
+    /// Is the line undecorated?
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.curves.is_empty()
+    }
+}
+
+/// Build the decorations of all sections of `line`.
+///
+/// Blank sections are decorated too, Neovim underlines spaces like any other text.
+/// Decorations are clipped to the first `columns` of the line, the part of the grid
+/// that is visible.
+pub fn decorate_line(
+    line: RenderedLine<'_>,
+    columns: usize,
+    cell: &CellMetrics,
+) -> LineDecorations {
+    let sections = line.map(|section| (section.rgb_attr, section.col, section.width));
+
+    decorate_sections(sections, columns, cell)
+}
+
+/// Build the decorations of sections given as `(attributes, col, width)`.
+fn decorate_sections(
+    sections: impl IntoIterator<Item = (RgbAttr, usize, usize)>,
+    columns: usize,
+    cell: &CellMetrics,
+) -> LineDecorations {
+    let mut decorations = LineDecorations::default();
+
+    for (attr, col, width) in sections {
+        let width = (col + width).min(columns).saturating_sub(col);
+        if width == 0 {
+            continue;
+        }
+
+        let foreground = attr.foreground.unwrap_or(Color::BLACK);
+        let special = attr.special.unwrap_or(foreground);
+
+        let x = (col as u32 * cell.width) as f32;
+        let pixels = (width as u32 * cell.width) as f32;
+
+        if attr.undercurl() {
+            undercurl(&mut decorations.curves, col, width, special, cell);
+        } else if attr.underline() {
+            decorations.lines.push(DecorationLine {
+                kind: LineKind::Underline,
+                x,
+                y: cell.underline_position as f32,
+                width: pixels,
+                height: cell.underline_thickness as f32,
+                color: special,
+            });
+        }
+
+        if attr.strikethrough() {
+            decorations.lines.push(DecorationLine {
+                kind: LineKind::Strikethrough,
+                x,
+                y: cell.strikethrough_position as f32,
+                width: pixels,
+                height: cell.strikethrough_thickness as f32,
+                color: foreground,
+            });
+        }
+    }
+
+    decorations
+}
+
+/// Add the wave under the `width` cells starting at `col`.
+///
+/// Each cell has a crest and a trough. The wave goes down from the underline, as deep
+/// as the space left in the cell allows, with an amplitude between half and twice the
+/// thickness of the underline.
+fn undercurl(
+    curves: &mut Vec<CurveSegment>,
+    col: usize,
+    width: usize,
+    color: Color,
+    cell: &CellMetrics,
+) {
+    let thickness = cell.underline_thickness as f32;
+    let room = cell.height.saturating_sub(cell.underline_position) as f32 - thickness;
+    let amplitude = (room / 2.0).max(thickness / 2.0).min(thickness * 2.0);
+    // Keep the bottom of the troughs inside the cell.
+    let center = (cell.underline_position as f32 + thickness / 2.0 + amplitude)
+        .min(cell.height as f32 - thickness / 2.0 - amplitude);
+
+    let half_period = cell.width as f32 / 2.0;
+    let first = col as u32 * cell.width;
+
+    for half in 0..width * 2 {
+        let start = first as f32 + half as f32 * half_period;
+        // A quadratic curve reaches half of the height of its control point.
+        let peak = if half % 2 == 0 { -2.0 } else { 2.0 } * amplitude;
+
+        curves.push(CurveSegment {
+            start: [start, center],
+            control: [start + half_period / 2.0, center + peak],
+            end: [start + half_period, center],
+            thickness,
+            color,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cells of 8 by 16 pixels, with a thin underline and a thick strikethrough.
+    const CELL: CellMetrics = CellMetrics {
+        width: 8,
+        height: 16,
+        baseline: 12,
+        ascent: 11.0,
+        descent: 3.0,
+        underline_position: 13,
+        underline_thickness: 1,
+        strikethrough_position: 8,
+        strikethrough_thickness: 2,
+        pixels_per_em: 13.0,
+    };
+
+    fn attr(styles: &[&str], foreground: Option<Color>, special: Option<Color>) -> RgbAttr {
+        let mut attr = RgbAttr::with_styles(styles);
+        attr.foreground = foreground;
+        attr.special = special;
+        attr
+    }
+
+    #[test]
+    fn lines_are_placed_with_the_cell_metrics() {
+        let red = Color::from_rgb(255, 0, 0);
+        let blue = Color::from_rgb(0, 0, 255);
+        let decorated = attr(&["underline", "strikethrough"], Some(red), Some(blue));
+        let sections = vec![
+            (decorated, 2, 3),
+            (attr(&["bold"], Some(red), Some(blue)), 5, 1),
+        ];
+
+        let decorations = decorate_sections(sections, 80, &CELL);
+
+        assert!(decorations.curves.is_empty());
+        assert_eq!(
+            decorations.lines,
+            vec![
+                DecorationLine {
+                    kind: LineKind::Underline,
+                    x: 16.0,
+                    y: 13.0,
+                    width: 24.0,
+                    height: 1.0,
+                    color: blue,
+                },
+                DecorationLine {
+                    kind: LineKind::Strikethrough,
+                    x: 16.0,
+                    y: 8.0,
+                    width: 24.0,
+                    height: 2.0,
+                    color: red,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn underlines_fall_back_to_the_foreground_color() {
+        let red = Color::from_rgb(255, 0, 0);
+        let sections = vec![
+            (attr(&["underline"], Some(red), None), 0, 1),
+            (attr(&["undercurl"], Some(red), None), 1, 1),
+            (attr(&["underline"], None, None), 2, 1),
+        ];
+
+        let decorations = decorate_sections(sections, 80, &CELL);
+
+        let colors: Vec<_> = decorations.lines.iter().map(|line| line.color).collect();
+        assert_eq!(colors, vec![red, Color::BLACK]);
+        assert!(decorations.curves.iter().all(|curve| curve.color == red));
+    }
+
+    #[test]
+    fn undercurls_have_a_period_per_cell_across_sections() {
+        let undercurl = attr(&["undercurl"], None, None);
+        let sections = vec![(undercurl, 0, 2), (undercurl, 2, 1), (undercurl, 4, 1)];
+
+        let curves = decorate_sections(sections, 80, &CELL).curves;
+        assert_eq!(curves.len(), 8);
+
+        // The waves of adjacent sections join.
+        for pair in curves[..6].windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        // Every cell starts with a crest, wherever its section starts.
+        for (idx, curve) in curves.iter().enumerate() {
+            let col = [0, 0, 1, 1, 2, 2, 4, 4][idx];
+            let half = idx % 2;
+
+            assert_eq!(curve.start[0], (col * 8 + half * 4) as f32);
+            assert_eq!(curve.end[0] - curve.start[0], 4.0);
+            assert_eq!(curve.control[1] < curve.start[1], half == 0);
+        }
+        // The troughs stay inside the cell.
+        let trough = curves[1].point(0.5)[1] + curves[1].thickness / 2.0;
+        assert!(trough <= CELL.height as f32);
+    }
+
+    #[test]
+    fn decorations_are_clipped_to_the_line_width() {
+        let sections = vec![
+            (attr(&["underline", "strikethrough"], None, None), 2, 5),
+            (attr(&["undercurl"], None, None), 7, 2),
+            (attr(&["underline"], None, None), 9, 1),
+        ];
+
+        let decorations = decorate_sections(sections.clone(), 8, &CELL);
+
+        let lines: Vec<_> = decorations.lines.iter().map(|l| (l.x, l.width)).collect();
+        assert_eq!(lines, vec![(16.0, 40.0), (16.0, 40.0)]);
+        // The undercurl keeps its first cell only.
+        assert_eq!(decorations.curves.len(), 2);
+        assert_eq!(decorations.curves[1].end[0], 64.0);
+
+        assert!(decorate_sections(sections, 2, &CELL).is_empty());
+    }
+}
@@ -0,0 +1,491 @@
+//! # Font Fallback
+//!
+//! Finds the faces to draw characters missing from the primary `guifont` font, like
+//! emoji, CJK text, Nerd Font icons or math symbols.
+//!
+//! Characters are looked up, in order, in the primary font, in the other fonts of the
+//! `guifont` and then in the fonts of the system. Double-width characters try the
+//! `guifontwide` font first. Results are cached per character, including characters
+//! that no font has, which are drawn with the primary font.
+//!
+//! The system fonts are indexed on another thread when the fallback is created: every
+//! font file is read once and the characters of its `cmap` are recorded. This may take
+//! a while on systems with many fonts, so characters missing from the `guifont` are
+//! drawn with the primary font until the index is ready. The index is kept when the
+//! `guifont` changes.
+//!
+//! Box drawing, block and powerline characters aren't looked up in any font, they are
+//! drawn to fill the cells, see [`box_drawing`](super::box_drawing).
+use super::box_drawing;
+use super::font::{FaceStyle, FontFace, FontFaces};
+use crate::editor::SelectedFont;
+use fnv::FnvHashMap;
+use font_kit::handle::Handle;
+use font_kit::source::SystemSource;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// The faces a character is drawn with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FaceSource {
+    /// The first font of the `guifont`.
+    Primary,
+    /// The `guifontwide` font.
+    Wide,
+    /// One of the other fonts of the `guifont`, in order.
+    Chain(usize),
+    /// A font of the system, by its position in the system index.
+    System(usize),
+    /// Drawn procedurally, without a font.
+    Procedural,
+}
+
+/// Load the faces of a `guifont` font, applying its style options.
+pub fn load_faces(font: &SelectedFont) -> Option<FontFaces> {
+    let base = FaceStyle::new(font.spec.bold, font.spec.italic);
+    FontFaces::from_family(&font.family).map(|faces| faces.with_base_style(base))
+}
+
+/// Load the first font of the `guifont` entries in `fonts` that has faces.
+///
+/// Returns the font with its faces, and the entries after it, to fall back to.
+pub fn load_primary(
+    fonts: Vec<SelectedFont>,
+) -> Option<(SelectedFont, FontFaces, Vec<SelectedFont>)> {
+    let mut fonts = fonts.into_iter();
+
+    while let Some(font) = fonts.next() {
+        match load_faces(&font) {
+            Some(faces) => return Some((font, faces, fonts.collect())),
+            None => log::warn!("Failed to load the faces of {:?}", font.spec.family),
+        }
+    }
+
+    None
+}
+
+/// A font of the `guifont` chain, loaded when first needed.
+#[derive(Debug)]
+enum ChainFont {
+    Unloaded(SelectedFont),
+    Loaded(Option<FontFaces>),
+}
+
+/// Which system fonts have each character.
+#[derive(Debug, Default)]
+struct SystemFonts {
+    handles: Vec<Handle>,
+    /// The first font with each character, preferring regular faces.
+    coverage: FnvHashMap<char, usize>,
+    /// The faces of the fonts already used.
+    faces: FnvHashMap<usize, Option<FontFaces>>,
+}
+
+impl SystemFonts {
+    /// Read all fonts of the system.
+    fn index_system() -> Self {
+        match SystemSource::new().all_fonts() {
+            Ok(handles) => Self::index(handles),
+            Err(error) => {
+                log::error!("Failed to list the system fonts: {:?}", error);
+                Self::default()
+            }
+        }
+    }
+
+    /// Read the fonts of `handles`.
+    fn index(handles: Vec<Handle>) -> Self {
+        let mut coverage = FnvHashMap::default();
+        let mut styled_coverage = FnvHashMap::default();
+
+        for (idx, handle) in handles.iter().enumerate() {
+            let data = match handle {
+                Handle::Path { path, .. } => match std::fs::read(path) {
+                    Ok(data) => data,
+                    Err(_) => continue,
+                },
+                Handle::Memory { bytes, .. } => bytes.to_vec(),
+            };
+            let font_index = match handle {
+                Handle::Path { font_index, .. } | Handle::Memory { font_index, .. } => *font_index,
+            };
+
+            let face = match ttf_parser::Face::from_slice(&data, font_index) {
+                Ok(face) => face,
+                Err(_) => continue,
+            };
+            // Bold and italic faces are only used for characters without a regular one.
+            let target = if face.is_bold() || face.is_italic() || face.is_oblique() {
+                &mut styled_coverage
+            } else {
+                &mut coverage
+            };
+
+            for subtable in face.character_mapping_subtables() {
+                if !subtable.is_unicode() {
+                    continue;
+                }
+
+                subtable.codepoints(|codepoint| {
+                    if let Some(chr) = std::char::from_u32(codepoint) {
+                        target.entry(chr).or_insert(idx);
+                    }
+                });
+            }
+        }
+
+        for (chr, idx) in styled_coverage {
+            coverage.entry(chr).or_insert(idx);
+        }
+
+        log::info!(
+            "Indexed {} system fonts, covering {} characters",
+            handles.len(),
+            coverage.len()
+        );
+
+        Self {
+            handles,
+            coverage,
+            faces: FnvHashMap::default(),
+        }
+    }
+
+    /// The font with `chr`, if any could be loaded.
+    fn find(&mut self, chr: char) -> Option<usize> {
+        let idx = *self.coverage.get(&chr)?;
+        let handles = &self.handles;
+
+        self.faces
+            .entry(idx)
+            .or_insert_with(|| FontFace::from_handle(&handles[idx]).map(FontFaces::from_face))
+            .as_ref()
+            .map(|_| idx)
+    }
+}
+
+/// The system fonts, once indexed.
+#[derive(Debug)]
+enum SystemIndex {
+    Indexing(Receiver<SystemFonts>),
+    Ready(SystemFonts),
+}
+
+impl SystemIndex {
+    /// The index, if it is ready.
+    fn get(&mut self) -> Option<&mut SystemFonts> {
+        if let SystemIndex::Indexing(receiver) = self {
+            *self = match receiver.try_recv() {
+                Ok(system) => SystemIndex::Ready(system),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => SystemIndex::Ready(SystemFonts::default()),
+            };
+        }
+
+        match self {
+            SystemIndex::Ready(system) => Some(system),
+            SystemIndex::Indexing(_) => None,
+        }
+    }
+}
+
+/// The faces of the `guifont` and of all its fallbacks.
+#[derive(Debug)]
+pub struct FontFallback {
+    primary: FontFaces,
+    wide: Option<FontFaces>,
+    chain: Vec<ChainFont>,
+    system: SystemIndex,
+    /// The source of each character, for single and double-width cells.
+    cache: FnvHashMap<(char, bool), FaceSource>,
+}
+
+impl FontFallback {
+    /// Fall back from `primary` to the fonts of `chain`, in order, then to the fonts of
+    /// the system.
+    ///
+    /// The system fonts are indexed on another thread, which calls `on_indexed` when it
+    /// is done, so text using them can be drawn again.
+    pub fn new(
+        primary: FontFaces,
+        chain: Vec<SelectedFont>,
+        wide: Option<FontFaces>,
+        on_indexed: impl FnOnce() + Send + 'static,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let spawned = thread::Builder::new()
+            .name("font-index".into())
+            .spawn(move || {
+                if sender.send(SystemFonts::index_system()).is_ok() {
+                    on_indexed();
+                }
+            });
+        if let Err(error) = spawned {
+            log::error!("Failed to index the system fonts: {:?}", error);
+        }
+
+        Self::with_system(primary, chain, wide, SystemIndex::Indexing(receiver))
+    }
+
+    /// Fall back from `primary` to the fonts of `chain` only, without system fonts.
+    #[cfg(test)]
+    pub(super) fn without_system(
+        primary: FontFaces,
+        chain: Vec<SelectedFont>,
+        wide: Option<FontFaces>,
+    ) -> Self {
+        Self::with_system(
+            primary,
+            chain,
+            wide,
+            SystemIndex::Ready(SystemFonts::default()),
+        )
+    }
+
+    fn with_system(
+        primary: FontFaces,
+        chain: Vec<SelectedFont>,
+        wide: Option<FontFaces>,
+        system: SystemIndex,
+    ) -> Self {
+        Self {
+            primary,
+            wide,
+            chain: chain.into_iter().map(ChainFont::Unloaded).collect(),
+            system,
+            cache: FnvHashMap::default(),
+        }
+    }
+
+    /// Replace the fonts of the `guifont`, keeping the system index.
+    pub fn set_guifont(&mut self, primary: FontFaces, chain: Vec<SelectedFont>) {
+        self.primary = primary;
+        self.chain = chain.into_iter().map(ChainFont::Unloaded).collect();
+        self.cache.clear();
+    }
+
+    /// Replace the `guifontwide` font.
+    pub fn set_wide(&mut self, wide: Option<FontFaces>) {
+        self.wide = wide;
+        self.cache.clear();
+    }
+
+    /// The faces of the primary font.
+    pub fn primary(&self) -> &FontFaces {
+        &self.primary
+    }
+
+    /// The faces of a source returned by [`FontFallback::resolve`].
+    pub fn faces(&self, source: FaceSource) -> &FontFaces {
+        let faces = match source {
+            FaceSource::Primary | FaceSource::Procedural => None,
+            FaceSource::Wide => self.wide.as_ref(),
+            FaceSource::Chain(idx) => match self.chain.get(idx) {
+                Some(ChainFont::Loaded(faces)) => faces.as_ref(),
+                _ => None,
+            },
+            FaceSource::System(idx) => match &self.system {
+                SystemIndex::Ready(system) => system.faces.get(&idx).and_then(Option::as_ref),
+                SystemIndex::Indexing(_) => None,
+            },
+        };
+
+        faces.unwrap_or(&self.primary)
+    }
+
+    /// Find the faces to draw `chr` with, `wide` if it is in a double-width cell.
+    pub fn resolve(&mut self, chr: char, wide: bool) -> FaceSource {
+        if let Some(source) = self.cache.get(&(chr, wide)) {
+            return *source;
+        }
+
+        match self.find(chr, wide) {
+            Some(source) => {
+                self.cache.insert((chr, wide), source);
+                source
+            }
+            // Found again once the system fonts are indexed.
+            None => FaceSource::Primary,
+        }
+    }
+
+    /// The source of `chr`, `None` if the system fonts must be searched but aren't
+    /// indexed yet.
+    fn find(&mut self, chr: char, wide: bool) -> Option<FaceSource> {
+        // Spaces and control characters are never drawn.
+        if chr.is_whitespace() || chr.is_control() {
+            return Some(FaceSource::Primary);
+        }
+        if box_drawing::is_procedural(chr) {
+            return Some(FaceSource::Procedural);
+        }
+
+        if wide && self.wide.as_ref().is_some_and(|faces| faces.has_char(chr)) {
+            return Some(FaceSource::Wide);
+        }
+        if self.primary.has_char(chr) {
+            return Some(FaceSource::Primary);
+        }
+
+        for (idx, font) in self.chain.iter_mut().enumerate() {
+            if let ChainFont::Unloaded(selected) = font {
+                let faces = load_faces(selected);
+                if faces.is_none() {
+                    log::warn!(
+                        "Failed to load the guifont fallback {:?}",
+                        selected.spec.family
+                    );
+                }
+
+                *font = ChainFont::Loaded(faces);
+            }
+
+            if let ChainFont::Loaded(Some(faces)) = font {
+                if faces.has_char(chr) {
+                    return Some(FaceSource::Chain(idx));
+                }
+            }
+        }
+
+        let source = match self.system.get()?.find(chr) {
+            Some(idx) => FaceSource::System(idx),
+            None => {
+                log::debug!("No font has {:?} (U+{:04X})", chr, chr as u32);
+                FaceSource::Primary
+            }
+        };
+
+        Some(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guifont::FontSpec;
+    use font_kit::family_handle::FamilyHandle;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> Handle {
+        let fonts = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/fonts"));
+        Handle::from_path(fonts.join(name), 0)
+    }
+
+    fn faces(name: &str) -> FontFaces {
+        FontFaces::from_face(FontFace::from_handle(&fixture(name)).unwrap())
+    }
+
+    fn selected(name: &str) -> SelectedFont {
+        SelectedFont {
+            family: FamilyHandle::from_font_handles(std::iter::once(fixture(name))),
+            spec: FontSpec::default(),
+        }
+    }
+
+    /// Fall back from `primary.ttf` to `chain.ttf`, then to `system.ttf`.
+    fn fallback(wide: Option<FontFaces>) -> FontFallback {
+        let system = SystemFonts::index(vec![fixture("system.ttf")]);
+
+        FontFallback::with_system(
+            faces("primary.ttf"),
+            vec![selected("chain.ttf")],
+            wide,
+            SystemIndex::Ready(system),
+        )
+    }
+
+    #[test]
+    fn characters_resolve_to_the_first_font_with_them() {
+        let mut fallback = fallback(None);
+
+        assert_eq!(fallback.resolve('a', false), FaceSource::Primary);
+        // The chain comes before the system fonts, which have it too.
+        assert_eq!(fallback.resolve('α', false), FaceSource::Chain(0));
+        assert_eq!(fallback.resolve('☃', false), FaceSource::System(0));
+        assert!(fallback.faces(FaceSource::System(0)).has_char('☃'));
+    }
+
+    #[test]
+    fn characters_without_a_font_use_the_primary_font() {
+        let mut fallback = fallback(None);
+
+        assert_eq!(fallback.resolve('♞', false), FaceSource::Primary);
+        assert_eq!(fallback.resolve(' ', false), FaceSource::Primary);
+        assert_eq!(fallback.resolve('─', false), FaceSource::Procedural);
+    }
+
+    #[test]
+    fn wide_cells_try_the_wide_font_first() {
+        let mut fallback = fallback(Some(faces("system.ttf")));
+
+        assert_eq!(fallback.resolve('α', true), FaceSource::Wide);
+        assert_eq!(fallback.resolve('α', false), FaceSource::Chain(0));
+        // Missing from the wide font.
+        assert_eq!(fallback.resolve('a', true), FaceSource::Primary);
+    }
+
+    #[test]
+    fn sources_are_cached_per_character_until_the_fonts_change() {
+        let mut fallback = fallback(None);
+
+        fallback.resolve('α', false);
+        fallback.resolve('α', true);
+        assert_eq!(fallback.cache.get(&('α', false)), Some(&FaceSource::Chain(0)));
+        assert_eq!(fallback.cache.len(), 2);
+
+        fallback.set_wide(Some(faces("system.ttf")));
+        assert!(fallback.cache.is_empty());
+        assert_eq!(fallback.resolve('α', true), FaceSource::Wide);
+
+        fallback.set_guifont(faces("system.ttf"), vec![]);
+        assert!(fallback.cache.is_empty());
+        assert_eq!(fallback.resolve('α', false), FaceSource::Primary);
+    }
+
+    #[test]
+    fn the_first_guifont_entry_that_loads_is_the_primary_font() {
+        let entry = |name: &str| SelectedFont {
+            spec: FontSpec {
+                family: String::from(name),
+                ..FontSpec::default()
+            },
+            ..selected(name)
+        };
+        let families = |fonts: &[SelectedFont]| -> Vec<String> {
+            fonts.iter().map(|font| font.spec.family.clone()).collect()
+        };
+
+        let fonts = vec![
+            entry("missing.ttf"),
+            entry("primary.ttf"),
+            entry("chain.ttf"),
+        ];
+        let (primary, faces, chain) = load_primary(fonts).unwrap();
+
+        assert_eq!(primary.spec.family, "primary.ttf");
+        assert!(faces.has_char('a'));
+        assert_eq!(families(&chain), ["chain.ttf"]);
+
+        assert!(load_primary(vec![entry("missing.ttf")]).is_none());
+        assert!(load_primary(vec![]).is_none());
+    }
+
+    #[test]
+    fn system_fonts_are_used_once_indexed() {
+        let (sender, receiver) = mpsc::channel();
+        let mut fallback = FontFallback::with_system(
+            faces("primary.ttf"),
+            vec![],
+            None,
+            SystemIndex::Indexing(receiver),
+        );
+
+        assert_eq!(fallback.resolve('☃', false), FaceSource::Primary);
+        assert!(fallback.cache.is_empty());
+
+        sender
+            .send(SystemFonts::index(vec![fixture("system.ttf")]))
+            .unwrap();
+        assert_eq!(fallback.resolve('☃', false), FaceSource::System(0));
+    }
+}
@@ -0,0 +1,306 @@
+//! # Font Faces
+//!
+//! The faces of the `guifont` family used to draw each text style. Faces keep the raw
+//! font data, shared between all its users, so they can be given to the shaper and the
+//! rasterizer without loading the font again.
+//!
+//! Styles without a face of their own are drawn with the closest face, emboldened or
+//! slanted when rasterized, see [`Synthesis`].
+use super::color_glyph;
+use font_kit::family_handle::FamilyHandle;
+use font_kit::font::Font;
+use font_kit::handle::Handle;
+use font_kit::properties::{Properties, Style, Weight};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Source of the face ids.
+static FACE_ID_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// Identifies a loaded face, shared by all its clones.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FaceId(u32);
+
+impl FaceId {
+    /// The face of the glyphs drawn by [`box_drawing`](super::box_drawing).
+    pub const PROCEDURAL: Self = Self(u32::MAX);
+}
+
+/// A face of a font file.
+#[derive(Clone)]
+pub struct FontFace {
+    id: FaceId,
+    data: Arc<Vec<u8>>,
+    index: u32,
+    units_per_em: u16,
+    bold: bool,
+    italic: bool,
+    color: bool,
+}
+
+impl std::fmt::Debug for FontFace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FontFace")
+            .field("id", &self.id)
+            .field("data", &format_args!("[{} bytes]", self.data.len()))
+            .field("index", &self.index)
+            .field("units_per_em", &self.units_per_em)
+            .field("bold", &self.bold)
+            .field("italic", &self.italic)
+            .field("color", &self.color)
+            .finish()
+    }
+}
+
+impl FontFace {
+    /// Read the face referenced by `handle`.
+    pub fn from_handle(handle: &Handle) -> Option<Self> {
+        let (data, index) = match handle {
+            Handle::Path { path, font_index } => (Arc::new(std::fs::read(path).ok()?), *font_index),
+            Handle::Memory { bytes, font_index } => (bytes.clone(), *font_index),
+        };
+
+        Self::from_data(data, index)
+    }
+
+    /// A face of the font file in `data`.
+    pub fn from_data(data: Arc<Vec<u8>>, index: u32) -> Option<Self> {
+        let face = ttf_parser::Face::from_slice(&data, index).ok()?;
+        let units_per_em = face.units_per_em()?;
+        let bold = face.weight().to_number() >= Weight::SEMIBOLD.0 as u16;
+        let italic = face.is_italic() || face.is_oblique();
+        let color = color_glyph::is_color_face(&face);
+
+        Some(Self {
+            id: FaceId(FACE_ID_COUNTER.fetch_add(1, Ordering::Relaxed)),
+            data,
+            index,
+            units_per_em,
+            bold,
+            italic,
+            color,
+        })
+    }
+
+    /// The id of the face.
+    pub fn id(&self) -> FaceId {
+        self.id
+    }
+
+    /// The face as seen by the shaper.
+    pub fn shaping_face(&self) -> Option<rustybuzz::Face<'_>> {
+        rustybuzz::Face::from_slice(&self.data, self.index)
+    }
+
+    /// The face as seen by ttf-parser, to read its tables.
+    pub fn parsed(&self) -> Option<ttf_parser::Face<'_>> {
+        ttf_parser::Face::from_slice(&self.data, self.index).ok()
+    }
+
+    /// Load the face with font-kit, to measure or rasterize it.
+    ///
+    /// Faces FreeType can't scale, like most bitmap emoji fonts, make font-kit panic
+    /// instead of failing, they are never loaded.
+    pub fn load(&self) -> Option<Font> {
+        let data = self.data.clone();
+        let index = self.index;
+
+        match std::panic::catch_unwind(move || Font::from_bytes(data, index)) {
+            Ok(font) => font.ok(),
+            Err(_) => {
+                log::warn!("Failed to load the face {:?}, it can't be scaled", self.id);
+                None
+            }
+        }
+    }
+
+    /// Does the face have a glyph for `chr`?
+    pub fn has_glyph(&self, chr: char) -> bool {
+        self.parsed()
+            .and_then(|face| face.glyph_index(chr))
+            .is_some()
+    }
+
+    /// Number of font units per em, the unit of all distances of the face.
+    pub fn units_per_em(&self) -> u16 {
+        self.units_per_em
+    }
+
+    /// Is the face at least semibold?
+    pub fn is_bold(&self) -> bool {
+        self.bold
+    }
+
+    /// Is the face italic or oblique?
+    pub fn is_italic(&self) -> bool {
+        self.italic
+    }
+
+    /// Does the face have color glyphs, see [`color_glyph`]?
+    pub fn is_color(&self) -> bool {
+        self.color
+    }
+}
+
+/// The text styles that may use different faces.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FaceStyle {
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+}
+
+impl FaceStyle {
+    /// The style for text with the given attributes.
+    pub fn new(bold: bool, italic: bool) -> Self {
+        match (bold, italic) {
+            (false, false) => Self::Regular,
+            (true, false) => Self::Bold,
+            (false, true) => Self::Italic,
+            (true, true) => Self::BoldItalic,
+        }
+    }
+
+    /// Is the style bold?
+    pub fn is_bold(self) -> bool {
+        matches!(self, Self::Bold | Self::BoldItalic)
+    }
+
+    /// Is the style italic?
+    pub fn is_italic(self) -> bool {
+        matches!(self, Self::Italic | Self::BoldItalic)
+    }
+
+    fn properties(self) -> Properties {
+        let mut properties = Properties::new();
+
+        if self.is_bold() {
+            properties.weight = Weight::BOLD;
+        }
+        if self.is_italic() {
+            properties.style = Style::Italic;
+        }
+
+        properties
+    }
+}
+
+/// The parts of a style missing from its face, drawn by transforming the glyphs.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Synthesis {
+    /// Thicken the strokes of the glyphs.
+    pub embolden: bool,
+    /// Slant the glyphs to the right.
+    pub slant: bool,
+}
+
+/// The faces of a family, one for each [`FaceStyle`].
+#[derive(Debug, Clone)]
+pub struct FontFaces {
+    regular: FontFace,
+    bold: Option<FontFace>,
+    italic: Option<FontFace>,
+    bold_italic: Option<FontFace>,
+    /// Style applied to all text, from the `:b` and `:i` options of the `guifont`.
+    base: FaceStyle,
+}
+
+impl FontFaces {
+    /// Pick the faces of `family` closest to each style.
+    ///
+    /// Styles without a face of their own in the family use the regular one.
+    pub fn from_family(family: &FamilyHandle) -> Option<Self> {
+        let fonts: Vec<(Handle, Properties)> = family
+            .fonts()
+            .iter()
+            .filter_map(|handle| Some((handle.clone(), handle.load().ok()?.properties())))
+            .collect();
+
+        let pick = |style: FaceStyle| {
+            let wanted = style.properties();
+
+            fonts
+                .iter()
+                .filter(|(_, properties)| {
+                    (properties.style == Style::Normal) == (wanted.style == Style::Normal)
+                })
+                // A face lighter than semibold is no bold face at all.
+                .filter(|(_, properties)| {
+                    wanted.weight != Weight::BOLD || properties.weight.0 >= Weight::SEMIBOLD.0
+                })
+                .min_by_key(|(_, properties)| (properties.weight.0 - wanted.weight.0).abs() as u32)
+                .and_then(|(handle, _)| FontFace::from_handle(handle))
+        };
+
+        let regular =
+            pick(FaceStyle::Regular).or_else(|| FontFace::from_handle(family.fonts().first()?))?;
+
+        Some(Self {
+            regular,
+            bold: pick(FaceStyle::Bold),
+            italic: pick(FaceStyle::Italic),
+            bold_italic: pick(FaceStyle::BoldItalic),
+            base: FaceStyle::Regular,
+        })
+    }
+
+    /// A single face used for all styles.
+    pub fn from_face(regular: FontFace) -> Self {
+        Self {
+            regular,
+            bold: None,
+            italic: None,
+            bold_italic: None,
+            base: FaceStyle::Regular,
+        }
+    }
+
+    /// Draw all text with at least the given style.
+    pub fn with_base_style(self, base: FaceStyle) -> Self {
+        Self { base, ..self }
+    }
+
+    /// The regular face.
+    pub fn regular(&self) -> &FontFace {
+        &self.regular
+    }
+
+    /// Does the regular face have a glyph for `chr`?
+    pub fn has_char(&self, chr: char) -> bool {
+        self.regular.has_glyph(chr)
+    }
+
+    /// The face of a style, if the family has one.
+    pub fn get(&self, style: FaceStyle) -> Option<&FontFace> {
+        match style {
+            FaceStyle::Regular => Some(&self.regular),
+            FaceStyle::Bold => self.bold.as_ref(),
+            FaceStyle::Italic => self.italic.as_ref(),
+            FaceStyle::BoldItalic => self.bold_italic.as_ref(),
+        }
+    }
+
+    /// The face to draw a style with, falling back to the closest available one.
+    ///
+    /// The style is combined with the base style of the faces. When the face lacks
+    /// part of the style, the glyphs must be transformed as told by the [`Synthesis`].
+    pub fn select(&self, style: FaceStyle) -> (&FontFace, Synthesis) {
+        let style = FaceStyle::new(
+            style.is_bold() || self.base.is_bold(),
+            style.is_italic() || self.base.is_italic(),
+        );
+        let fallback = match style {
+            FaceStyle::BoldItalic => self.italic.as_ref().or(self.bold.as_ref()),
+            _ => None,
+        };
+
+        let face = self.get(style).or(fallback).unwrap_or(&self.regular);
+        let synthesis = Synthesis {
+            embolden: style.is_bold() && !face.is_bold(),
+            slant: style.is_italic() && !face.is_italic(),
+        };
+
+        (face, synthesis)
+    }
+}
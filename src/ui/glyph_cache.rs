@@ -0,0 +1,702 @@
+//! # Glyph Cache
+//!
+//! Glyphs are rasterized on the CPU, as 8-bit coverage masks, and packed in the pages of
+//! an [`Atlas`]. The cache keeps a copy of the pixels of every page, and the pages that
+//! changed are uploaded to the GPU once per frame.
+//!
+//! Glyphs are keyed by their face, id, size and horizontal subpixel offset, so the same
+//! glyph drawn at different fractional positions looks the same everywhere.
+//! Procedural glyphs, see [`box_drawing`], are keyed by their character and the size of
+//! the cells they fill instead.
+//!
+//! Color glyphs, see [`color_glyph`], are kept as RGBA in a cache of their own. Their
+//! keys have the color of the text too, some of their layers are drawn with it.
+//!
+//! [`box_drawing`]: super::box_drawing
+//! [`color_glyph`]: super::color_glyph
+use super::atlas::{Atlas, AtlasRegion};
+use super::color_glyph;
+use super::font::{FaceId, FontFace, Synthesis};
+use super::metrics::CellMetrics;
+use crate::color::Color;
+use fnv::FnvHashMap;
+use font_kit::canvas::{Canvas, Format, RasterizationOptions};
+use font_kit::font::Font;
+use font_kit::hinting::HintingOptions;
+use pathfinder_geometry::transform2d::Transform2F;
+use pathfinder_geometry::vector::Vector2F;
+
+/// Size of the sides of the atlas pages.
+pub const PAGE_SIZE: u32 = 1024;
+/// Maximum number of atlas pages.
+pub const MAX_PAGES: usize = 4;
+/// Number of horizontal positions inside a pixel for which glyphs are rasterized.
+pub const SUBPIXEL_STEPS: u8 = 4;
+/// Horizontal shift per pixel of height of synthetic italics, about 12 degrees.
+const SYNTHETIC_SLANT: f32 = 0.2;
+/// Pixels per em for each pixel added to the strokes of synthetic bold glyphs.
+const SYNTHETIC_EMBOLDEN_PPEM: f32 = 24.0;
+
+/// Identifies a rasterized glyph.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct GlyphKey {
+    /// The face of the glyph.
+    pub face: FaceId,
+    /// The glyph id in the face.
+    pub glyph: u32,
+    /// Size of the font, in 1/64 pixels per em.
+    pub size: u32,
+    /// Horizontal offset, in `1 / SUBPIXEL_STEPS` pixels.
+    pub subpixel: u8,
+    /// The transformations applied to the glyph.
+    pub synthesis: Synthesis,
+    /// Color of the text as RGBA, for the layers of color glyphs drawn in that color.
+    /// Transparent for the other glyphs.
+    pub foreground: [u8; 4],
+}
+
+impl GlyphKey {
+    /// The key of a glyph drawn with its origin at `x` pixels.
+    ///
+    /// Also returns the whole pixel where the origin of the rasterized glyph goes.
+    pub fn new(
+        face: FaceId,
+        glyph: u32,
+        synthesis: Synthesis,
+        pixels_per_em: f32,
+        x: f32,
+    ) -> (Self, i32) {
+        let steps = SUBPIXEL_STEPS as f32;
+        let position = (x * steps).round() / steps;
+        let pixel = position.floor();
+
+        let key = Self {
+            face,
+            glyph,
+            size: (pixels_per_em * 64.0).round() as u32,
+            subpixel: ((position - pixel) * steps) as u8,
+            synthesis,
+            foreground: [0; 4],
+        };
+
+        (key, pixel as i32)
+    }
+
+    /// The key of `chr` drawn procedurally to fill `cols` cells.
+    ///
+    /// The size is the width of the glyph in the upper 16 bits and its height in the
+    /// lower ones, so glyphs drawn for other cell sizes are never re-used.
+    pub fn procedural(chr: char, cols: u32, cell: &CellMetrics) -> Self {
+        Self {
+            face: FaceId::PROCEDURAL,
+            glyph: chr as u32,
+            size: (cell.width * cols) << 16 | (cell.height & 0xFFFF),
+            subpixel: 0,
+            synthesis: Synthesis::default(),
+            foreground: [0; 4],
+        }
+    }
+
+    /// The key of the same glyph in color, with its layers in the color of the text
+    /// drawn with `foreground`, an sRGB color.
+    pub fn with_foreground(self, foreground: Color) -> Self {
+        Self {
+            foreground: foreground.to_rgba(),
+            ..self
+        }
+    }
+
+    /// Size of the font, in pixels per em.
+    pub fn pixels_per_em(&self) -> f32 {
+        self.size as f32 / 64.0
+    }
+
+    /// Horizontal offset, in pixels.
+    pub fn subpixel_offset(&self) -> f32 {
+        self.subpixel as f32 / SUBPIXEL_STEPS as f32
+    }
+}
+
+/// The pixels of the glyphs of a cache.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 8-bit coverage masks, drawn with the color of the text.
+    Alpha,
+    /// Premultiplied 8-bit RGBA, drawn as they are.
+    Rgba,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Alpha => 1,
+            Self::Rgba => 4,
+        }
+    }
+}
+
+/// A rasterized glyph, in the [`PixelFormat`] of its cache.
+#[derive(Debug, Clone, Default)]
+pub struct RasterizedGlyph {
+    pub width: u32,
+    pub height: u32,
+    /// Distance from the origin to the left edge of the mask.
+    pub left: i32,
+    /// Distance from the baseline to the top edge of the mask, positive upwards.
+    pub top: i32,
+    /// The pixels, row by row.
+    pub pixels: Vec<u8>,
+}
+
+/// A glyph in the cache.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CachedGlyph {
+    /// Where the glyph is in the atlas, `None` for glyphs without pixels, like spaces.
+    pub region: Option<AtlasRegion>,
+    /// Distance from the origin to the left edge of the glyph.
+    pub left: i32,
+    /// Distance from the baseline to the top edge of the glyph, positive upwards.
+    pub top: i32,
+}
+
+impl CachedGlyph {
+    const EMPTY: Self = Self {
+        region: None,
+        left: 0,
+        top: 0,
+    };
+}
+
+#[derive(Debug, Clone)]
+struct PagePixels {
+    pixels: Vec<u8>,
+    /// Did the pixels change since the last upload?
+    dirty: bool,
+}
+
+/// The cache of rasterized glyphs.
+#[derive(Debug, Clone)]
+pub struct GlyphCache {
+    format: PixelFormat,
+    atlas: Atlas,
+    glyphs: FnvHashMap<GlyphKey, CachedGlyph>,
+    pages: Vec<PagePixels>,
+}
+
+impl Default for GlyphCache {
+    fn default() -> Self {
+        Self::new(PAGE_SIZE, MAX_PAGES)
+    }
+}
+
+impl GlyphCache {
+    /// An empty cache of coverage masks, using up to `max_pages` pages of `page_size`
+    /// pixels.
+    pub fn new(page_size: u32, max_pages: usize) -> Self {
+        Self::with_format(page_size, max_pages, PixelFormat::Alpha)
+    }
+
+    /// An empty cache of glyphs with pixels in `format`.
+    pub fn with_format(page_size: u32, max_pages: usize, format: PixelFormat) -> Self {
+        Self {
+            format,
+            atlas: Atlas::new(page_size, max_pages),
+            glyphs: FnvHashMap::default(),
+            pages: Vec::new(),
+        }
+    }
+
+    /// Size of the sides of the pages.
+    pub fn page_size(&self) -> u32 {
+        self.atlas.page_size()
+    }
+
+    /// The format of the pixels of the pages.
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Start a new frame, see [`Atlas::begin_frame`].
+    pub fn begin_frame(&mut self) {
+        self.atlas.begin_frame();
+    }
+
+    /// The cached glyph of `key`, calling `rasterize` if it isn't cached.
+    ///
+    /// Glyphs that fail to rasterize are cached as empty. Returns `None` only if
+    /// there is no room for the glyph in this frame.
+    pub fn get_or_rasterize(
+        &mut self,
+        key: GlyphKey,
+        rasterize: impl FnOnce(&GlyphKey) -> Option<RasterizedGlyph>,
+    ) -> Option<CachedGlyph> {
+        if let Some(glyph) = self.glyphs.get(&key) {
+            if let Some(region) = glyph.region {
+                self.atlas.touch(region.page);
+            }
+
+            return Some(*glyph);
+        }
+
+        let rasterized = match rasterize(&key) {
+            Some(rasterized) if rasterized.width > 0 && rasterized.height > 0 => rasterized,
+            _ => {
+                self.glyphs.insert(key, CachedGlyph::EMPTY);
+                return Some(CachedGlyph::EMPTY);
+            }
+        };
+
+        let allocation = self.atlas.allocate(rasterized.width, rasterized.height)?;
+        let region = allocation.region;
+
+        if let Some(evicted) = allocation.evicted {
+            self.glyphs
+                .retain(|_, glyph| glyph.region.is_none_or(|r| r.page != evicted));
+
+            let page = &mut self.pages[evicted];
+            page.pixels.fill(0);
+            page.dirty = true;
+        }
+
+        self.blit(region, &rasterized);
+
+        let glyph = CachedGlyph {
+            region: Some(region),
+            left: rasterized.left,
+            top: rasterized.top,
+        };
+        self.glyphs.insert(key, glyph);
+
+        Some(glyph)
+    }
+
+    /// Number of pages holding glyphs.
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Upload every page on the next call to [`Self::upload_dirty_pages`], for example
+    /// to a new texture.
+    pub fn mark_pages_dirty(&mut self) {
+        for page in &mut self.pages {
+            page.dirty = true;
+        }
+    }
+
+    /// Call `upload` with the pixels of every page that changed since the last call.
+    pub fn upload_dirty_pages(&mut self, mut upload: impl FnMut(usize, &[u8])) {
+        for (idx, page) in self.pages.iter_mut().enumerate() {
+            if page.dirty {
+                upload(idx, &page.pixels);
+                page.dirty = false;
+            }
+        }
+    }
+
+    /// Remove all glyphs, for example when the font changes.
+    pub fn clear(&mut self) {
+        self.atlas.clear();
+        self.glyphs.clear();
+
+        for page in &mut self.pages {
+            page.pixels.fill(0);
+            page.dirty = true;
+        }
+    }
+
+    fn blit(&mut self, region: AtlasRegion, rasterized: &RasterizedGlyph) {
+        let page_size = self.atlas.page_size() as usize;
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+
+        while self.pages.len() <= region.page {
+            self.pages.push(PagePixels {
+                pixels: vec![0; page_size * page_size * bytes_per_pixel],
+                dirty: true,
+            });
+        }
+
+        let page = &mut self.pages[region.page];
+        let row_bytes = rasterized.width as usize * bytes_per_pixel;
+
+        for (row, src) in rasterized.pixels.chunks_exact(row_bytes).enumerate() {
+            let start =
+                ((region.y as usize + row) * page_size + region.x as usize) * bytes_per_pixel;
+            page.pixels[start..start + row_bytes].copy_from_slice(src);
+        }
+
+        page.dirty = true;
+    }
+}
+
+/// Rasterizes glyphs with font-kit.
+///
+/// Faces are only loaded when a glyph of them is rasterized. As the loaded fonts can't
+/// be sent between threads, rasterizers are meant to live for a single frame.
+#[derive(Default)]
+pub struct Rasterizer {
+    fonts: FnvHashMap<FaceId, Option<Font>>,
+}
+
+impl Rasterizer {
+    /// Rasterize the glyph of `key`, which must be a glyph of `face`.
+    pub fn rasterize(&mut self, face: &FontFace, key: &GlyphKey) -> Option<RasterizedGlyph> {
+        let font = self.font(face)?;
+
+        let mut glyph = rasterize_mask(font, key.glyph, key)?;
+        if glyph.pixels.is_empty() {
+            return Some(glyph);
+        }
+
+        // The synthetic styles are applied to the mask, font-kit doesn't handle skewed
+        // transforms consistently between its loaders.
+        if key.synthesis.embolden {
+            let strength = (key.pixels_per_em() / SYNTHETIC_EMBOLDEN_PPEM)
+                .round()
+                .max(1.0) as u32;
+            glyph = embolden(glyph, strength);
+        }
+        if key.synthesis.slant {
+            glyph = slant(glyph);
+        }
+
+        Some(glyph)
+    }
+
+    /// Rasterize the glyph of `key` in color, as premultiplied RGBA.
+    ///
+    /// Returns `None` if the glyph has no color data, it must then be rasterized as a
+    /// coverage mask.
+    pub fn rasterize_color(&mut self, face: &FontFace, key: &GlyphKey) -> Option<RasterizedGlyph> {
+        if let Some(glyph) = color_glyph::rasterize_bitmap(face, key) {
+            return Some(glyph);
+        }
+
+        color_glyph::rasterize_layers(face, self.font(face)?, key)
+    }
+
+    /// `face` loaded, `None` if it can't be loaded.
+    fn font(&mut self, face: &FontFace) -> Option<&Font> {
+        self.fonts
+            .entry(face.id())
+            .or_insert_with(|| face.load())
+            .as_ref()
+    }
+}
+
+/// Rasterize `glyph` of `font` as a coverage mask, with the size and offset of `key`.
+///
+/// Blank glyphs, like spaces, have no pixels.
+pub(super) fn rasterize_mask(font: &Font, glyph: u32, key: &GlyphKey) -> Option<RasterizedGlyph> {
+    let size = key.pixels_per_em();
+    let hinting = HintingOptions::None;
+    let options = RasterizationOptions::GrayscaleAa;
+    let transform = Transform2F::from_translation(Vector2F::new(key.subpixel_offset(), 0.0));
+
+    let bounds = font
+        .raster_bounds(glyph, size, transform, hinting, options)
+        .ok()?;
+    if bounds.width() <= 0 || bounds.height() <= 0 {
+        return Some(RasterizedGlyph::default());
+    }
+
+    let mut canvas = Canvas::new(bounds.size(), Format::A8);
+
+    // Move the top left corner of the bounds to the origin of the canvas.
+    let to_canvas = Transform2F::from_translation(-bounds.origin().to_f32()) * transform;
+    font.rasterize_glyph(&mut canvas, glyph, size, to_canvas, hinting, options)
+        .ok()?;
+
+    let width = bounds.width() as u32;
+    let height = bounds.height() as u32;
+
+    // Drop the row padding of the canvas, if any.
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for row in canvas.pixels.chunks(canvas.stride).take(height as usize) {
+        pixels.extend_from_slice(&row[..width as usize]);
+    }
+
+    Some(RasterizedGlyph {
+        width,
+        height,
+        left: bounds.origin_x(),
+        top: -bounds.origin_y(),
+        pixels,
+    })
+}
+
+/// Shear `glyph` to the right by [`SYNTHETIC_SLANT`], around the baseline.
+///
+/// Rows are shifted by fractional amounts, spreading the coverage between the two
+/// nearest pixels.
+fn slant(glyph: RasterizedGlyph) -> RasterizedGlyph {
+    if glyph.pixels.is_empty() {
+        return glyph;
+    }
+
+    let (src_width, height) = (glyph.width as usize, glyph.height as usize);
+    // Shift of the center of each row, positive to the right.
+    let shift = |row: usize| SYNTHETIC_SLANT * (glyph.top as f32 - row as f32 - 0.5);
+
+    let min_shift = shift(height - 1).floor();
+    let max_shift = shift(0).ceil();
+    let width = src_width + (max_shift - min_shift) as usize;
+
+    let mut pixels = vec![0; width * height];
+    for (row, (src, dst)) in glyph
+        .pixels
+        .chunks_exact(src_width)
+        .zip(pixels.chunks_exact_mut(width))
+        .enumerate()
+    {
+        let offset = shift(row) - min_shift;
+        let (whole, fract) = (offset.floor() as usize, offset.fract());
+
+        for (x, coverage) in src.iter().enumerate() {
+            let coverage = *coverage as f32;
+            let left = &mut dst[x + whole];
+            *left = (*left as f32 + coverage * (1.0 - fract)).min(255.0) as u8;
+            // Rows shifted by whole pixels don't reach the next column, which may be
+            // past the last one.
+            if fract > 0.0 {
+                let right = &mut dst[x + whole + 1];
+                *right = (*right as f32 + coverage * fract).min(255.0) as u8;
+            }
+        }
+    }
+
+    RasterizedGlyph {
+        width: width as u32,
+        left: glyph.left + min_shift as i32,
+        pixels,
+        ..glyph
+    }
+}
+
+/// Thicken the vertical strokes of `glyph` by `strength` pixels, to the right.
+///
+/// Each pixel gets the highest coverage of itself and of the `strength` pixels to
+/// its left, like drawing the glyph several times with small offsets.
+fn embolden(glyph: RasterizedGlyph, strength: u32) -> RasterizedGlyph {
+    if glyph.pixels.is_empty() {
+        return glyph;
+    }
+
+    let width = glyph.width + strength;
+    let (src_width, strength) = (glyph.width as usize, strength as usize);
+    let mut pixels = vec![0; width as usize * glyph.height as usize];
+
+    for (src, dst) in glyph
+        .pixels
+        .chunks_exact(src_width)
+        .zip(pixels.chunks_exact_mut(width as usize))
+    {
+        for (x, coverage) in src.iter().enumerate() {
+            for dst in &mut dst[x..=x + strength] {
+                *dst = (*dst).max(*coverage);
+            }
+        }
+    }
+
+    RasterizedGlyph {
+        width,
+        pixels,
+        ..glyph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(glyph: u32) -> GlyphKey {
+        GlyphKey {
+            face: FaceId::PROCEDURAL,
+            glyph,
+            size: 16 * 64,
+            subpixel: 0,
+            synthesis: Synthesis::default(),
+            foreground: [0; 4],
+        }
+    }
+
+    fn solid(width: u32, height: u32, coverage: u8) -> RasterizedGlyph {
+        RasterizedGlyph {
+            width,
+            height,
+            left: 0,
+            top: height as i32,
+            pixels: vec![coverage; (width * height) as usize],
+        }
+    }
+
+    fn rows(glyph: &RasterizedGlyph) -> Vec<&[u8]> {
+        glyph.pixels.chunks(glyph.width as usize).collect()
+    }
+
+    #[test]
+    fn embolden_widens_strokes_to_the_right() {
+        let glyph = RasterizedGlyph {
+            width: 3,
+            height: 1,
+            left: 2,
+            top: 5,
+            pixels: vec![100, 200, 50],
+        };
+        let bold = embolden(glyph, 2);
+
+        assert_eq!((bold.width, bold.height), (5, 1));
+        assert_eq!((bold.left, bold.top), (2, 5));
+        assert_eq!(bold.pixels, [100, 200, 200, 200, 50]);
+    }
+
+    #[test]
+    fn embolden_keeps_solid_glyphs_solid() {
+        let bold = embolden(solid(3, 2, 200), 1);
+
+        assert_eq!((bold.width, bold.height), (4, 2));
+        assert!(bold.pixels.iter().all(|coverage| *coverage == 200));
+    }
+
+    #[test]
+    fn slant_shifts_higher_rows_further_right() {
+        let italic = slant(solid(1, 4, 255));
+
+        assert_eq!((italic.width, italic.height), (2, 4));
+        assert_eq!((italic.left, italic.top), (0, 4));
+
+        let rows = rows(&italic);
+        assert!(rows.windows(2).all(|rows| rows[0][1] > rows[1][1]));
+        // The coverage of each row is only spread.
+        for row in rows {
+            let coverage: u32 = row.iter().map(|coverage| *coverage as u32).sum();
+            assert!((254..=255).contains(&coverage), "{:?}", row);
+        }
+    }
+
+    #[test]
+    fn slant_covers_its_edge_columns() {
+        // A descender, shifted to the left below the baseline.
+        let glyph = RasterizedGlyph {
+            top: 1,
+            ..solid(2, 4, 255)
+        };
+        let italic = slant(glyph);
+
+        assert_eq!(italic.width, 4);
+        assert_eq!(italic.left, -1);
+
+        let rows = rows(&italic);
+        for col in 0..italic.width as usize {
+            assert!(rows.iter().any(|row| row[col] > 0), "column {} is blank", col);
+        }
+    }
+
+    #[test]
+    fn synthetic_styles_keep_empty_glyphs_empty() {
+        let empty = RasterizedGlyph {
+            width: 3,
+            ..RasterizedGlyph::default()
+        };
+
+        let italic = slant(empty.clone());
+        assert_eq!((italic.width, italic.height), (3, 0));
+        assert!(italic.pixels.is_empty());
+
+        let bold = embolden(empty, 2);
+        assert_eq!((bold.width, bold.height), (3, 0));
+        assert!(bold.pixels.is_empty());
+    }
+
+    #[test]
+    fn cached_glyphs_are_rasterized_once() {
+        let mut cache = GlyphCache::new(64, 1);
+        let mut calls = 0;
+
+        for _ in 0..3 {
+            cache.get_or_rasterize(key(1), |_| {
+                calls += 1;
+                Some(solid(4, 4, 255))
+            });
+        }
+
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn blank_glyphs_have_no_region() {
+        let mut cache = GlyphCache::new(64, 1);
+        let glyph = cache.get_or_rasterize(key(1), |_| Some(RasterizedGlyph::default()));
+
+        assert_eq!(glyph, Some(CachedGlyph::EMPTY));
+        assert_eq!(cache.atlas.pages(), 0);
+    }
+
+    #[test]
+    fn evicted_pages_drop_their_glyphs() {
+        // A single page, with room for a single glyph.
+        let mut cache = GlyphCache::new(16, 1);
+
+        cache.begin_frame();
+        let first = cache.get_or_rasterize(key(1), |_| Some(solid(15, 15, 10)));
+        assert!(first.and_then(|glyph| glyph.region).is_some());
+        // The page is in use in this frame, nothing can be evicted.
+        assert_eq!(cache.get_or_rasterize(key(2), |_| Some(solid(15, 15, 20))), None);
+
+        cache.begin_frame();
+        let second = cache.get_or_rasterize(key(2), |_| Some(solid(15, 15, 20)));
+        assert_eq!(second.and_then(|glyph| glyph.region.map(|r| r.page)), Some(0));
+        assert!(!cache.glyphs.contains_key(&key(1)));
+
+        // The page was cleared, only the pixels of the second glyph are left.
+        let mut uploaded = Vec::new();
+        cache.upload_dirty_pages(|page, pixels| uploaded.push((page, pixels.to_vec())));
+        assert_eq!(uploaded.len(), 1);
+        let pixels = &uploaded[0].1;
+        assert_eq!(pixels[0], 20);
+        assert_eq!(pixels[15], 0);
+        assert_eq!(pixels[15 * 16], 0);
+
+        // The first glyph must be rasterized again.
+        let mut rasterized = false;
+        cache.begin_frame();
+        cache.get_or_rasterize(key(1), |_| {
+            rasterized = true;
+            Some(solid(15, 15, 10))
+        });
+        assert!(rasterized);
+        assert!(!cache.glyphs.contains_key(&key(2)));
+    }
+
+    #[test]
+    fn pages_are_uploaded_once_per_change() {
+        let mut cache = GlyphCache::new(16, 2);
+        cache.get_or_rasterize(key(1), |_| Some(solid(2, 2, 255)));
+
+        let mut uploads = 0;
+        cache.upload_dirty_pages(|_, _| uploads += 1);
+        cache.upload_dirty_pages(|_, _| uploads += 1);
+        assert_eq!(uploads, 1);
+
+        cache.clear();
+        cache.upload_dirty_pages(|_, pixels| {
+            uploads += 1;
+            assert!(pixels.iter().all(|pixel| *pixel == 0));
+        });
+        assert_eq!(uploads, 2);
+    }
+
+    #[test]
+    fn marked_pages_are_uploaded_again() {
+        // Room for a single glyph per page.
+        let mut cache = GlyphCache::new(16, 2);
+        cache.get_or_rasterize(key(1), |_| Some(solid(15, 15, 255)));
+        cache.get_or_rasterize(key(2), |_| Some(solid(15, 15, 255)));
+        assert_eq!(cache.pages(), 2);
+        cache.upload_dirty_pages(|_, _| {});
+
+        cache.mark_pages_dirty();
+        let mut uploaded = Vec::new();
+        cache.upload_dirty_pages(|page, _| uploaded.push(page));
+        assert_eq!(uploaded, [0, 1]);
+    }
+}
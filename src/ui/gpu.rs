@@ -71,6 +71,11 @@ impl Gpu {
         self.swap_chain_descr.format
     }
 
+    /// Size of the surface drawn to.
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
         self.size = new_size;
         self.swap_chain_descr.width = new_size.width;
@@ -98,6 +103,21 @@ impl Gpu {
         self.device.create_render_pipeline(descr)
     }
 
+    pub fn create_bind_group_layout(
+        &self,
+        descr: &wgpu::BindGroupLayoutDescriptor<'_>,
+    ) -> wgpu::BindGroupLayout {
+        self.device.create_bind_group_layout(descr)
+    }
+
+    pub fn create_bind_group(&self, descr: &wgpu::BindGroupDescriptor<'_>) -> wgpu::BindGroup {
+        self.device.create_bind_group(descr)
+    }
+
+    pub fn create_sampler(&self, descr: &wgpu::SamplerDescriptor<'_>) -> wgpu::Sampler {
+        self.device.create_sampler(descr)
+    }
+
     pub fn create_buffer(&self, descr: &wgpu::BufferDescriptor<'_>) -> wgpu::Buffer {
         self.device.create_buffer(descr)
     }
@@ -106,6 +126,20 @@ impl Gpu {
         self.device.create_buffer_init(descr)
     }
 
+    pub fn create_texture(&self, descr: &wgpu::TextureDescriptor<'_>) -> wgpu::Texture {
+        self.device.create_texture(descr)
+    }
+
+    pub fn write_texture(
+        &self,
+        texture: wgpu::TextureCopyView<'_>,
+        data: &[u8],
+        data_layout: wgpu::TextureDataLayout,
+        size: wgpu::Extent3d,
+    ) {
+        self.queue.write_texture(texture, data, data_layout, size)
+    }
+
     pub fn write_buffer(
         &mut self,
         encoder: &mut wgpu::CommandEncoder,
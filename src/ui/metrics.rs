@@ -0,0 +1,289 @@
+//! # Cell Metrics
+//!
+//! The geometry of a grid cell, derived from the metrics of the selected font, its
+//! size, the `linespace` option and the scale factor of the window.
+//!
+//! All values are in physical pixels. Neovim sizes (`guifont` heights and `linespace`)
+//! are in logical pixels, so fractional scale factors are applied before rounding, and
+//! the cell size is always a whole number of physical pixels. This way, every cell
+//! starts at a pixel boundary and rows and columns never drift apart.
+use super::font::FontFace;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+/// Pixels per point, for a 96 DPI logical screen.
+const PIXELS_PER_POINT: f32 = 96.0 / 72.0;
+/// Character used to measure the cell width of monospaced fonts.
+const REFERENCE_CHAR: char = 'M';
+
+/// The metrics of a font at a given size, in logical pixels.
+///
+/// Unlike [`FontFace`], this is plain data, so it can be kept around to
+/// recompute the [`CellMetrics`] when the scale factor or the `linespace` change.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FontMetrics {
+    /// Size of the font, in points.
+    pub size: f32,
+    /// Advance of the reference character.
+    pub advance: f32,
+    /// Distance from the baseline to the top of the line.
+    pub ascent: f32,
+    /// Distance from the baseline to the bottom of the line, positive below it.
+    pub descent: f32,
+    /// Extra space between lines suggested by the font.
+    pub line_gap: f32,
+    /// Distance from the baseline to the top of the underline, positive below it.
+    pub underline_position: f32,
+    /// Thickness of the underline.
+    pub underline_thickness: f32,
+    /// Distance from the baseline to the top of the strikeout, positive above it.
+    pub strikeout_position: f32,
+    /// Thickness of the strikeout.
+    pub strikeout_thickness: f32,
+    /// Height of non-ascending lowercase letters.
+    pub x_height: f32,
+}
+
+impl FontMetrics {
+    /// Measure `face` at `size` points, or `None` if it can't be loaded.
+    pub fn from_face(face: &FontFace, size: f32) -> Option<Self> {
+        let font = face.load()?;
+        let metrics = font.metrics();
+        let scale = size * PIXELS_PER_POINT / metrics.units_per_em as f32;
+
+        // Proportional fonts can miss the reference character, their average
+        // character width is still a better guess than nothing.
+        let advance = font
+            .glyph_for_char(REFERENCE_CHAR)
+            .and_then(|glyph| font.advance(glyph).ok())
+            .map(|advance| advance.x())
+            .filter(|advance| *advance > 0.0)
+            .unwrap_or(metrics.units_per_em as f32 / 2.0);
+
+        // Fonts without an underline position would draw it over the baseline.
+        let underline_position = if metrics.underline_position < 0.0 {
+            -metrics.underline_position
+        } else {
+            metrics.descent.abs() / 2.0
+        };
+        let underline_thickness = if metrics.underline_thickness > 0.0 {
+            metrics.underline_thickness
+        } else {
+            metrics.units_per_em as f32 / 14.0
+        };
+        // Fonts without strikeout metrics get a line centered on the lowercase letters.
+        let (strikeout_position, strikeout_thickness) = face
+            .parsed()
+            .and_then(|parsed| parsed.strikeout_metrics())
+            .filter(|strikeout| strikeout.position > 0 && strikeout.thickness > 0)
+            .map_or(
+                (
+                    metrics.x_height / 2.0 + underline_thickness / 2.0,
+                    underline_thickness,
+                ),
+                |strikeout| (strikeout.position as f32, strikeout.thickness as f32),
+            );
+
+        Some(Self {
+            size,
+            advance: advance * scale,
+            ascent: metrics.ascent * scale,
+            descent: metrics.descent.abs() * scale,
+            line_gap: metrics.line_gap.max(0.0) * scale,
+            underline_position: underline_position * scale,
+            underline_thickness: underline_thickness * scale,
+            strikeout_position: strikeout_position * scale,
+            strikeout_thickness: strikeout_thickness * scale,
+            x_height: metrics.x_height.max(0.0) * scale,
+        })
+    }
+
+    /// Use cells of `width` points, instead of the advance of the font.
+    pub fn with_cell_width(self, width: f32) -> Self {
+        Self {
+            advance: width * PIXELS_PER_POINT,
+            ..self
+        }
+    }
+}
+
+/// The geometry of a grid cell, in physical pixels.
+///
+/// Vertical positions are relative to the top of the cell.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CellMetrics {
+    /// Width of a cell.
+    pub width: u32,
+    /// Height of a cell, including the `linespace`.
+    pub height: u32,
+    /// Distance from the top of the cell to the baseline.
+    pub baseline: u32,
+    /// Distance from the baseline to the top of the glyphs.
+    pub ascent: f32,
+    /// Distance from the baseline to the bottom of the glyphs.
+    pub descent: f32,
+    /// Top of the underline.
+    pub underline_position: u32,
+    /// Thickness of the underline.
+    pub underline_thickness: u32,
+    /// Top of the strikethrough.
+    pub strikethrough_position: u32,
+    /// Thickness of the strikethrough.
+    pub strikethrough_thickness: u32,
+    /// Size of the font.
+    pub pixels_per_em: f32,
+}
+
+impl CellMetrics {
+    /// Compute the cell geometry for `font` at the given scale factor.
+    ///
+    /// `linespace` is given in logical pixels and is split between the top and bottom
+    /// of the cell, keeping the text vertically centered. Negative values shrink the
+    /// cell, but never below a single pixel.
+    pub fn new(font: &FontMetrics, scale_factor: f64, linespace: i64) -> Self {
+        let scale = scale_factor as f32;
+
+        let width = (font.advance * scale).round().max(1.0) as u32;
+        let ascent = font.ascent * scale;
+        let descent = font.descent * scale;
+        let line_gap = font.line_gap * scale;
+        let linespace = (linespace as f32 * scale).round();
+
+        let text_height = (ascent + descent + line_gap).round();
+        let height = (text_height + linespace).max(1.0) as u32;
+
+        let space_above = (line_gap + linespace) / 2.0;
+        let baseline = (space_above + ascent).round().max(0.0) as u32;
+        let baseline = baseline.min(height - 1);
+
+        let underline_thickness = (font.underline_thickness * scale).round().max(1.0) as u32;
+        let strikethrough_thickness = (font.strikeout_thickness * scale).round().max(1.0) as u32;
+        // Keep the decorations inside the cell, so that they aren't cut by the next row.
+        let underline_position = (baseline as f32 + font.underline_position * scale).round() as u32;
+        let strikethrough_position = (baseline as f32 - font.strikeout_position * scale)
+            .round()
+            .max(0.0) as u32;
+
+        Self {
+            width,
+            height,
+            baseline,
+            ascent,
+            descent,
+            underline_position: underline_position.min(height.saturating_sub(underline_thickness)),
+            underline_thickness,
+            strikethrough_position: strikethrough_position
+                .min(height.saturating_sub(strikethrough_thickness)),
+            strikethrough_thickness,
+            pixels_per_em: font.size * PIXELS_PER_POINT * scale,
+        }
+    }
+
+    /// The cell under a position of the window, as `(row, col)`.
+    pub fn cell_at(&self, position: PhysicalPosition<f64>) -> (u64, u64) {
+        let row = position.y.max(0.0) as u64 / self.height as u64;
+        let col = position.x.max(0.0) as u64 / self.width as u64;
+
+        (row, col)
+    }
+
+    /// The number of `(rows, cols)` of a grid that fits in a window of `size`.
+    pub fn grid_size(&self, size: PhysicalSize<u32>) -> (u64, u64) {
+        let rows = size.height / self.height;
+        let cols = size.width / self.width;
+
+        // Neovim doesn't accept empty grids.
+        (rows.max(1) as u64, cols.max(1) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 10 point font, with metrics that don't fall on whole pixels.
+    const FONT: FontMetrics = FontMetrics {
+        size: 10.0,
+        advance: 7.4,
+        ascent: 9.3,
+        descent: 2.4,
+        line_gap: 0.0,
+        underline_position: 1.5,
+        underline_thickness: 0.8,
+        strikeout_position: 3.0,
+        strikeout_thickness: 0.8,
+        x_height: 5.0,
+    };
+
+    #[test]
+    fn cell_sizes_are_rounded_to_whole_pixels() {
+        let cell = CellMetrics::new(&FONT, 1.0, 0);
+
+        assert_eq!((cell.width, cell.height, cell.baseline), (7, 12, 9));
+        assert_eq!((cell.underline_position, cell.underline_thickness), (11, 1));
+        assert_eq!(
+            (cell.strikethrough_position, cell.strikethrough_thickness),
+            (6, 1)
+        );
+        assert_eq!(cell.ascent, 9.3);
+    }
+
+    #[test]
+    fn fractional_scale_factors_are_applied_before_rounding() {
+        let cell = CellMetrics::new(&FONT, 1.5, 0);
+
+        // 11.1 by 17.55 pixels.
+        assert_eq!((cell.width, cell.height, cell.baseline), (11, 18, 14));
+        assert_eq!((cell.underline_position, cell.underline_thickness), (16, 1));
+        assert_eq!(cell.strikethrough_position, 10);
+        assert_eq!(cell.pixels_per_em, 20.0);
+
+        // 9.25 by 14.625 pixels.
+        let cell = CellMetrics::new(&FONT, 1.25, 0);
+        assert_eq!((cell.width, cell.height, cell.baseline), (9, 15, 12));
+
+        // The cell width set in points is scaled too.
+        let cell = CellMetrics::new(&FONT.with_cell_width(7.5), 1.25, 0);
+        assert_eq!(cell.width, 13);
+    }
+
+    #[test]
+    fn positive_linespace_is_split_around_the_text() {
+        let cell = CellMetrics::new(&FONT, 1.0, 4);
+
+        assert_eq!((cell.height, cell.baseline), (16, 11));
+        assert_eq!(cell.underline_position, 13);
+        assert_eq!(cell.strikethrough_position, 8);
+
+        // 3 logical pixels are 4.5 physical pixels, rounded once.
+        let cell = CellMetrics::new(&FONT, 1.5, 3);
+        assert_eq!((cell.height, cell.baseline), (23, 16));
+    }
+
+    #[test]
+    fn negative_linespace_shrinks_the_cell_down_to_a_pixel() {
+        let cell = CellMetrics::new(&FONT, 1.0, -4);
+
+        assert_eq!((cell.height, cell.baseline), (8, 7));
+        // The underline stays in the cell.
+        assert_eq!(cell.underline_position, 7);
+        assert_eq!(cell.strikethrough_position, 4);
+
+        let cell = CellMetrics::new(&FONT, 1.0, -20);
+        assert_eq!(cell.width, 7);
+        assert_eq!((cell.height, cell.baseline), (1, 0));
+        assert_eq!(
+            (cell.underline_position, cell.strikethrough_position),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn grids_fill_the_window_with_whole_cells() {
+        let cell = CellMetrics::new(&FONT, 1.0, 0);
+
+        assert_eq!(cell.grid_size(PhysicalSize::new(800, 600)), (50, 114));
+        assert_eq!(cell.grid_size(PhysicalSize::new(3, 3)), (1, 1));
+        assert_eq!(cell.cell_at(PhysicalPosition::new(13.9, 24.0)), (2, 1));
+        assert_eq!(cell.cell_at(PhysicalPosition::new(-5.0, -1.0)), (0, 0));
+    }
+}
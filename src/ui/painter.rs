@@ -0,0 +1,350 @@
+//! # Frame Painting
+//!
+//! Draws a published [`Frame`] with the GPU renderers.
+//!
+//! Layers are painted from bottom to top. Each row of a layer is shaped, see
+//! [`Shaper`], and its glyphs are rasterized through the [`GlyphCache`], with box
+//! drawing characters drawn by [`box_drawing`] to fill their cells. Glyphs of color
+//! faces are rasterized in color when they have color data, see [`color_glyph`], and
+//! drawn untinted. The backgrounds, the cursor, the glyphs and then the decorations of
+//! a layer are drawn before the next layer, so floating windows cover the text below
+//! them. Decorations are quads, undercurls are approximated by small squares along
+//! their curves. The link under the mouse pointer is underlined last.
+//!
+//! [`color_glyph`]: super::color_glyph
+use super::box_drawing;
+use super::decorations::{decorate_line, LineDecorations};
+use super::fallback::{FaceSource, FontFallback};
+use super::glyph_cache::{GlyphCache, GlyphKey, Rasterizer};
+use super::gpu::Gpu;
+use super::metrics::CellMetrics;
+use super::renderers::{GlyphQuad, GlyphRenderer, Quad, QuadRenderer};
+use super::shaper::Shaper;
+use crate::color::Color;
+use crate::compositor::Layer;
+use crate::editor::Frame;
+use crate::grid::links::Link;
+use crate::grid::rendered::RenderedLine;
+use crate::neovim::CursorShape;
+use winit::dpi::PhysicalSize;
+
+/// Draws a frame, using the fonts and caches of the UI.
+pub struct Painter<'a> {
+    pub frame: &'a Frame,
+    pub cell: CellMetrics,
+    /// Size of the surface drawn to.
+    pub size: PhysicalSize<u32>,
+    pub fonts: &'a mut FontFallback,
+    pub shaper: &'a mut Shaper,
+    pub glyph_cache: &'a mut GlyphCache,
+    pub color_glyph_cache: &'a mut GlyphCache,
+    pub rasterizer: Rasterizer,
+    pub quads: &'a mut QuadRenderer,
+    pub glyphs: &'a mut GlyphRenderer,
+    pub color_glyphs: &'a mut GlyphRenderer,
+}
+
+impl Painter<'_> {
+    /// Draw all layers of the frame over `target`, then underline the `hovered` link.
+    pub fn paint(
+        &mut self,
+        target: &wgpu::TextureView,
+        encoder: &mut wgpu::CommandEncoder,
+        gpu: &mut Gpu,
+        hovered: Option<&Link>,
+    ) {
+        let frame = self.frame;
+
+        for layer in &frame.layers {
+            let decorations = self.queue_layer(layer);
+
+            self.quads.render_in(target, encoder, gpu);
+            self.glyphs.render_in(target, encoder, gpu);
+            self.color_glyphs.render_in(target, encoder, gpu);
+
+            // Strikethroughs go over the text.
+            for (y, decorations) in decorations {
+                self.queue_decorations(&decorations, layer, y);
+            }
+            self.quads.render_in(target, encoder, gpu);
+        }
+
+        if let Some(link) = hovered {
+            self.queue_link_underline(link);
+            self.quads.render_in(target, encoder, gpu);
+        }
+    }
+
+    /// Queue the backgrounds, the cursor and the glyphs of `layer`.
+    ///
+    /// Returns the decorations of its lines, with the top of each line.
+    fn queue_layer(&mut self, layer: &Layer) -> Vec<(f32, LineDecorations)> {
+        let frame = self.frame;
+        let mut decorations = Vec::new();
+        let lines = match frame.grids.get(layer.grid) {
+            Some(lines) => lines,
+            None => return decorations,
+        };
+
+        let rows = lines.rendered_lines(&frame.hl_groups).take(layer.height);
+        for (row, line) in rows.enumerate() {
+            let y = ((layer.row + row) as u32 * self.cell.height) as f32;
+
+            self.queue_backgrounds(line.clone(), layer, y);
+
+            let line_decorations = decorate_line(line.clone(), layer.width, &self.cell);
+            if !line_decorations.is_empty() {
+                decorations.push((y, line_decorations));
+            }
+
+            let cursor = &frame.cursor;
+            let cursor_col = Some(cursor.col)
+                .filter(|col| cursor.grid == layer.grid && cursor.row == row && *col < layer.width);
+
+            if let Some(col) = cursor_col {
+                let wide = lines.cell_text(row, col + 1) == Some("");
+                self.queue_cursor(layer.col + col, y, wide);
+            }
+
+            self.queue_glyphs(line, layer, y, cursor_col);
+        }
+
+        decorations
+    }
+
+    /// Fill the cells of each section of `line` with its background.
+    fn queue_backgrounds(&mut self, line: RenderedLine<'_>, layer: &Layer, y: f32) {
+        let cell = self.cell;
+
+        for section in line {
+            let end = (section.col + section.width).min(layer.width);
+            if section.col >= end {
+                continue;
+            }
+
+            self.quads.queue(Quad::from_pixels(
+                ((layer.col + section.col) as u32 * cell.width) as f32,
+                y,
+                ((end - section.col) as u32 * cell.width) as f32,
+                cell.height as f32,
+                section.rgb_attr.background.unwrap_or(Color::BLACK),
+                self.size,
+            ));
+        }
+    }
+
+    /// Draw the cursor over the cell at screen column `col`, two cells if it is `wide`.
+    fn queue_cursor(&mut self, col: usize, y: f32, wide: bool) {
+        let cell = self.cell;
+        let cursor = &self.frame.cursor;
+        let color = self
+            .frame
+            .cursor_color_set()
+            .background
+            .unwrap_or(Color::WHITE);
+
+        let cols = if wide { 2 } else { 1 };
+        let (width, height) = ((cell.width * cols) as f32, cell.height as f32);
+        let percentage = cursor.cell_percentage as f32;
+
+        let (width, y, height) = match cursor.shape {
+            CursorShape::Block => (width, y, height),
+            CursorShape::Vertical => ((width * percentage).round().max(1.0), y, height),
+            CursorShape::Horizontal => {
+                let bar = (height * percentage).round().max(1.0);
+                (width, y + height - bar, bar)
+            }
+        };
+
+        self.quads.queue(Quad::from_pixels(
+            (col as u32 * cell.width) as f32,
+            y,
+            width,
+            height,
+            color,
+            self.size,
+        ));
+    }
+
+    /// Shape `line` and queue its glyphs.
+    ///
+    /// `cursor_col` is the column of the cursor, if it is in the line. Glyphs under a
+    /// block cursor are drawn with the colors of the cursor. Glyphs of runs of color
+    /// faces are queued in color, or as masks if they have no color data.
+    fn queue_glyphs(
+        &mut self,
+        line: RenderedLine<'_>,
+        layer: &Layer,
+        y: f32,
+        cursor_col: Option<usize>,
+    ) {
+        let frame = self.frame;
+        let cell = self.cell;
+        let page_size = self.glyph_cache.page_size();
+        let color_page_size = self.color_glyph_cache.page_size();
+
+        let block_cursor = cursor_col.filter(|_| matches!(frame.cursor.shape, CursorShape::Block));
+        let cursor_foreground = frame.cursor_color_set().foreground;
+
+        let shaped = self
+            .shaper
+            .shape_line(line, self.fonts, cursor_col, cell.pixels_per_em);
+
+        for run in &shaped.runs {
+            let foreground = frame
+                .hl_groups
+                .group_color_set(run.hl_id)
+                .foreground
+                .unwrap_or(Color::WHITE);
+            // Procedural glyphs are drawn without a font.
+            let face = match run.source {
+                FaceSource::Procedural => None,
+                source => Some(self.fonts.faces(source).select(run.style).0),
+            };
+
+            for glyph in &run.glyphs {
+                if glyph.col >= layer.width {
+                    continue;
+                }
+
+                let x = ((layer.col + glyph.col) as u32 * cell.width) as f32 + glyph.x;
+                let color = match block_cursor {
+                    Some(col) if col == glyph.col => cursor_foreground.unwrap_or(foreground),
+                    _ => foreground,
+                };
+                let rasterizer = &mut self.rasterizer;
+
+                let (key, pixel, cached, in_color) = match face {
+                    Some(face) => {
+                        let (key, pixel) = GlyphKey::new(
+                            face.id(),
+                            glyph.id,
+                            run.synthesis,
+                            cell.pixels_per_em,
+                            x,
+                        );
+                        let color_key = key.with_foreground(color);
+                        let colored = if run.color {
+                            self.color_glyph_cache.get_or_rasterize(color_key, |key| {
+                                rasterizer.rasterize_color(face, key)
+                            })
+                        } else {
+                            None
+                        };
+
+                        match colored {
+                            Some(cached) if cached.region.is_some() => {
+                                (color_key, pixel, Some(cached), true)
+                            }
+                            // Glyphs without color data are cached as empty, they are
+                            // drawn as masks, like those with no room in the color atlas.
+                            _ => {
+                                let cached = self
+                                    .glyph_cache
+                                    .get_or_rasterize(key, |key| rasterizer.rasterize(face, key));
+                                (key, pixel, cached, false)
+                            }
+                        }
+                    }
+                    None => {
+                        let chr = std::char::from_u32(glyph.id).unwrap_or_default();
+                        let cols = glyph.width as u32;
+                        let key = GlyphKey::procedural(chr, cols, &cell);
+                        let cached = self
+                            .glyph_cache
+                            .get_or_rasterize(key, |_| box_drawing::rasterize(chr, cols, &cell));
+                        (key, x as i32, cached, false)
+                    }
+                };
+                let cached = match cached {
+                    Some(cached) => cached,
+                    None => {
+                        log::warn!("No room in the glyph atlas for {:?}", key);
+                        continue;
+                    }
+                };
+                let region = match cached.region {
+                    Some(region) => region,
+                    None => continue,
+                };
+
+                // Color glyphs only take the alpha of the color.
+                let (glyphs, page_size) = if in_color {
+                    (&mut *self.color_glyphs, color_page_size)
+                } else {
+                    (&mut *self.glyphs, page_size)
+                };
+                glyphs.queue(GlyphQuad::new(
+                    (pixel + cached.left) as f32,
+                    y + cell.baseline as f32 - glyph.y.round() - cached.top as f32,
+                    region,
+                    page_size,
+                    color,
+                    self.size,
+                ));
+            }
+        }
+    }
+
+    /// Queue the decorations of a line of `layer`, whose top is at `y`.
+    fn queue_decorations(&mut self, decorations: &LineDecorations, layer: &Layer, y: f32) {
+        let left = (layer.col as u32 * self.cell.width) as f32;
+
+        for line in &decorations.lines {
+            self.quads.queue(Quad::from_pixels(
+                left + line.x,
+                y + line.y,
+                line.width,
+                line.height,
+                line.color,
+                self.size,
+            ));
+        }
+
+        for curve in &decorations.curves {
+            // Squares as thick as the curve, about half a pixel apart.
+            let [start, control, end] = [curve.start, curve.control, curve.end];
+            let length = (control[0] - start[0]).hypot(control[1] - start[1])
+                + (end[0] - control[0]).hypot(end[1] - control[1]);
+            let steps = (length * 2.0).ceil().max(1.0) as usize;
+            let half = curve.thickness / 2.0;
+
+            for step in 0..=steps {
+                let [x, curve_y] = curve.point(step as f32 / steps as f32);
+                self.quads.queue(Quad::from_pixels(
+                    left + x - half,
+                    y + curve_y - half,
+                    curve.thickness,
+                    curve.thickness,
+                    curve.color,
+                    self.size,
+                ));
+            }
+        }
+    }
+
+    /// Underline `link`, with the foreground color of its first cell.
+    fn queue_link_underline(&mut self, link: &Link) {
+        let frame = self.frame;
+        let cell = self.cell;
+
+        let hl_id = frame
+            .grid_position(link.row, link.cols.start)
+            .and_then(|(grid, row, col)| frame.grids.get(grid)?.hl_id_at(row, col))
+            .unwrap_or(0);
+        let color = frame
+            .hl_groups
+            .group_color_set(hl_id)
+            .foreground
+            .unwrap_or(Color::WHITE);
+
+        self.quads.queue(Quad::from_pixels(
+            (link.cols.start as u32 * cell.width) as f32,
+            (link.row as u32 * cell.height + cell.underline_position) as f32,
+            (link.cols.len() as u32 * cell.width) as f32,
+            cell.underline_thickness as f32,
+            color,
+            self.size,
+        ));
+    }
+}
@@ -1,7 +1,19 @@
+use super::atlas::AtlasRegion;
+use super::glyph_cache::{GlyphCache, PixelFormat};
 use super::gpu::Gpu;
 use crate::color::Color;
+use winit::dpi::PhysicalSize;
 use zerocopy::AsBytes;
 
+/// Convert a position in pixels of a surface of `size`, from its top left corner, to
+/// normalized device coordinates.
+fn to_ndc(size: PhysicalSize<u32>, x: f32, y: f32) -> [f32; 2] {
+    [
+        x / size.width as f32 * 2.0 - 1.0,
+        1.0 - y / size.height as f32 * 2.0,
+    ]
+}
+
 #[derive(Copy, Clone, Debug, Default, AsBytes)]
 #[repr(C)]
 pub struct Quad {
@@ -21,6 +33,27 @@ impl Quad {
         }
     }
 
+    /// A rectangle with its top left corner at `(x, y)`, in pixels of a surface of
+    /// `size`, filled with the sRGB `color`.
+    pub fn from_pixels(
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        color: Color,
+        size: PhysicalSize<u32>,
+    ) -> Self {
+        let [x, y] = to_ndc(size, x + width / 2.0, y + height / 2.0);
+
+        Self::new(
+            x,
+            y,
+            width / size.width as f32 * 2.0,
+            height / size.height as f32 * 2.0,
+            color.to_linear(),
+        )
+    }
+
     const fn x(&self) -> f32 {
         self.position[0]
     }
@@ -192,5 +225,404 @@ impl QuadRenderer {
 
             i += Self::MAX_INSTANCES;
         }
+
+        self.pending.clear();
+    }
+}
+
+/// The pages of a [`GlyphCache`] in the GPU, as the layers of a texture array.
+struct GlyphAtlasTexture {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    /// Number of layers of the texture.
+    pages: usize,
+}
+
+impl GlyphAtlasTexture {
+    fn new(gpu: &Gpu, page_size: u32, pages: usize, format: PixelFormat) -> Self {
+        let texture = gpu.create_texture(&wgpu::TextureDescriptor {
+            label: Some("weovim::ui::renderers::glyph atlas texture"),
+            size: wgpu::Extent3d {
+                width: page_size,
+                height: page_size,
+                depth: pages as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: match format {
+                PixelFormat::Alpha => wgpu::TextureFormat::R8Unorm,
+                // Color glyphs are in sRGB, like the surface.
+                PixelFormat::Rgba => wgpu::TextureFormat::Rgba8UnormSrgb,
+            },
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("weovim::ui::renderers::glyph atlas view"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            pages,
+        }
+    }
+}
+
+/// A glyph of an atlas, drawn in a rectangle of the surface.
+#[derive(Copy, Clone, Debug, Default, AsBytes)]
+#[repr(C)]
+pub struct GlyphQuad {
+    /// The top left corner and the size of the rectangle, in normalized device
+    /// coordinates.
+    position: [f32; 2],
+    size: [f32; 2],
+    /// The top left corner and the size of the glyph in its page, in texture coordinates.
+    uv_position: [f32; 2],
+    uv_size: [f32; 2],
+    /// The page of the glyph.
+    page: f32,
+    pub color: Color,
+}
+
+impl GlyphQuad {
+    /// The glyph in `region` of an atlas of pages of `page_size`, with its top left
+    /// corner at `(x, y)` pixels of a surface of `size`, tinted with the sRGB `color`.
+    pub fn new(
+        x: f32,
+        y: f32,
+        region: AtlasRegion,
+        page_size: u32,
+        color: Color,
+        size: PhysicalSize<u32>,
+    ) -> Self {
+        let page_size = page_size as f32;
+
+        Self {
+            position: to_ndc(size, x, y),
+            size: [
+                region.width as f32 / size.width as f32 * 2.0,
+                -(region.height as f32) / size.height as f32 * 2.0,
+            ],
+            uv_position: [region.x as f32 / page_size, region.y as f32 / page_size],
+            uv_size: [
+                region.width as f32 / page_size,
+                region.height as f32 / page_size,
+            ],
+            page: region.page as f32,
+            color: color.to_linear(),
+        }
+    }
+
+    const fn vertex_buffer_descriptior() -> wgpu::VertexBufferDescriptor<'static> {
+        const COLOR_VERTEX_DESCRIPTOR: wgpu::VertexAttributeDescriptor =
+            Color::vertex_attribute_descriptor(5, 4 * 8 + 4);
+
+        wgpu::VertexBufferDescriptor {
+            stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float2,
+                    offset: 0,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float2,
+                    offset: 8,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float2,
+                    offset: 8 * 2,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float2,
+                    offset: 8 * 3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float,
+                    offset: 8 * 4,
+                },
+                COLOR_VERTEX_DESCRIPTOR,
+            ],
+        }
+    }
+}
+
+/// Draws the glyphs of a [`GlyphCache`], blending them over the surface.
+///
+/// The pages of the cache are uploaded to a texture that grows with them, up to the
+/// maximum number of pages.
+pub struct GlyphRenderer {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    /// The texture of the pages and its bind group, once there are pages.
+    atlas: Option<(GlyphAtlasTexture, wgpu::BindGroup)>,
+    page_size: u32,
+    max_pages: usize,
+    format: PixelFormat,
+    indices: wgpu::Buffer,
+    instances: wgpu::Buffer,
+    pending: Vec<GlyphQuad>,
+}
+
+impl GlyphRenderer {
+    const MAX_INSTANCES: usize = 10_000;
+    const QUAD_INDICES: [u16; 6] = [0, 1, 2, 0, 2, 3];
+}
+
+impl GlyphRenderer {
+    /// A renderer of glyphs with pixels in `format`, in up to `max_pages` pages of
+    /// `page_size` pixels.
+    ///
+    /// Coverage masks are tinted with the color of each glyph, color glyphs are drawn
+    /// with their own colors and only take the alpha of the color of the glyph.
+    pub fn new(gpu: &mut Gpu, page_size: u32, max_pages: usize, format: PixelFormat) -> Self {
+        let vs_module =
+            gpu.create_shader_module(wgpu::include_spirv!("../../shaders/glyph.vert.spv"));
+        let fs_module = gpu.create_shader_module(match format {
+            PixelFormat::Alpha => wgpu::include_spirv!("../../shaders/glyph.frag.spv"),
+            PixelFormat::Rgba => wgpu::include_spirv!("../../shaders/color_glyph.frag.spv"),
+        });
+        // The pixels of color glyphs are premultiplied.
+        let src_factor = match format {
+            PixelFormat::Alpha => wgpu::BlendFactor::SrcAlpha,
+            PixelFormat::Rgba => wgpu::BlendFactor::One,
+        };
+
+        let bind_group_layout = gpu.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("weovim-glyph-bind-group-layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        dimension: wgpu::TextureViewDimension::D2Array,
+                        component_type: wgpu::TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+            ],
+        });
+
+        // Glyphs are drawn at whole pixels, with the size they were rasterized at.
+        let sampler = gpu.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("weovim::ui::renderers::glyph atlas sampler"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let render_pipeline_layout = gpu.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("weovim-glyph-pipeline-layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = gpu.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("weovim-glyph-renderer-pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+                clamp_depth: false,
+            }),
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: gpu.color_format(),
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            depth_stencil_state: None,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[GlyphQuad::vertex_buffer_descriptior()],
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        let indices = gpu.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("weovim::ui::renderers::glyph indices buffer"),
+            usage: wgpu::BufferUsage::INDEX,
+            contents: Self::QUAD_INDICES.as_bytes(),
+        });
+
+        let instances = gpu.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("weovim::ui::renderers::glyph instances buffer"),
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            size: std::mem::size_of::<GlyphQuad>() as u64 * Self::MAX_INSTANCES as u64,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            atlas: None,
+            page_size,
+            max_pages,
+            format,
+            indices,
+            instances,
+            pending: Vec::with_capacity(1024),
+        }
+    }
+
+    /// Upload the pages of `cache` that changed since the last upload.
+    ///
+    /// Returns `true` if the texture had to grow for the new pages of `cache`, the
+    /// glyphs drawn in this frame may be missing and the frame must be drawn again.
+    pub fn upload(&mut self, gpu: &Gpu, cache: &mut GlyphCache) -> bool {
+        let pages = self.atlas.as_ref().map_or(0, |(texture, _)| texture.pages);
+        let grow = cache.pages() > pages;
+
+        if grow {
+            // Double the pages, to not create a texture for each new page.
+            let pages = cache.pages().next_power_of_two().min(self.max_pages);
+            let texture = GlyphAtlasTexture::new(gpu, self.page_size, pages, self.format);
+            let bind_group = self.bind_group(gpu, &texture);
+
+            self.atlas = Some((texture, bind_group));
+            cache.mark_pages_dirty();
+        }
+
+        let (texture, _) = match &self.atlas {
+            Some(atlas) => atlas,
+            None => return false,
+        };
+        let page_size = self.page_size;
+        let bytes_per_row = page_size * self.format.bytes_per_pixel() as u32;
+
+        cache.upload_dirty_pages(|page, pixels| {
+            gpu.write_texture(
+                wgpu::TextureCopyView {
+                    texture: &texture.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: page as u32,
+                    },
+                },
+                pixels,
+                wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row,
+                    rows_per_image: page_size,
+                },
+                wgpu::Extent3d {
+                    width: page_size,
+                    height: page_size,
+                    depth: 1,
+                },
+            );
+        });
+
+        grow
+    }
+
+    fn bind_group(&self, gpu: &Gpu, texture: &GlyphAtlasTexture) -> wgpu::BindGroup {
+        gpu.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("weovim::ui::renderers::glyph atlas bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        })
+    }
+
+    pub fn queue(&mut self, glyph: GlyphQuad) {
+        self.pending.push(glyph);
+    }
+
+    /// Draw the queued glyphs over `frame`, in order.
+    ///
+    /// Nothing is drawn before the first upload of the pages.
+    pub fn render_in(
+        &mut self,
+        frame: &wgpu::TextureView,
+        encoder: &mut wgpu::CommandEncoder,
+        gpu: &mut Gpu,
+    ) {
+        let bind_group = match &self.atlas {
+            Some((_, bind_group)) => bind_group,
+            None => {
+                self.pending.clear();
+                return;
+            }
+        };
+
+        for glyphs in self.pending.chunks(Self::MAX_INSTANCES) {
+            let instances_bytes = glyphs.as_bytes();
+
+            let size = wgpu::BufferSize::new(instances_bytes.len() as u64).unwrap();
+            gpu.write_buffer(encoder, &self.instances, 0, size)
+                .copy_from_slice(instances_bytes);
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: frame,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.set_index_buffer(self.indices.slice(..));
+            render_pass.set_vertex_buffer(0, self.instances.slice(..));
+
+            render_pass.draw_indexed(0..Self::QUAD_INDICES.len() as u32, 0, 0..glyphs.len() as u32);
+        }
+
+        self.pending.clear();
     }
 }
@@ -0,0 +1,593 @@
+//! # Text Shaping
+//!
+//! Turns the sections of rendered lines into runs of glyphs positioned on the grid.
+//!
+//! The layout is monospace: the advances given by the font are ignored, and every glyph
+//! is snapped to the first column of the cells it was shaped from. Kerning and rounding
+//! errors never accumulate along a line, and the text always lines up with the cells
+//! Neovim laid out. Glyphs of the same cells, like combining marks, keep their position
+//! relative to the first one.
+//!
+//! Sections are split into runs of cells drawn with the same faces: the `guifont`, the
+//! `guifontwide` for double-width cells, or a fallback font for characters missing
+//! from them, see [`FontFallback`]. Box drawing, block and powerline characters aren't
+//! shaped, each cell gets a glyph with the character as its id, to be drawn by
+//! [`box_drawing`](super::box_drawing).
+//!
+//! Ligatures, from the `calt` and `liga` features, join adjacent cells of the same
+//! section. The cell under the cursor is always shaped on its own, so the cursor never
+//! hides half of a ligature. Ligatures and other features are disabled as configured
+//! by the [`LigatureConfig`].
+//!
+//! The output is plain data, independent of the GPU.
+use super::fallback::{FaceSource, FontFallback};
+use super::font::{FaceStyle, FontFace, Synthesis};
+use crate::grid::rendered::RenderedLine;
+use crate::grid::SectionedLine;
+use crate::ligatures::LigatureConfig;
+use rustybuzz::{Direction, Feature, UnicodeBuffer};
+
+/// Disable the features that join characters into ligatures.
+const LIGATURE_FEATURES: &[&str] = &["-calt", "-liga"];
+
+/// A glyph positioned in a line.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShapedGlyph {
+    /// The glyph id in its face.
+    pub id: u32,
+    /// The first column of the cells the glyph was shaped from.
+    pub col: usize,
+    /// The number of columns of those cells.
+    ///
+    /// More than one for double-width cells and ligatures.
+    pub width: usize,
+    /// Horizontal offset from the left edge of `col`, in pixels.
+    pub x: f32,
+    /// Vertical offset from the baseline, in pixels, positive upwards.
+    pub y: f32,
+}
+
+/// The glyphs of a section of a line, all drawn with the same face and highlight.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphRun {
+    /// The style of the face used to shape the run.
+    pub style: FaceStyle,
+    /// How the glyphs must be transformed when the face lacks part of the style.
+    pub synthesis: Synthesis,
+    /// The highlight group of the section.
+    pub hl_id: u64,
+    /// The first grid column covered by the section.
+    pub col: usize,
+    /// Number of grid columns covered by the section.
+    pub width: usize,
+    /// The faces used to shape the run.
+    pub source: FaceSource,
+    /// Was the run shaped with a color face? Its glyphs must be rasterized with
+    /// [`Rasterizer::rasterize_color`] first, falling back to coverage masks.
+    ///
+    /// [`Rasterizer::rasterize_color`]: super::glyph_cache::Rasterizer::rasterize_color
+    pub color: bool,
+    /// The glyphs, in visual order. Blank cells have no glyphs.
+    pub glyphs: Vec<ShapedGlyph>,
+}
+
+/// The glyph runs of a line.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ShapedLine {
+    pub runs: Vec<GlyphRun>,
+}
+
+/// Shapes lines with a set of OpenType features.
+///
+/// The shaping buffer is re-used between lines.
+pub struct Shaper {
+    features: Vec<Feature>,
+    /// `features`, with the ligature features disabled.
+    no_ligature_features: Vec<Feature>,
+    buffer: Option<UnicodeBuffer>,
+}
+
+impl Default for Shaper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shaper {
+    /// A shaper using the default features of the fonts.
+    pub fn new() -> Self {
+        Self {
+            features: Vec::new(),
+            no_ligature_features: LIGATURE_FEATURES
+                .iter()
+                .copied()
+                .filter_map(parse_feature)
+                .collect(),
+            buffer: Some(UnicodeBuffer::new()),
+        }
+    }
+
+    /// A shaper that enables or disables the given features.
+    ///
+    /// Features use the Harfbuzz syntax, like `liga`, `-calt` or `ss01=1`. Invalid
+    /// features are ignored.
+    pub fn with_features<'f>(features: impl IntoIterator<Item = &'f str>) -> Self {
+        let mut shaper = Self::new();
+        let ligature_tags: Vec<_> = shaper.no_ligature_features.iter().map(|f| f.tag).collect();
+
+        for feature in features.into_iter().filter_map(parse_feature) {
+            shaper.features.push(feature);
+            // Sections without ligatures keep them disabled, even if they are enabled
+            // explicitly.
+            if !ligature_tags.contains(&feature.tag) {
+                shaper.no_ligature_features.push(feature);
+            }
+        }
+
+        shaper
+    }
+
+    /// A shaper disabling the features of `config`, and the ligatures if it disables
+    /// them.
+    pub fn from_config(config: &LigatureConfig) -> Self {
+        let disabled: Vec<String> = config
+            .disabled_features
+            .iter()
+            .map(|tag| format!("-{}", tag))
+            .collect();
+
+        let ligature_features = if config.enabled {
+            &[][..]
+        } else {
+            LIGATURE_FEATURES
+        };
+        let features = disabled.iter().map(String::as_str);
+
+        Self::with_features(features.chain(ligature_features.iter().copied()))
+    }
+
+    /// Shape all sections of `line`.
+    ///
+    /// `cursor` is the column of the cursor, if it is in the line. `pixels_per_em` is
+    /// the physical size of the font, see [`CellMetrics::pixels_per_em`].
+    ///
+    /// [`CellMetrics::pixels_per_em`]: super::metrics::CellMetrics::pixels_per_em
+    pub fn shape_line(
+        &mut self,
+        line: RenderedLine<'_>,
+        fonts: &mut FontFallback,
+        cursor: Option<usize>,
+        pixels_per_em: f32,
+    ) -> ShapedLine {
+        let sectioned = line.sectioned();
+        let mut shaped = ShapedLine::default();
+
+        for section in line {
+            if section.text.trim().is_empty() {
+                continue;
+            }
+
+            let style = FaceStyle::new(section.rgb_attr.bold(), section.rgb_attr.italic());
+            let end = section.start + section.text.len();
+            let segments =
+                split_segments(sectioned, section.start..end, section.col, cursor, fonts);
+
+            for segment in segments {
+                if segment.source == FaceSource::Procedural {
+                    shaped.runs.push(GlyphRun {
+                        style,
+                        synthesis: Synthesis::default(),
+                        hl_id: section.hl_id,
+                        col: segment.col,
+                        width: segment.width,
+                        source: segment.source,
+                        color: false,
+                        glyphs: procedural_glyphs(sectioned, &segment),
+                    });
+                    continue;
+                }
+
+                let (face, synthesis) = fonts.faces(segment.source).select(style);
+
+                let glyphs = match self.shape_segment(
+                    sectioned,
+                    &segment,
+                    face,
+                    section.ligatures,
+                    pixels_per_em,
+                ) {
+                    Some(glyphs) => glyphs,
+                    None => {
+                        log::warn!("Failed to parse the {:?} face for shaping", style);
+                        continue;
+                    }
+                };
+
+                shaped.runs.push(GlyphRun {
+                    style,
+                    synthesis,
+                    hl_id: section.hl_id,
+                    col: segment.col,
+                    width: segment.width,
+                    source: segment.source,
+                    color: face.is_color(),
+                    glyphs,
+                });
+            }
+        }
+
+        shaped
+    }
+
+    /// Shape the text of `segment` with `face`.
+    ///
+    /// Returns `None` if the face can't be parsed.
+    fn shape_segment(
+        &mut self,
+        sectioned: &SectionedLine<u64>,
+        segment: &Segment,
+        face: &FontFace,
+        ligatures: bool,
+        pixels_per_em: f32,
+    ) -> Option<Vec<ShapedGlyph>> {
+        let shaping_face = face.shaping_face()?;
+        let text = &sectioned.text[segment.start..segment.end];
+
+        let mut buffer = self.buffer.take().unwrap_or_default();
+        buffer.push_str(text);
+        // Neovim already gives us the text in visual order.
+        buffer.set_direction(Direction::LeftToRight);
+
+        let features = if ligatures {
+            &self.features
+        } else {
+            &self.no_ligature_features
+        };
+        let output = rustybuzz::shape(&shaping_face, features, buffer);
+        let scale = pixels_per_em / face.units_per_em() as f32;
+
+        let mut glyphs = Vec::with_capacity(output.len());
+        let mut pen = 0.0;
+        let mut prev_cluster = None;
+
+        let infos = output.glyph_infos();
+        let positions = output.glyph_positions();
+        let cluster_ends = cluster_ends(infos.iter().map(|info| info.cluster), text.len());
+
+        for (idx, (info, position)) in infos.iter().zip(positions).enumerate() {
+            let cluster = info.cluster as usize;
+            let cluster_end = cluster_ends[idx];
+
+            if prev_cluster == Some(cluster) {
+                pen += positions[idx - 1].x_advance as f32 * scale;
+            } else {
+                pen = 0.0;
+                prev_cluster = Some(cluster);
+            }
+
+            if text[cluster..cluster_end].trim().is_empty() {
+                continue;
+            }
+
+            let start = segment.start + cluster;
+            let end = segment.start + cluster_end;
+            let (col, width) = match columns_of(sectioned, start, end) {
+                Some(columns) => columns,
+                None => continue,
+            };
+
+            glyphs.push(ShapedGlyph {
+                id: info.codepoint,
+                col,
+                width,
+                x: pen + position.x_offset as f32 * scale,
+                y: position.y_offset as f32 * scale,
+            });
+        }
+
+        self.buffer = Some(output.clear());
+
+        Some(glyphs)
+    }
+}
+
+/// A part of a section shaped as a single run.
+#[derive(Debug, Clone)]
+struct Segment {
+    /// Byte range of the segment in the line.
+    start: usize,
+    end: usize,
+    /// The first column and number of columns of the segment.
+    col: usize,
+    width: usize,
+    /// The faces of all cells of the segment.
+    source: FaceSource,
+    /// Is this the cell under the cursor, which is never joined with others?
+    cursor: bool,
+}
+
+/// Split the section in `range`, starting at `col`, in segments of cells with the
+/// same faces.
+///
+/// The faces of a cell are the ones of its first character. The cell at the `cursor`
+/// column gets a segment of its own.
+fn split_segments(
+    sectioned: &SectionedLine<u64>,
+    range: std::ops::Range<usize>,
+    col: usize,
+    cursor: Option<usize>,
+    fonts: &mut FontFallback,
+) -> Vec<Segment> {
+    let columns = &sectioned.columns;
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut col = col;
+
+    while col < columns.len() && columns[col] < range.end {
+        let cell_start = columns[col];
+        // The right half of a double-width cell starts at the same offset.
+        let wide = columns.get(col + 1) == Some(&cell_start);
+        let cell_width = if wide { 2 } else { 1 };
+        let cell_end = columns
+            .get(col + cell_width)
+            .map_or(range.end, |offset| (*offset).min(range.end));
+
+        let source = match sectioned.text[cell_start..cell_end].chars().next() {
+            Some(chr) => fonts.resolve(chr, wide),
+            None => FaceSource::Primary,
+        };
+        let under_cursor = cursor.is_some_and(|cursor| (col..col + cell_width).contains(&cursor));
+
+        match segments.last_mut() {
+            Some(segment) if segment.source == source && !segment.cursor && !under_cursor => {
+                segment.end = cell_end;
+                segment.width += cell_width;
+            }
+            _ => segments.push(Segment {
+                start: cell_start,
+                end: cell_end,
+                col,
+                width: cell_width,
+                source,
+                cursor: under_cursor,
+            }),
+        }
+
+        col += cell_width;
+    }
+
+    // Text past the last column, if any, goes with the last segment.
+    if let Some(last) = segments.last_mut() {
+        last.end = range.end;
+    }
+
+    segments
+}
+
+/// One glyph per cell of `segment`, with the first character of the cell as its id.
+fn procedural_glyphs(sectioned: &SectionedLine<u64>, segment: &Segment) -> Vec<ShapedGlyph> {
+    let columns = &sectioned.columns;
+    let mut glyphs = Vec::new();
+    let mut col = segment.col;
+
+    while col < segment.col + segment.width {
+        let start = columns[col];
+        let width = if columns.get(col + 1) == Some(&start) {
+            2
+        } else {
+            1
+        };
+
+        if let Some(chr) = sectioned.text[start..segment.end].chars().next() {
+            glyphs.push(ShapedGlyph {
+                id: chr as u32,
+                col,
+                width,
+                x: 0.0,
+                y: 0.0,
+            });
+        }
+
+        col += width;
+    }
+
+    glyphs
+}
+
+fn parse_feature(feature: &str) -> Option<Feature> {
+    match feature.parse() {
+        Ok(feature) => Some(feature),
+        Err(error) => {
+            log::warn!("Invalid font feature {:?}: {}", feature, error);
+            None
+        }
+    }
+}
+
+/// The end of the cluster of each glyph, in bytes of the shaped text.
+///
+/// Clusters are increasing in left to right text, so a cluster ends where the next
+/// one starts, and the last one at the end of the text.
+fn cluster_ends(
+    clusters: impl DoubleEndedIterator<Item = u32> + ExactSizeIterator,
+    text_len: usize,
+) -> Vec<usize> {
+    let mut ends = vec![text_len; clusters.len()];
+    let mut end = text_len;
+    let mut next = None;
+
+    for (idx, cluster) in clusters.enumerate().rev() {
+        let cluster = cluster as usize;
+        if let Some(next) = next.filter(|next| *next != cluster) {
+            end = next;
+        }
+
+        ends[idx] = end;
+        next = Some(cluster);
+    }
+
+    ends
+}
+
+/// The first column and number of columns of the text in `start..end`.
+fn columns_of(sectioned: &SectionedLine<u64>, start: usize, end: usize) -> Option<(usize, usize)> {
+    let col = sectioned.column_at(start)?;
+    // `columns` is sorted, count the columns starting before `end`.
+    let end_col = sectioned.columns.partition_point(|offset| *offset < end);
+
+    Some((col, end_col.saturating_sub(col).max(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::font::FontFaces;
+    use font_kit::handle::Handle;
+    use std::path::PathBuf;
+
+    fn sectioned(text: &str, columns: &[usize]) -> SectionedLine<u64> {
+        SectionedLine {
+            text: String::from(text),
+            sections: Vec::new(),
+            columns: columns.to_vec(),
+        }
+    }
+
+    /// Fall back from the primary fixture font to nothing.
+    fn fonts() -> FontFallback {
+        let fonts = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/fonts"));
+        let face = FontFace::from_handle(&Handle::from_path(fonts.join("primary.ttf"), 0));
+
+        FontFallback::without_system(FontFaces::from_face(face.unwrap()), vec![], None)
+    }
+
+    /// The byte range, columns and cursor flag of the segments of the whole line.
+    fn segments(
+        line: &SectionedLine<u64>,
+        cursor: Option<usize>,
+    ) -> Vec<(std::ops::Range<usize>, usize, usize, bool)> {
+        split_segments(line, 0..line.text.len(), 0, cursor, &mut fonts())
+            .into_iter()
+            .map(|s| (s.start..s.end, s.col, s.width, s.cursor))
+            .collect()
+    }
+
+    fn features(features: &[&str]) -> Vec<Feature> {
+        features.iter().copied().filter_map(parse_feature).collect()
+    }
+
+    #[test]
+    fn the_cursor_cell_is_a_segment_of_its_own() {
+        let line = sectioned("abcd", &[0, 1, 2, 3]);
+
+        assert_eq!(segments(&line, None), vec![(0..4, 0, 4, false)]);
+        assert_eq!(
+            segments(&line, Some(2)),
+            vec![(0..2, 0, 2, false), (2..3, 2, 1, true), (3..4, 3, 1, false)]
+        );
+        // At the edges of the line.
+        assert_eq!(
+            segments(&line, Some(0)),
+            vec![(0..1, 0, 1, true), (1..4, 1, 3, false)]
+        );
+        assert_eq!(
+            segments(&line, Some(3)),
+            vec![(0..3, 0, 3, false), (3..4, 3, 1, true)]
+        );
+        // Out of the line.
+        assert_eq!(segments(&line, Some(7)), vec![(0..4, 0, 4, false)]);
+    }
+
+    #[test]
+    fn the_cursor_on_either_half_of_a_wide_cell_isolates_the_cell() {
+        let line = sectioned("a世b", &[0, 1, 1, 4]);
+        let expected = vec![(0..1, 0, 1, false), (1..4, 1, 2, true), (4..5, 3, 1, false)];
+
+        assert_eq!(segments(&line, Some(1)), expected);
+        assert_eq!(segments(&line, Some(2)), expected);
+    }
+
+    #[test]
+    fn the_cursor_segment_keeps_its_combining_marks() {
+        let line = sectioned("ae\u{301}x", &[0, 1, 4]);
+
+        assert_eq!(
+            segments(&line, Some(1)),
+            vec![(0..1, 0, 1, false), (1..4, 1, 1, true), (4..5, 2, 1, false)]
+        );
+    }
+
+    #[test]
+    fn disabled_ligatures_disable_calt_and_liga() {
+        let config = LigatureConfig {
+            enabled: false,
+            disabled_features: vec![String::from("ss01")],
+            ..LigatureConfig::default()
+        };
+        let shaper = Shaper::from_config(&config);
+
+        assert_eq!(shaper.features, features(&["-ss01", "-calt", "-liga"]));
+
+        let config = LigatureConfig {
+            enabled: true,
+            ..config
+        };
+        let shaper = Shaper::from_config(&config);
+
+        assert_eq!(shaper.features, features(&["-ss01"]));
+        assert_eq!(
+            shaper.no_ligature_features,
+            features(&["-calt", "-liga", "-ss01"])
+        );
+    }
+
+    #[test]
+    fn explicit_ligature_features_do_not_apply_without_ligatures() {
+        let shaper = Shaper::with_features(vec!["liga", "ss01", "calt=1"]);
+
+        assert_eq!(shaper.features, features(&["liga", "ss01", "calt=1"]));
+        assert_eq!(
+            shaper.no_ligature_features,
+            features(&["-calt", "-liga", "ss01"])
+        );
+    }
+
+    #[test]
+    fn clusters_end_where_the_next_one_starts() {
+        assert_eq!(cluster_ends([0, 0, 1, 3].iter().copied(), 5), [1, 1, 3, 5]);
+        assert_eq!(cluster_ends([0, 2, 2].iter().copied(), 4), [2, 4, 4]);
+        assert!(cluster_ends(std::iter::empty(), 3).is_empty());
+    }
+
+    #[test]
+    fn double_width_clusters_cover_two_columns() {
+        // `a世b`, the right half of `世` starts at the same offset as its left half.
+        let line = sectioned("a世b", &[0, 1, 1, 4]);
+
+        assert_eq!(columns_of(&line, 0, 1), Some((0, 1)));
+        assert_eq!(columns_of(&line, 1, 4), Some((1, 2)));
+        assert_eq!(columns_of(&line, 4, 5), Some((3, 1)));
+        // A ligature of the whole line.
+        assert_eq!(columns_of(&line, 0, 5), Some((0, 4)));
+    }
+
+    #[test]
+    fn combining_marks_stay_in_the_cell_of_their_base() {
+        // `e` with a combining acute accent, then `x`.
+        let line = sectioned("e\u{301}x", &[0, 3]);
+        let ends = cluster_ends([0, 0, 3].iter().copied(), line.text.len());
+
+        assert_eq!(ends, [3, 3, 4]);
+        assert_eq!(columns_of(&line, 0, ends[0]), Some((0, 1)));
+        assert_eq!(columns_of(&line, 0, ends[1]), Some((0, 1)));
+        assert_eq!(columns_of(&line, 3, ends[2]), Some((1, 1)));
+    }
+
+    #[test]
+    fn combining_marks_on_double_width_cells() {
+        let line = sectioned("世\u{301}a", &[0, 0, 5]);
+
+        assert_eq!(columns_of(&line, 0, 5), Some((0, 2)));
+        assert_eq!(columns_of(&line, 5, 6), Some((2, 1)));
+        assert_eq!(columns_of(&line, 6, 7), None);
+    }
+}
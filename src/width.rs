@@ -0,0 +1,282 @@
+//! # Text Width
+//!
+//! The number of cells that Neovim uses to display a text, following the same rules as
+//! its `strdisplaywidth()`, including the `ambiwidth` and `emoji` options.
+//!
+//! Neovim already laid out the text of the grids, but frontends still measure it: the
+//! columns of links, the double-width cells cut by a layer, and the cells that a
+//! terminal would measure differently. Using the same rules ensures that what we draw
+//! lines up with the grid.
+use unicode_width::UnicodeWidthChar;
+
+/// Characters that Neovim never prints, showing their code as `<xxxx>` instead.
+const UNPRINTABLE: &[(u32, u32)] = &[
+    (0x070f, 0x070f),
+    (0x180b, 0x180e),
+    (0x200b, 0x200f),
+    (0x202a, 0x202e),
+    (0x2060, 0x206f),
+    (0xd800, 0xdfff),
+    (0xfeff, 0xfeff),
+    (0xfff9, 0xfffb),
+    (0xfffe, 0xffff),
+];
+
+/// Emoji that are only double-width when the `emoji` option is set.
+///
+/// The other emoji are always double-width, as they are in the East Asian Wide
+/// category.
+const EMOJI_WIDTH: &[(u32, u32)] = &[
+    (0x1f1e6, 0x1f1ff),
+    (0x1f321, 0x1f321),
+    (0x1f324, 0x1f32c),
+    (0x1f336, 0x1f336),
+    (0x1f37d, 0x1f37d),
+    (0x1f396, 0x1f397),
+    (0x1f399, 0x1f39b),
+    (0x1f39e, 0x1f39f),
+    (0x1f3cb, 0x1f3ce),
+    (0x1f3d4, 0x1f3df),
+    (0x1f3f3, 0x1f3f5),
+    (0x1f3f7, 0x1f3f7),
+    (0x1f43f, 0x1f43f),
+    (0x1f441, 0x1f441),
+    (0x1f4fd, 0x1f4fd),
+    (0x1f549, 0x1f54a),
+    (0x1f56f, 0x1f570),
+    (0x1f573, 0x1f579),
+    (0x1f587, 0x1f587),
+    (0x1f58a, 0x1f58d),
+    (0x1f590, 0x1f590),
+    (0x1f5a5, 0x1f5a5),
+    (0x1f5a8, 0x1f5a8),
+    (0x1f5b1, 0x1f5b2),
+    (0x1f5bc, 0x1f5bc),
+    (0x1f5c2, 0x1f5c4),
+    (0x1f5d1, 0x1f5d3),
+    (0x1f5dc, 0x1f5de),
+    (0x1f5e1, 0x1f5e1),
+    (0x1f5e3, 0x1f5e3),
+    (0x1f5e8, 0x1f5e8),
+    (0x1f5ef, 0x1f5ef),
+    (0x1f5f3, 0x1f5f3),
+    (0x1f5fa, 0x1f5fa),
+    (0x1f6cb, 0x1f6cf),
+    (0x1f6e0, 0x1f6e5),
+    (0x1f6e9, 0x1f6e9),
+    (0x1f6f0, 0x1f6f0),
+    (0x1f6f3, 0x1f6f3),
+];
+
+/// Value of the `ambiwidth` option.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum AmbiWidth {
+    /// Characters with ambiguous width use a single cell.
+    #[default]
+    Single,
+    /// Characters with ambiguous width use two cells.
+    Double,
+}
+
+impl AmbiWidth {
+    /// Parse the value of the option, as sent by Neovim.
+    pub fn from_option(value: &str) -> Option<Self> {
+        match value {
+            "single" => Some(Self::Single),
+            "double" => Some(Self::Double),
+            _ => None,
+        }
+    }
+}
+
+/// The options that change how many cells a text uses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WidthOptions {
+    /// The `ambiwidth` option.
+    pub ambiwidth: AmbiWidth,
+    /// The `emoji` option.
+    pub emoji: bool,
+}
+
+impl Default for WidthOptions {
+    /// The default values of the options in Neovim.
+    fn default() -> Self {
+        Self {
+            ambiwidth: AmbiWidth::Single,
+            emoji: true,
+        }
+    }
+}
+
+impl WidthOptions {
+    /// The number of cells used to display `text`.
+    pub fn width(&self, text: &str) -> usize {
+        let mut width = 0;
+        let mut has_base = false;
+
+        for chr in text.chars() {
+            // Combining characters are drawn in the cell of the previous character.
+            if has_base && is_composing(chr) {
+                continue;
+            }
+
+            width += self.char_width(chr);
+            has_base = true;
+        }
+
+        width
+    }
+
+    /// The number of cells used to display `chr` on its own.
+    ///
+    /// Control characters are shown as `^X`, and other unprintable characters as their
+    /// code, like `<80>` or `<200b>`.
+    pub fn char_width(&self, chr: char) -> usize {
+        let code = chr as u32;
+
+        if code < 0x20 || code == 0x7f {
+            return 2;
+        }
+        if code < 0x80 {
+            return 1;
+        }
+        if code < 0xa0 {
+            return 4;
+        }
+        if in_table(UNPRINTABLE, code) {
+            return 6;
+        }
+
+        let width = match self.ambiwidth {
+            AmbiWidth::Single => chr.width(),
+            AmbiWidth::Double => chr.width_cjk(),
+        };
+
+        match width {
+            Some(2) => 2,
+            _ if self.emoji && in_table(EMOJI_WIDTH, code) => 2,
+            _ => 1,
+        }
+    }
+
+    /// The longest prefix of `text` that fits in `cols` cells, and its width.
+    ///
+    /// Never splits a character from its combining characters.
+    pub fn truncate<'t>(&self, text: &'t str, cols: usize) -> (&'t str, usize) {
+        let mut width = 0;
+        let mut has_base = false;
+
+        for (idx, chr) in text.char_indices() {
+            if has_base && is_composing(chr) {
+                continue;
+            }
+
+            let chr_width = self.char_width(chr);
+            if width + chr_width > cols {
+                return (&text[..idx], width);
+            }
+
+            width += chr_width;
+            has_base = true;
+        }
+
+        (text, width)
+    }
+}
+
+/// Is `chr` a combining character, drawn together with the previous one?
+fn is_composing(chr: char) -> bool {
+    chr as u32 >= 0x300 && chr.width() == Some(0) && !in_table(UNPRINTABLE, chr as u32)
+}
+
+fn in_table(table: &[(u32, u32)], code: u32) -> bool {
+    table
+        .binary_search_by(|&(start, end)| {
+            if end < code {
+                std::cmp::Ordering::Less
+            } else if start > code {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SINGLE: WidthOptions = WidthOptions {
+        ambiwidth: AmbiWidth::Single,
+        emoji: true,
+    };
+    const DOUBLE: WidthOptions = WidthOptions {
+        ambiwidth: AmbiWidth::Double,
+        emoji: true,
+    };
+    const NO_EMOJI: WidthOptions = WidthOptions {
+        ambiwidth: AmbiWidth::Single,
+        emoji: false,
+    };
+
+    #[test]
+    fn defaults_match_neovim() {
+        assert_eq!(WidthOptions::default(), SINGLE);
+        assert_eq!(AmbiWidth::default(), AmbiWidth::Single);
+    }
+
+    #[test]
+    fn ambiguous_characters_follow_ambiwidth() {
+        for text in &["¡", "·", "○", "→", "★"] {
+            assert_eq!(SINGLE.width(text), 1, "{}", text);
+            assert_eq!(DOUBLE.width(text), 2, "{}", text);
+        }
+
+        // Not ambiguous, the same with both values.
+        for (text, width) in &[("a", 1), ("é", 1), ("世", 2), ("ｱ", 1)] {
+            assert_eq!(SINGLE.width(text), *width, "{}", text);
+            assert_eq!(DOUBLE.width(text), *width, "{}", text);
+        }
+    }
+
+    #[test]
+    fn emoji_option_widens_text_presentation_emoji() {
+        for text in &["\u{1f321}", "\u{1f1e6}", "\u{1f5a5}", "\u{1f6f3}"] {
+            assert_eq!(SINGLE.width(text), 2, "{}", text);
+            assert_eq!(NO_EMOJI.width(text), 1, "{}", text);
+        }
+
+        // East Asian Wide emoji, always double-width.
+        for text in &["\u{1f600}", "\u{1f680}", "\u{231a}"] {
+            assert_eq!(SINGLE.width(text), 2, "{}", text);
+            assert_eq!(NO_EMOJI.width(text), 2, "{}", text);
+        }
+    }
+
+    #[test]
+    fn unprintable_characters_use_their_notation() {
+        assert_eq!(SINGLE.width("\x01"), 2);
+        assert_eq!(SINGLE.width("\x7f"), 2);
+        assert_eq!(SINGLE.width("\u{85}"), 4);
+        assert_eq!(SINGLE.width("\u{200b}"), 6);
+        assert_eq!(SINGLE.width("\u{feff}"), 6);
+    }
+
+    #[test]
+    fn combining_characters_use_the_base_cell() {
+        assert_eq!(SINGLE.width("e\u{301}"), 1);
+        assert_eq!(SINGLE.width("世\u{301}a"), 3);
+        // Without a base character, Neovim draws it on its own cell.
+        assert_eq!(SINGLE.width("\u{301}"), 1);
+    }
+
+    #[test]
+    fn truncate_keeps_whole_characters() {
+        assert_eq!(SINGLE.truncate("a世b", 2), ("a", 1));
+        assert_eq!(SINGLE.truncate("a世b", 3), ("a世", 3));
+        assert_eq!(SINGLE.truncate("e\u{301}x", 1), ("e\u{301}", 1));
+        assert_eq!(DOUBLE.truncate("○○", 3), ("○", 2));
+        assert_eq!(NO_EMOJI.truncate("\u{1f321}\u{1f321}", 1), ("\u{1f321}", 1));
+    }
+}
@@ -0,0 +1,239 @@
+#!/usr/bin/env python3
+"""Write the tiny TrueType fonts used by the font fallback tests.
+
+Each font maps a few characters to a square glyph, and nothing else:
+
+* `primary.ttf`: `a`.
+* `chain.ttf`: `α`.
+* `system.ttf`: `α` and `☃`.
+
+The color glyph tests use two more fonts:
+
+* `bitmap_emoji.ttf`: `☺`, also drawn as a 4x4 PNG in its `sbix` table.
+* `layered_emoji.ttf`: `★`, also drawn with two layers in its `COLR` table, the square in
+  red and a smaller square in the color of the text over it.
+
+Run from this directory, with no dependencies: `python3 generate.py`.
+"""
+
+import struct
+import zlib
+
+UNITS_PER_EM = 1000
+ADVANCE = 600
+ASCENDER = 800
+DESCENDER = -200
+# The square of every glyph but `.notdef`.
+BOX = (50, 0, 550, 700)
+# The square of the top layer of `★` in `layered_emoji.ttf`.
+INNER_BOX = (200, 200, 400, 500)
+# Pixels per em of the `sbix` strike of `bitmap_emoji.ttf`.
+EMOJI_PPEM = 10
+# The rows of the PNG of `☺`, as RGBA: opaque red over half transparent blue.
+EMOJI_PIXELS = [[(255, 0, 0, 255)] * 4] * 2 + [[(0, 0, 255, 128)] * 4] * 2
+
+FONTS = {
+    "primary.ttf": ("Weovim Test Primary", "a"),
+    "chain.ttf": ("Weovim Test Chain", "α"),
+    "system.ttf": ("Weovim Test System", "α☃"),
+}
+
+
+def checksum(data):
+    data += b"\0" * (-len(data) % 4)
+    return sum(struct.unpack(">%dI" % (len(data) // 4), data)) & 0xFFFFFFFF
+
+
+def square(box):
+    x_min, y_min, x_max, y_max = box
+    glyph = struct.pack(">hhhhh", 1, x_min, y_min, x_max, y_max)
+    # One contour of four on-curve points, with no instructions.
+    glyph += struct.pack(">HH", 3, 0) + bytes([0x01] * 4)
+    glyph += struct.pack(">4h", x_min, 0, x_max - x_min, 0)
+    glyph += struct.pack(">4h", y_min, y_max - y_min, 0, y_min - y_max)
+    return glyph + b"\0" * (len(glyph) % 2)
+
+
+def glyf_table(boxes):
+    # `.notdef` is empty.
+    offsets = [0, 0]
+    glyf = b""
+    for box in boxes:
+        glyf += square(box)
+        offsets.append(len(glyf))
+
+    loca = struct.pack(">%dH" % len(offsets), *(offset // 2 for offset in offsets))
+    return glyf, loca
+
+
+def cmap_table(chars):
+    # Format 4, one segment per character and the final 0xFFFF segment.
+    codes = sorted(ord(chr) for chr in chars)
+    ends = codes + [0xFFFF]
+    starts = codes + [0xFFFF]
+    deltas = [(gid + 1 - code) & 0xFFFF for gid, code in enumerate(codes)] + [1]
+
+    seg_count = len(ends)
+    entry_selector = seg_count.bit_length() - 1
+    search_range = 2 * (1 << entry_selector)
+    body = struct.pack(
+        ">HHHH", seg_count * 2, search_range, entry_selector, 2 * seg_count - search_range
+    )
+    body += struct.pack(">%dH" % seg_count, *ends) + b"\0\0"
+    body += struct.pack(">%dH" % seg_count, *starts)
+    body += struct.pack(">%dH" % seg_count, *deltas)
+    body += struct.pack(">%dH" % seg_count, *([0] * seg_count))
+
+    subtable = struct.pack(">HHH", 4, 6 + len(body), 0) + body
+    return struct.pack(">HHHHI", 0, 1, 3, 1, 12) + subtable
+
+
+def name_table(family):
+    names = [(1, family), (2, "Regular"), (4, family), (6, family.replace(" ", ""))]
+    records = b""
+    strings = b""
+    for name_id, value in names:
+        encoded = value.encode("utf-16-be")
+        records += struct.pack(">6H", 3, 1, 0x409, name_id, len(encoded), len(strings))
+        strings += encoded
+    header = struct.pack(">HHH", 0, len(names), 6 + len(records))
+    return header + records + strings
+
+
+def os2_table(chars):
+    codes = [ord(chr) for chr in chars]
+    table = struct.pack(">HhHHH", 4, ADVANCE, 400, 5, 0)
+    # Subscript, superscript and strikeout metrics.
+    table += struct.pack(">10h", 650, 600, 0, 75, 650, 600, 0, 350, 50, 250)
+    table += struct.pack(">h", 0) + bytes(10)
+    table += struct.pack(">4I", 0, 0, 0, 0) + b"WEOV"
+    # Regular.
+    table += struct.pack(">HHH", 0x40, min(codes), min(max(codes), 0xFFFF))
+    table += struct.pack(">hhhHH", ASCENDER, DESCENDER, 0, ASCENDER, -DESCENDER)
+    table += struct.pack(">II", 1, 0)
+    table += struct.pack(">hhHHH", 500, 700, 0, 32, 0)
+    return table
+
+
+def png(rows):
+    def chunk(kind, data):
+        return (
+            struct.pack(">I", len(data)) + kind + data
+            + struct.pack(">I", zlib.crc32(kind + data) & 0xFFFFFFFF)
+        )
+
+    # 8-bit RGBA, each row without a filter.
+    header = struct.pack(">IIBBBBB", len(rows[0]), len(rows), 8, 6, 0, 0, 0)
+    data = b"".join(b"\0" + bytes(channel for pixel in row for channel in pixel) for row in rows)
+    return (
+        b"\x89PNG\r\n\x1a\n" + chunk(b"IHDR", header) + chunk(b"IDAT", zlib.compress(data))
+        + chunk(b"IEND", b"")
+    )
+
+
+def sbix_table(num_glyphs, images):
+    # One strike, with the images of the glyph ids in `images` drawn at the origin.
+    strike_header = 4 + 4 * (num_glyphs + 1)
+    offsets = [strike_header]
+    data = b""
+    for glyph in range(num_glyphs):
+        if glyph in images:
+            data += struct.pack(">hh4s", 0, 0, b"png ") + images[glyph]
+        offsets.append(strike_header + len(data))
+
+    strike = struct.pack(">HH", EMOJI_PPEM, 72) + struct.pack(">%dI" % len(offsets), *offsets)
+    return struct.pack(">HHII", 1, 1, 1, 12) + strike + data
+
+
+def colr_table(glyph, layers):
+    # One base glyph, with its layers as glyph ids and palette indices.
+    table = struct.pack(">HHIIH", 0, 1, 14, 20, len(layers))
+    table += struct.pack(">HHH", glyph, 0, len(layers))
+    for layer in layers:
+        table += struct.pack(">HH", *layer)
+    return table
+
+
+def cpal_table(colors):
+    # One palette of RGBA colors, stored as BGRA.
+    table = struct.pack(">HHHHIH", 0, len(colors), 1, len(colors), 14, 0)
+    for red, green, blue, alpha in colors:
+        table += struct.pack(">4B", blue, green, red, alpha)
+    return table
+
+
+def bitmap_emoji_font():
+    tables = {"sbix": sbix_table(2, {1: png(EMOJI_PIXELS)})}
+    return font("Weovim Test Bitmap Emoji", "☺", extra_tables=tables)
+
+
+def layered_emoji_font():
+    # `★`, then its layers.
+    tables = {
+        "COLR": colr_table(1, [(2, 0), (3, 0xFFFF)]),
+        "CPAL": cpal_table([(255, 0, 0, 255)]),
+    }
+    return font("Weovim Test Layered Emoji", "★", [BOX, BOX, INNER_BOX], tables)
+
+
+def font(family, chars, boxes=None, extra_tables=None):
+    boxes = boxes or [BOX] * len(chars)
+    num_glyphs = len(boxes) + 1
+    glyf, loca = glyf_table(boxes)
+    x_min, y_min, x_max, y_max = BOX
+
+    tables = {
+        "OS/2": os2_table(chars),
+        "cmap": cmap_table(chars),
+        "glyf": glyf,
+        "head": struct.pack(
+            ">IIIIHHqqhhhhHHhhh",
+            0x00010000, 0x00010000, 0, 0x5F0F3CF5, 0x000B, UNITS_PER_EM, 0, 0,
+            x_min, y_min, x_max, y_max, 0, 8, 2, 0, 0,
+        ),
+        "hhea": struct.pack(
+            ">IhhhHhhhhhh4hhH",
+            0x00010000, ASCENDER, DESCENDER, 0, ADVANCE, 0, 0, x_max, 1, 0, 0,
+            0, 0, 0, 0, 0, num_glyphs,
+        ),
+        "hmtx": struct.pack(">Hh", ADVANCE, 0)
+        + b"".join(struct.pack(">Hh", ADVANCE, box[0]) for box in boxes),
+        "loca": loca,
+        "maxp": struct.pack(">IH13H", 0x00010000, num_glyphs, 4, 1, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0),
+        "name": name_table(family),
+        "post": struct.pack(">IIhhIIIII", 0x00030000, 0, -100, 50, 1, 0, 0, 0, 0),
+        **(extra_tables or {}),
+    }
+    num_tables = len(tables)
+    entry_selector = num_tables.bit_length() - 1
+    search_range = 16 * (1 << entry_selector)
+    header = struct.pack(
+        ">IHHHH", 0x00010000, num_tables, search_range, entry_selector,
+        num_tables * 16 - search_range,
+    )
+
+    offset = len(header) + 16 * num_tables
+    directory = b""
+    data = b""
+    for tag in sorted(tables):
+        table = tables[tag]
+        if tag == "head":
+            head = offset + len(data)
+        directory += struct.pack(">4sIII", tag.encode(), checksum(table), offset + len(data), len(table))
+        data += table + b"\0" * (-len(table) % 4)
+
+    font = bytearray(header + directory + data)
+    adjustment = (0xB1B0AFBA - checksum(bytes(font))) & 0xFFFFFFFF
+    font[head + 8 : head + 12] = struct.pack(">I", adjustment)
+    return bytes(font)
+
+
+if __name__ == "__main__":
+    for path, (family, chars) in FONTS.items():
+        with open(path, "wb") as file:
+            file.write(font(family, chars))
+
+    with open("bitmap_emoji.ttf", "wb") as file:
+        file.write(bitmap_emoji_font())
+    with open("layered_emoji.ttf", "wb") as file:
+        file.write(layered_emoji_font())
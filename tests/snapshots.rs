@@ -0,0 +1,84 @@
+//! Golden snapshots of the screen after each flush of a redraw recording.
+//!
+//! The recordings in `tests/fixtures` are in the format written with
+//! `WEOVIM_RECORD_REDRAW`. Set `WEOVIM_UPDATE_SNAPSHOTS=1` to rewrite the golden
+//! files in `tests/snapshots` after an intended change.
+use std::fs;
+use std::path::{Path, PathBuf};
+use weovim::editor::{Editor, UiStateFromEditor};
+use weovim::neovim::{EventListener, Recording, RedrawEvent};
+use weovim::snapshot;
+
+/// Takes the snapshots of every published frame.
+struct SnapshotEditor {
+    editor: Editor,
+    ui_state: UiStateFromEditor,
+    plain_text: String,
+    ansi: String,
+}
+
+impl EventListener for SnapshotEditor {
+    fn on_redraw_event(&mut self, event: RedrawEvent<'_>) {
+        let is_flush = matches!(event, RedrawEvent::Flush);
+
+        self.editor.on_redraw_event(event);
+
+        if is_flush {
+            let frame = self.ui_state.reader.buffer();
+            let n = self.plain_text.matches("--- flush").count() + 1;
+
+            self.plain_text += &format!("--- flush {}\n", n);
+            self.plain_text += &snapshot::plain_text(frame);
+            self.ansi += &format!("--- flush {}\n", n);
+            self.ansi += &snapshot::ansi(frame);
+
+            while self.ui_state.recv.try_recv().is_ok() {}
+        }
+    }
+}
+
+fn path(dir: &str, file: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(dir)
+        .join(file)
+}
+
+fn assert_snapshot(name: &str, actual: &str) {
+    let golden = path("snapshots", name);
+
+    if std::env::var_os("WEOVIM_UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&golden, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&golden)
+        .unwrap_or_else(|error| panic!("failed to read {}: {}", golden.display(), error));
+    assert!(
+        expected == actual,
+        "{} doesn't match:\n{}",
+        golden.display(),
+        actual
+    );
+}
+
+fn check_recording(name: &str) {
+    let recording = Recording::load(path("fixtures", &format!("{}.redraw", name))).unwrap();
+    let (editor, ui_state) = Editor::new();
+    let mut listener = SnapshotEditor {
+        editor,
+        ui_state,
+        plain_text: String::new(),
+        ansi: String::new(),
+    };
+
+    recording.replay(&mut listener).unwrap();
+
+    assert_snapshot(&format!("{}.txt", name), &listener.plain_text);
+    assert_snapshot(&format!("{}.ansi", name), &listener.ansi);
+}
+
+#[test]
+fn windows_floats_and_scrolling() {
+    check_recording("windows");
+}